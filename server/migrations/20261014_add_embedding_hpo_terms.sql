@@ -0,0 +1,3 @@
+-- Structured HPO associations for Orphanet documents, used to build
+-- symptom-focused candidate snippets for the selection pass
+ALTER TABLE embeddings ADD COLUMN IF NOT EXISTS hpo_terms JSONB;
@@ -0,0 +1,4 @@
+-- SHA-256 of the uploaded bytes, used to detect re-uploads of the same file
+ALTER TABLE uploaded_files ADD COLUMN IF NOT EXISTS content_hash VARCHAR(64);
+
+CREATE INDEX IF NOT EXISTS idx_uploaded_files_user_hash ON uploaded_files(user_id, content_hash);
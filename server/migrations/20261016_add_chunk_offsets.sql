@@ -0,0 +1,14 @@
+-- Character offsets of each chunk within the extracted document text, so the
+-- UI can highlight the exact source passage. Offsets are end-exclusive.
+--
+-- Backfill: rows created before this migration keep NULL offsets (the
+-- extracted text is not stored). Uploading the file again only populates them
+-- with UPLOAD_DEDUP=false; with dedup on, identical bytes return the existing
+-- file without reprocessing it.
+--
+-- A custom VECTOR_TABLE gets these columns at startup (upgrade_embeddings_table).
+ALTER TABLE embeddings_metadata ADD COLUMN IF NOT EXISTS start_offset INTEGER;
+ALTER TABLE embeddings_metadata ADD COLUMN IF NOT EXISTS end_offset INTEGER;
+
+ALTER TABLE embeddings ADD COLUMN IF NOT EXISTS start_offset INTEGER;
+ALTER TABLE embeddings ADD COLUMN IF NOT EXISTS end_offset INTEGER;
@@ -0,0 +1,4 @@
+-- Start of the extracted text (PDFs) or the image description, written as soon
+-- as extraction finishes so users can check it before processing completes.
+-- Existing rows keep NULL until the file is uploaded again.
+ALTER TABLE uploaded_files ADD COLUMN IF NOT EXISTS text_preview TEXT;
@@ -0,0 +1,4 @@
+-- Files extracted from one zip upload share a document set id; NULL for
+-- files uploaded on their own.
+ALTER TABLE uploaded_files ADD COLUMN IF NOT EXISTS document_set_id UUID;
+CREATE INDEX IF NOT EXISTS idx_uploaded_files_document_set ON uploaded_files(document_set_id);
@@ -0,0 +1,3 @@
+-- Which PDF_EXTRACTORS entry produced a PDF's text (lopdf, pdf-extract), so
+-- poor extractions can be traced to an extractor. NULL for images and older rows.
+ALTER TABLE uploaded_files ADD COLUMN IF NOT EXISTS text_extractor TEXT;
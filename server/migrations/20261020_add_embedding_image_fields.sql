@@ -0,0 +1,13 @@
+-- Structured fields of a Vision image analysis, so image-derived documents can be
+-- filtered by modality and body region like Orphanet rows are by orpha_code.
+--
+-- Backfill: existing image rows keep NULL (their prose description is not
+-- re-parsed). Uploading the image again only populates them with
+-- UPLOAD_DEDUP=false; with dedup on, identical bytes return the existing file.
+--
+-- A custom VECTOR_TABLE gets these columns and the index at startup
+-- (upgrade_embeddings_table).
+ALTER TABLE embeddings ADD COLUMN IF NOT EXISTS modality TEXT;
+ALTER TABLE embeddings ADD COLUMN IF NOT EXISTS region TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_embeddings_modality ON embeddings (modality) WHERE modality IS NOT NULL;
@@ -0,0 +1,3 @@
+-- FILE_STORAGE backend holding the original bytes (local, appwrite), so they can be
+-- downloaded or reprocessed. NULL when the bytes were not kept (and for older rows).
+ALTER TABLE uploaded_files ADD COLUMN IF NOT EXISTS storage_backend TEXT;
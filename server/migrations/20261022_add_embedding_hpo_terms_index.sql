@@ -0,0 +1,5 @@
+-- Exact lookups of HPO IDs pasted into a query (`HPO_ID_SEARCH`): containment
+-- on hpo_terms (e.g. hpo_terms @> '[{"hpo_id": "HP:0001249"}]') uses this index.
+-- Only Orphanet rows carry hpo_terms; uploaded-file chunks are never keyword hits.
+-- A custom VECTOR_TABLE gets its own copy at startup (upgrade_embeddings_table).
+CREATE INDEX IF NOT EXISTS idx_embeddings_hpo_terms ON embeddings USING GIN (hpo_terms jsonb_path_ops);
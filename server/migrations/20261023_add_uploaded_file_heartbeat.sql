@@ -0,0 +1,5 @@
+-- Last time the instance holding a pending/processing file confirmed it is still
+-- working on it. The startup sweep (STALE_PROCESSING_SECS) fails only files whose
+-- heartbeat stopped, so a long upload on another live instance is left alone.
+-- NULL for older rows and when the sweep is disabled.
+ALTER TABLE uploaded_files ADD COLUMN IF NOT EXISTS heartbeat_at TIMESTAMPTZ;
@@ -0,0 +1,153 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::answer_parser::StructuredAnswer;
+
+/// What the answer pass produced, replayed on a cache hit
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedAnswer {
+    /// Answer text before the disclaimer is appended
+    pub content: String,
+    pub structured: Option<StructuredAnswer>,
+    pub ungrounded: bool,
+}
+
+struct Entry {
+    answer: CachedAnswer,
+    stored_at: Instant,
+}
+
+/// Recent answers keyed by what determines them (`ANSWER_CACHE`), so a repeated
+/// question skips the answer pass. Keys mix in a corpus version that
+/// `invalidate` bumps whenever the knowledge base is reloaded, so stale answers
+/// are never served after a reload.
+#[derive(Clone)]
+pub struct AnswerCache {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    corpus_version: Arc<AtomicU64>,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl AnswerCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            corpus_version: Arc::new(AtomicU64::new(0)),
+            ttl,
+            max_entries,
+        }
+    }
+
+    /// Hash of `parts` under the current corpus version
+    pub fn key(&self, parts: &[&str]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.corpus_version.load(Ordering::SeqCst).to_le_bytes());
+        for part in parts {
+            // Separator keeps ("ab", "c") and ("a", "bc") apart
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub fn get(&self, key: &str) -> Option<CachedAnswer> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.stored_at.elapsed() < self.ttl => Some(entry.answer.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Store an answer, dropping expired entries and then the oldest ones to stay
+    /// within `max_entries`
+    pub fn insert(&self, key: String, answer: CachedAnswer) {
+        if self.max_entries == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.stored_at.elapsed() < self.ttl);
+        while entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let Some(oldest) = entries.iter().min_by_key(|(_, e)| e.stored_at).map(|(k, _)| k.clone()) else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.insert(key, Entry { answer, stored_at: Instant::now() });
+    }
+
+    /// Forget every answer, e.g. after the Orphanet corpus reloads. Keys built
+    /// before the call no longer match anything stored after it.
+    pub fn invalidate(&self) {
+        self.corpus_version.fetch_add(1, Ordering::SeqCst);
+        let cleared = {
+            let mut entries = self.entries.lock().unwrap();
+            let cleared = entries.len();
+            entries.clear();
+            cleared
+        };
+        if cleared > 0 {
+            tracing::info!("Answer cache invalidated ({} entries dropped)", cleared);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(content: &str) -> CachedAnswer {
+        CachedAnswer { content: content.to_string(), structured: None, ungrounded: false }
+    }
+
+    #[test]
+    fn test_hit_until_invalidated() {
+        let cache = AnswerCache::new(Duration::from_secs(60), 10);
+        let key = cache.key(&["muscle weakness", "ORPHA:98896", "patient"]);
+        assert_ne!(key, cache.key(&["muscle weaknessORPHA:98896", "", "patient"]));
+
+        cache.insert(key.clone(), answer("Duchenne"));
+        assert_eq!(cache.get(&key), Some(answer("Duchenne")));
+
+        cache.invalidate();
+        assert!(cache.get(&key).is_none());
+        // Same inputs hash differently once the corpus version moves on
+        assert_ne!(key, cache.key(&["muscle weakness", "ORPHA:98896", "patient"]));
+    }
+
+    #[test]
+    fn test_expired_and_oldest_entries_are_dropped() {
+        let expiring = AnswerCache::new(Duration::ZERO, 10);
+        expiring.insert("a".to_string(), answer("a"));
+        assert!(expiring.get("a").is_none());
+        assert!(expiring.is_empty());
+
+        let cache = AnswerCache::new(Duration::from_secs(60), 2);
+        cache.insert("a".to_string(), answer("a"));
+        cache.insert("b".to_string(), answer("b"));
+        cache.insert("c".to_string(), answer("c"));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("c").is_some());
+
+        let disabled = AnswerCache::new(Duration::from_secs(60), 0);
+        disabled.insert("a".to_string(), answer("a"));
+        assert!(disabled.is_empty());
+    }
+}
@@ -0,0 +1,357 @@
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::processing::orphanet::HPOAssociation;
+
+/// Machine-readable form of the answer pass output.
+///
+/// The answer prompt asks Gemini for fixed headers ("Most likely condition:",
+/// "Reasons:", "Next steps:"); this is what we can recover from them. A trailing
+/// "Disclaimer:" line (appended server-side) ends the current section.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StructuredAnswer {
+    pub most_likely_condition: String,
+    pub orpha_code: Option<String>,
+    /// Reason text with any HPO citations removed
+    pub reasons: Vec<String>,
+    pub next_steps: Vec<String>,
+    /// `reasons` tied to the HPO terms backing them; empty when none could be
+    /// linked (see `link_reasons`), leaving the plain reasons
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub grounded_reasons: Vec<GroundedReason>,
+}
+
+/// A reason with the condition's HPO terms it rests on
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GroundedReason {
+    pub text: String,
+    pub hpo_ids: Vec<String>,
+}
+
+impl StructuredAnswer {
+    /// Keep only cited HPO IDs that belong to the named condition, link uncited
+    /// reasons by the HPO terms they mention, and drop the grounding entirely when
+    /// no reason links to anything (e.g. the condition came without HPO data)
+    pub fn link_reasons(&mut self, associations: &[HPOAssociation]) {
+        for reason in &mut self.grounded_reasons {
+            reason.hpo_ids.retain(|id| associations.iter().any(|a| a.hpo_id == *id));
+            if reason.hpo_ids.is_empty() {
+                let text = reason.text.to_lowercase();
+                reason.hpo_ids = associations
+                    .iter()
+                    .filter(|a| !a.hpo_term.is_empty() && text.contains(&a.hpo_term.to_lowercase()))
+                    .map(|a| a.hpo_id.clone())
+                    .collect();
+            }
+            // Repeats needn't be adjacent; keep each ID where it was first cited
+            let mut seen = HashSet::new();
+            reason.hpo_ids.retain(|id| seen.insert(id.clone()));
+        }
+
+        if self.grounded_reasons.iter().all(|r| r.hpo_ids.is_empty()) {
+            self.grounded_reasons.clear();
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Section {
+    None,
+    Reasons,
+    NextSteps,
+}
+
+/// Parse the formatted answer. Returns `None` when no condition line is present,
+/// in which case callers should fall back to the raw text.
+pub fn parse_structured_answer(text: &str) -> Option<StructuredAnswer> {
+    let mut condition_line: Option<String> = None;
+    let mut reasons = Vec::new();
+    let mut next_steps = Vec::new();
+    let mut section = Section::None;
+
+    for raw in text.lines() {
+        let raw = raw.trim();
+        if raw.is_empty() {
+            continue;
+        }
+
+        if let Some(item) = strip_bullet(raw) {
+            let item = item.replace('*', "").trim().to_string();
+            match section {
+                Section::Reasons if !item.is_empty() => reasons.push(split_hpo_citations(&item)),
+                Section::NextSteps if !item.is_empty() => next_steps.push(item),
+                _ => {}
+            }
+            continue;
+        }
+
+        // Headers may come wrapped in markdown (e.g. "**Reasons:**" or "## Next steps:")
+        let cleaned = raw.trim_start_matches('#').replace('*', "");
+        let cleaned = cleaned.trim();
+
+        if let Some(rest) = header_value(cleaned, "most likely condition:") {
+            condition_line = Some(rest.to_string());
+            section = Section::None;
+        } else if let Some(rest) = header_value(cleaned, "reasons:") {
+            section = Section::Reasons;
+            if !rest.is_empty() {
+                reasons.push(split_hpo_citations(rest));
+            }
+        } else if let Some(rest) = header_value(cleaned, "next steps:") {
+            section = Section::NextSteps;
+            if !rest.is_empty() {
+                next_steps.push(rest.to_string());
+            }
+        } else if header_value(cleaned, "disclaimer:").is_some() {
+            section = Section::None;
+        } else {
+            // Un-bulleted continuation line inside a list section
+            match section {
+                Section::Reasons => reasons.push(split_hpo_citations(cleaned)),
+                Section::NextSteps => next_steps.push(cleaned.to_string()),
+                Section::None => {}
+            }
+        }
+    }
+
+    let (most_likely_condition, orpha_code) = split_orpha_code(&condition_line?);
+    if most_likely_condition.is_empty() {
+        return None;
+    }
+
+    Some(StructuredAnswer {
+        most_likely_condition,
+        orpha_code,
+        reasons: reasons.iter().map(|r| r.text.clone()).collect(),
+        next_steps,
+        grounded_reasons: reasons,
+    })
+}
+
+/// Split "Seizures [HP:0001250, HP:0002069]" into the text and the cited IDs.
+/// Only bracketed or parenthesised groups made up entirely of HPO IDs count as
+/// citations; other parentheses stay in the text.
+fn split_hpo_citations(item: &str) -> GroundedReason {
+    let mut text = String::with_capacity(item.len());
+    let mut hpo_ids = Vec::new();
+    let mut rest = item;
+
+    while let Some(open) = rest.find(['[', '(']) {
+        let close = if rest[open..].starts_with('[') { ']' } else { ')' };
+        let Some(len) = rest[open + 1..].find(close) else {
+            break;
+        };
+        let inner = &rest[open + 1..open + 1 + len];
+        let ids: Vec<&str> = inner
+            .split([',', ';', ' '])
+            .filter(|part| !part.is_empty())
+            .collect();
+
+        let end = open + 1 + len + 1;
+        if !ids.is_empty() && ids.iter().all(|id| is_hpo_id(id)) {
+            text.push_str(&rest[..open]);
+            hpo_ids.extend(ids.iter().map(|id| id.to_ascii_uppercase()));
+        } else {
+            text.push_str(&rest[..end]);
+        }
+        rest = &rest[end..];
+    }
+    text.push_str(rest);
+
+    GroundedReason {
+        text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+        hpo_ids,
+    }
+}
+
+fn is_hpo_id(token: &str) -> bool {
+    token
+        .get(..3)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("hp:"))
+        && token.len() > 3
+        && token[3..].chars().all(|c| c.is_ascii_digit())
+}
+
+/// What follows `header` (ASCII, lowercase) when `cleaned` starts with it in any
+/// case. Compared in place rather than on a lowercased copy, whose byte offsets
+/// can differ (e.g. U+212A KELVIN SIGN lowercases to a one-byte "k").
+fn header_value<'a>(cleaned: &'a str, header: &str) -> Option<&'a str> {
+    cleaned
+        .get(..header.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(header))
+        .map(|_| cleaned[header.len()..].trim())
+}
+
+fn strip_bullet(line: &str) -> Option<&str> {
+    for marker in ["- ", "* ", "• "] {
+        if let Some(rest) = line.strip_prefix(marker) {
+            return Some(rest);
+        }
+    }
+
+    // Numbered list ("1. item" / "2) item")
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 {
+        let rest = &line[digits..];
+        if let Some(item) = rest.strip_prefix(". ").or_else(|| rest.strip_prefix(") ")) {
+            return Some(item);
+        }
+    }
+
+    None
+}
+
+/// Split "Name (Orpha: 123)" into the name and code. Only the last parenthesised
+/// group mentioning Orpha is treated as the code, so names containing parentheses survive.
+fn split_orpha_code(line: &str) -> (String, Option<String>) {
+    let line = line.trim();
+    if let Some(open) = line.rfind('(') {
+        let inner = &line[open + 1..];
+        let inner = inner.split(')').next().unwrap_or(inner);
+        if inner.to_lowercase().contains("orpha") {
+            let code: String = inner.chars().filter(|c| c.is_ascii_digit()).collect();
+            let name = line[..open].trim().to_string();
+            return (name, (!code.is_empty()).then_some(code));
+        }
+    }
+
+    (line.to_string(), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_standard_answer() {
+        let text = "Most likely condition: Alexander disease (Orpha: 58)\n\
+                    Reasons:\n\
+                    - Macrocephaly in infancy\n\
+                    - Seizures\n\
+                    Next steps:\n\
+                    - See a neurologist\n\
+                    - MRI of the brain\n\
+                    Disclaimer: This is not a medical diagnosis. Please consult a qualified physician.";
+
+        let parsed = parse_structured_answer(text).unwrap();
+        assert_eq!(parsed.most_likely_condition, "Alexander disease");
+        assert_eq!(parsed.orpha_code.as_deref(), Some("58"));
+        assert_eq!(parsed.reasons, vec!["Macrocephaly in infancy", "Seizures"]);
+        assert_eq!(parsed.next_steps, vec!["See a neurologist", "MRI of the brain"]);
+    }
+
+    #[test]
+    fn test_parse_markdown_answer() {
+        let text = "**Most likely condition:** Fabry disease (ORPHA:324)\n\n\
+                    **Reasons:**\n\
+                    * Burning pain in hands\n\
+                    1. Angiokeratomas\n\n\
+                    ## Next steps:\n\
+                    - Enzyme assay";
+
+        let parsed = parse_structured_answer(text).unwrap();
+        assert_eq!(parsed.most_likely_condition, "Fabry disease");
+        assert_eq!(parsed.orpha_code.as_deref(), Some("324"));
+        assert_eq!(parsed.reasons, vec!["Burning pain in hands", "Angiokeratomas"]);
+        assert_eq!(parsed.next_steps, vec!["Enzyme assay"]);
+    }
+
+    #[test]
+    fn test_header_is_matched_in_place() {
+        // U+212A lowercases to a one-byte "k", so a lowercased copy's offsets
+        // don't line up with the original and would cut it mid-header
+        assert!(parse_structured_answer("Most li\u{212A}ely condition: Fabry disease").is_none());
+        assert_eq!(header_value("REASONS: Seizures", "reasons:"), Some("Seizures"));
+        assert_eq!(header_value("Reasonsé", "reasons:"), None);
+    }
+
+    #[test]
+    fn test_parse_name_with_parentheses_and_no_code() {
+        let parsed = parse_structured_answer(
+            "Most likely condition: Marfan syndrome (type 1) (Orpha: 558)",
+        )
+        .unwrap();
+        assert_eq!(parsed.most_likely_condition, "Marfan syndrome (type 1)");
+        assert_eq!(parsed.orpha_code.as_deref(), Some("558"));
+
+        let parsed = parse_structured_answer("Most likely condition: Unknown rare myopathy").unwrap();
+        assert_eq!(parsed.most_likely_condition, "Unknown rare myopathy");
+        assert_eq!(parsed.orpha_code, None);
+    }
+
+    fn assoc(hpo_id: &str, hpo_term: &str) -> HPOAssociation {
+        HPOAssociation {
+            hpo_id: hpo_id.to_string(),
+            hpo_term: hpo_term.to_string(),
+            frequency: "Very frequent (99-80%)".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_reasons_link_to_cited_hpo_terms() {
+        let text = "Most likely condition: Alexander disease (Orpha: 58)\n\
+                    Reasons:\n\
+                    - Enlarged head since infancy [HP:0000256]\n\
+                    - Seizures (HP:0001250, HP:9999999)\n\
+                    - Developmental delay (global) fits the picture\n\
+                    Next steps:\n\
+                    - MRI of the brain";
+
+        let mut parsed = parse_structured_answer(text).unwrap();
+        assert_eq!(
+            parsed.reasons,
+            vec!["Enlarged head since infancy", "Seizures", "Developmental delay (global) fits the picture"]
+        );
+
+        parsed.link_reasons(&[
+            assoc("HP:0000256", "Macrocephaly"),
+            assoc("HP:0001250", "Seizure"),
+            assoc("HP:0001263", "Developmental delay"),
+        ]);
+        let linked: Vec<(&str, Vec<&str>)> = parsed
+            .grounded_reasons
+            .iter()
+            .map(|r| (r.text.as_str(), r.hpo_ids.iter().map(String::as_str).collect()))
+            .collect();
+        assert_eq!(
+            linked,
+            vec![
+                ("Enlarged head since infancy", vec!["HP:0000256"]),
+                // IDs the condition doesn't have are dropped
+                ("Seizures", vec!["HP:0001250"]),
+                // Uncited reasons link by the term they mention
+                ("Developmental delay (global) fits the picture", vec!["HP:0001263"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_link_reasons_drops_non_adjacent_repeats() {
+        let mut parsed =
+            parse_structured_answer("Most likely condition: Alexander disease
+Reasons:
+- Signs [HP:0001250, HP:0000256, HP:0001250]")
+                .unwrap();
+        parsed.link_reasons(&[assoc("HP:0000256", "Macrocephaly"), assoc("HP:0001250", "Seizure")]);
+        assert_eq!(parsed.grounded_reasons[0].hpo_ids, vec!["HP:0001250", "HP:0000256"]);
+    }
+
+    #[test]
+    fn test_unlinked_reasons_fall_back_to_plain() {
+        let text = "Most likely condition: Fabry disease (Orpha: 324)\nReasons:\n- Burning pain [HP:0000001]\n- Fatigue";
+
+        let mut parsed = parse_structured_answer(text).unwrap();
+        parsed.link_reasons(&[assoc("HP:0001014", "Angiokeratoma")]);
+        assert!(parsed.grounded_reasons.is_empty());
+        assert_eq!(parsed.reasons, vec!["Burning pain", "Fatigue"]);
+
+        let json = serde_json::to_value(&parsed).unwrap();
+        assert!(json.get("grounded_reasons").is_none());
+    }
+
+    #[test]
+    fn test_parse_unstructured_answer_returns_none() {
+        assert!(parse_structured_answer("I couldn't generate a response right now.").is_none());
+        assert!(parse_structured_answer("Most likely condition:   \nReasons:\n- x").is_none());
+    }
+}
@@ -1,6 +1,6 @@
 use axum::{
-    extract::FromRequestParts,
-    http::{request::Parts, StatusCode},
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     Json, RequestPartsExt,
 };
@@ -11,6 +11,40 @@ use axum_extra::{
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::fmt::Display;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use crate::config::{Config, Lookup, flag, parse_or};
+
+/// Appwrite and admin credentials, loaded once into `Config`
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    pub appwrite_endpoint: String,
+    /// Required to validate user JWTs; user requests fail with 503 without it
+    pub appwrite_project_id: Option<String>,
+    /// `APPWRITE_SERVICE_KEYS`: comma-separated `name=key` pairs
+    pub service_keys: String,
+    /// `ADMIN_TOKEN`; empty disables admin endpoints
+    pub admin_token: String,
+    /// Require a user or service identity for `/api/vector/inspect` (`PROTECT_INSPECT`);
+    /// turn off only for local development
+    pub protect_inspect: bool,
+    pub retry: AppwriteRetryPolicy,
+}
+
+impl AuthConfig {
+    pub fn from_lookup(lookup: Lookup<'_>) -> Self {
+        Self {
+            appwrite_endpoint: lookup("APPWRITE_ENDPOINT")
+                .unwrap_or_else(|| "https://cloud.appwrite.io/v1".to_string()),
+            appwrite_project_id: lookup("APPWRITE_PROJECT_ID"),
+            service_keys: lookup("APPWRITE_SERVICE_KEYS").unwrap_or_default(),
+            admin_token: lookup("ADMIN_TOKEN").unwrap_or_default(),
+            protect_inspect: flag(lookup, "PROTECT_INSPECT", true),
+            retry: AppwriteRetryPolicy::from_lookup(lookup),
+        }
+    }
+}
 
 // Appwrite JWT claims structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +53,12 @@ pub struct AppwriteClaims {
     pub user_id: String,
     pub email: Option<String>,
     pub name: Option<String>,
+    /// Trusted backend caller authenticated with `X-Appwrite-Key`, not an end user
+    #[serde(default)]
+    pub is_service: bool,
+    /// Appwrite account labels (set server-side only), e.g. a plan for upload quotas
+    #[serde(default)]
+    pub labels: Vec<String>,
 }
 
 impl Display for AppwriteClaims {
@@ -30,11 +70,23 @@ impl Display for AppwriteClaims {
 // Extractor for Appwrite JWT from request
 impl<S> FromRequestParts<S> for AppwriteClaims
 where
+    Arc<Config>: FromRef<S>,
     S: Send + Sync,
 {
     type Rejection = AuthError;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+
+        // Backend-to-backend calls carry a server key instead of a user JWT
+        if let Some(key) = parts.headers.get("X-Appwrite-Key") {
+            let key = key.to_str().map_err(|_| AuthError::InvalidToken)?;
+            return service_claims(key, &config.auth.service_keys).ok_or_else(|| {
+                tracing::warn!("Rejected request with unknown X-Appwrite-Key");
+                AuthError::InvalidToken
+            });
+        }
+
         // Extract the token from the authorization header
         let TypedHeader(Authorization(bearer)) = parts
             .extract::<TypedHeader<Authorization<Bearer>>>()
@@ -42,57 +94,218 @@ where
             .map_err(|_| AuthError::MissingToken)?;
 
         // Validate JWT with Appwrite API
-        let claims = validate_with_appwrite_api(bearer.token())
+        let claims = validate_with_appwrite_api(bearer.token(), &config.auth)
             .await
             .map_err(|e| {
                 tracing::error!("Appwrite API validation failed: {}", e);
-                AuthError::InvalidToken
+                match e {
+                    AppwriteError::Unavailable(_) => AuthError::ServiceUnavailable,
+                    AppwriteError::Rejected(_) | AppwriteError::InvalidResponse(_) => {
+                        AuthError::InvalidToken
+                    }
+                }
             })?;
 
         Ok(claims)
     }
 }
 
+/// Marker extractor for operator-only endpoints: requires `X-Admin-Token` to match
+/// `ADMIN_TOKEN`. Admin endpoints are disabled entirely when `ADMIN_TOKEN` is unset.
+pub struct AdminAccess;
+
+impl<S> FromRequestParts<S> for AdminAccess
+where
+    Arc<Config>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+        let provided = parts
+            .headers
+            .get("X-Admin-Token")
+            .ok_or(AuthError::MissingToken)?
+            .to_str()
+            .map_err(|_| AuthError::InvalidToken)?;
+
+        if admin_token_matches(provided, &config.auth.admin_token) {
+            Ok(AdminAccess)
+        } else {
+            tracing::warn!("Rejected admin request with invalid X-Admin-Token");
+            Err(AuthError::InvalidToken)
+        }
+    }
+}
+
+/// Gate for the vector inspect endpoints: the caller must authenticate like any
+/// user request unless `PROTECT_INSPECT=false` opens them for development
+pub struct InspectAccess;
+
+impl<S> FromRequestParts<S> for InspectAccess
+where
+    Arc<Config>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+        if config.auth.protect_inspect {
+            AppwriteClaims::from_request_parts(parts, state).await?;
+        }
+        Ok(InspectAccess)
+    }
+}
+
+/// Whether the request carries a valid `X-Admin-Token`, for endpoints open to
+/// everyone that unlock extra options for operators
+pub fn has_admin_token(headers: &HeaderMap, config: &AuthConfig) -> bool {
+    headers
+        .get("X-Admin-Token")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|provided| admin_token_matches(provided, &config.admin_token))
+}
+
+/// An empty configured token never matches, so a missing `ADMIN_TOKEN` locks admin routes
+fn admin_token_matches(provided: &str, configured: &str) -> bool {
+    !configured.is_empty() && constant_time_eq(provided.as_bytes(), configured.as_bytes())
+}
+
+/// Match a server key against `APPWRITE_SERVICE_KEYS` (comma-separated `name=key` pairs)
+/// and build a synthetic service identity. Service auth is disabled when none are configured.
+fn service_claims(key: &str, configured: &str) -> Option<AppwriteClaims> {
+    if key.is_empty() {
+        return None;
+    }
+
+    configured
+        .split(',')
+        .filter_map(|entry| entry.trim().split_once('='))
+        .find(|(_, expected)| constant_time_eq(expected.trim().as_bytes(), key.as_bytes()))
+        .map(|(name, _)| AppwriteClaims {
+            user_id: format!("service:{}", name.trim()),
+            email: None,
+            name: Some(name.trim().to_string()),
+            is_service: true,
+            labels: vec![],
+        })
+}
+
+/// Compare without short-circuiting so key checks don't leak timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Failure modes when validating a token against Appwrite
+#[derive(Debug, thiserror::Error)]
+pub enum AppwriteError {
+    /// Appwrite answered and refused the token (401/403 and other 4xx)
+    #[error("Appwrite rejected the token: {0}")]
+    Rejected(String),
+    /// Appwrite could not be reached or kept failing with 5xx/429 after retries
+    #[error("Appwrite unavailable: {0}")]
+    Unavailable(String),
+    /// Appwrite answered 2xx but the body was not a usable account
+    #[error("Invalid Appwrite response: {0}")]
+    InvalidResponse(String),
+}
+
+/// Timeout and retry settings for the Appwrite account lookup
+#[derive(Debug, Clone)]
+pub struct AppwriteRetryPolicy {
+    pub timeout: Duration,
+    pub max_retries: u32,
+    pub base_delay: Duration,
+}
+
+impl AppwriteRetryPolicy {
+    pub fn from_lookup(lookup: Lookup<'_>) -> Self {
+        Self {
+            timeout: Duration::from_millis(parse_or(lookup, "APPWRITE_TIMEOUT_MS", 5000)),
+            max_retries: parse_or(lookup, "APPWRITE_MAX_RETRIES", 2),
+            base_delay: Duration::from_millis(200),
+        }
+    }
+}
+
 // Validate JWT by calling Appwrite API
-async fn validate_with_appwrite_api(token: &str) -> anyhow::Result<AppwriteClaims> {
-    let appwrite_endpoint = std::env::var("APPWRITE_ENDPOINT")
-        .unwrap_or_else(|_| "https://cloud.appwrite.io/v1".to_string());
-    let project_id = std::env::var("APPWRITE_PROJECT_ID")
-        .map_err(|_| anyhow::anyhow!("APPWRITE_PROJECT_ID not set"))?;
-    
-    // Call Appwrite API to validate the JWT
-    let client = reqwest::Client::new();
-    let response = client
-        .get(format!("{}/account", appwrite_endpoint))
-        .header("X-Appwrite-Project", &project_id)
-        .header("X-Appwrite-JWT", token)
-        .send()
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to call Appwrite API: {}", e))?;
-    
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response.text().await.unwrap_or_default();
-        return Err(anyhow::anyhow!(
-            "Appwrite API returned error {}: {}",
-            status,
-            error_text
-        ));
-    }
-    
-    // Parse user info from response
-    let user: serde_json::Value = response
-        .json()
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to parse Appwrite response: {}", e))?;
-    
+async fn validate_with_appwrite_api(token: &str, config: &AuthConfig) -> Result<AppwriteClaims, AppwriteError> {
+    let project_id = config.appwrite_project_id.as_deref()
+        .ok_or_else(|| AppwriteError::Unavailable("APPWRITE_PROJECT_ID not set".to_string()))?;
+
+    fetch_appwrite_account(&config.appwrite_endpoint, project_id, token, &config.retry).await
+}
+
+/// Call `GET /account` with the user's JWT, retrying transient failures with
+/// exponential backoff. 4xx responses are legitimate rejections and are never retried.
+async fn fetch_appwrite_account(
+    appwrite_endpoint: &str,
+    project_id: &str,
+    token: &str,
+    policy: &AppwriteRetryPolicy,
+) -> Result<AppwriteClaims, AppwriteError> {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    let client = CLIENT.get_or_init(reqwest::Client::new);
+
+    let mut attempt = 0;
+    let user: serde_json::Value = loop {
+        let result = client
+            .get(format!("{}/account", appwrite_endpoint))
+            .header("X-Appwrite-Project", project_id)
+            .header("X-Appwrite-JWT", token)
+            .timeout(policy.timeout)
+            .send()
+            .await;
+
+        let failure = match result {
+            Ok(response) if response.status().is_success() => {
+                break response
+                    .json()
+                    .await
+                    .map_err(|e| AppwriteError::InvalidResponse(e.to_string()))?;
+            }
+            Ok(response) => {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+                let message = format!("Appwrite API returned error {}: {}", status, error_text);
+
+                if !(status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS) {
+                    return Err(AppwriteError::Rejected(message));
+                }
+                message
+            }
+            Err(e) => format!("Failed to call Appwrite API: {}", e),
+        };
+
+        if attempt >= policy.max_retries {
+            return Err(AppwriteError::Unavailable(failure));
+        }
+
+        let delay = policy.base_delay * 2_u32.pow(attempt);
+        tracing::warn!(
+            "Appwrite validation attempt {} failed ({}), retrying in {}ms",
+            attempt + 1,
+            failure,
+            delay.as_millis()
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    };
+
     Ok(AppwriteClaims {
         user_id: user["$id"]
             .as_str()
-            .ok_or_else(|| anyhow::anyhow!("Missing user ID in Appwrite response"))?
+            .ok_or_else(|| AppwriteError::InvalidResponse("Missing user ID in Appwrite response".to_string()))?
             .to_string(),
         email: user["email"].as_str().map(|s| s.to_string()),
         name: user["name"].as_str().map(|s| s.to_string()),
+        is_service: false,
+        labels: user["labels"]
+            .as_array()
+            .map(|labels| labels.iter().filter_map(|l| l.as_str()).map(str::to_string).collect())
+            .unwrap_or_default(),
     })
 }
 
@@ -102,19 +315,43 @@ pub enum AuthError {
     MissingToken,
     InvalidToken,
     ExpiredToken,
+    ServiceUnavailable,
+}
+
+impl AuthError {
+    /// Stable code for clients that can't see the HTTP status (SSE `error` events)
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthError::MissingToken => "missing_token",
+            AuthError::InvalidToken => "invalid_token",
+            AuthError::ExpiredToken => "expired_token",
+            AuthError::ServiceUnavailable => "auth_unavailable",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            AuthError::MissingToken => "Missing authorization token",
+            AuthError::InvalidToken => "Invalid authorization token",
+            AuthError::ExpiredToken => "Token has expired",
+            AuthError::ServiceUnavailable => "Authentication service unavailable, please retry",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            AuthError::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
 }
 
 impl IntoResponse for AuthError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AuthError::MissingToken => (StatusCode::UNAUTHORIZED, "Missing authorization token"),
-            AuthError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid authorization token"),
-            AuthError::ExpiredToken => (StatusCode::UNAUTHORIZED, "Token has expired"),
-        };
         let body = Json(json!({
-            "error": error_message,
+            "error": self.message(),
         }));
-        (status, body).into_response()
+        (self.status(), body).into_response()
     }
 }
 
@@ -126,4 +363,145 @@ pub async fn protected(claims: AppwriteClaims) -> Result<String, AuthError> {
         claims.email,
         claims.name
     ))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, routing::get};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Spawn a stub Appwrite that answers `/account` with the scripted statuses in order
+    /// (the last one repeats). Returns the endpoint and a hit counter.
+    async fn stub_appwrite(statuses: Vec<u16>) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = Router::new().route(
+            "/account",
+            get(move || {
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                let status = statuses[n.min(statuses.len() - 1)];
+                async move {
+                    let status = StatusCode::from_u16(status).unwrap();
+                    let body = Json(json!({"$id": "user-1", "email": "a@b.c", "name": "A"}));
+                    (status, body)
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}", addr), hits)
+    }
+
+    fn fast_policy() -> AppwriteRetryPolicy {
+        AppwriteRetryPolicy {
+            timeout: Duration::from_millis(500),
+            max_retries: 2,
+            base_delay: Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_valid_token_returns_claims() {
+        let (endpoint, hits) = stub_appwrite(vec![200]).await;
+        let claims = fetch_appwrite_account(&endpoint, "p", "t", &fast_policy()).await.unwrap();
+        assert_eq!(claims.user_id, "user-1");
+        assert_eq!(claims.email.as_deref(), Some("a@b.c"));
+        assert!(!claims.is_service);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_service_key_yields_service_claims() {
+        let configured = "ingest=key-one, eval-runner=key-two";
+
+        let claims = service_claims("key-two", configured).unwrap();
+        assert_eq!(claims.user_id, "service:eval-runner");
+        assert!(claims.is_service);
+
+        assert!(service_claims("key-three", configured).is_none());
+        assert!(service_claims("", "broken=").is_none());
+        assert!(service_claims("key-one", "").is_none());
+    }
+
+    #[test]
+    fn test_admin_token_requires_configured_match() {
+        assert!(admin_token_matches("s3cret", "s3cret"));
+        assert!(!admin_token_matches("s3cre", "s3cret"));
+        assert!(!admin_token_matches("", ""));
+    }
+
+    #[tokio::test]
+    async fn test_inspect_access_follows_protect_inspect() {
+        let config = |protect: &'static str| {
+            Arc::new(Config::from_lookup(&move |name| (name == "PROTECT_INSPECT").then(|| protect.to_string())).unwrap())
+        };
+        let parts = || axum::http::Request::new(()).into_parts().0;
+
+        let protected = config("true");
+        let err = InspectAccess::from_request_parts(&mut parts(), &protected).await.err().unwrap();
+        assert!(matches!(err, AuthError::MissingToken));
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+
+        let open = config("false");
+        assert!(InspectAccess::from_request_parts(&mut parts(), &open).await.is_ok());
+    }
+
+    #[test]
+    fn test_has_admin_token() {
+        let config = AuthConfig::from_lookup(&|name| (name == "ADMIN_TOKEN").then(|| "s3cret".to_string()));
+        let mut headers = HeaderMap::new();
+        assert!(!has_admin_token(&headers, &config));
+
+        headers.insert("X-Admin-Token", "wrong".parse().unwrap());
+        assert!(!has_admin_token(&headers, &config));
+        headers.insert("X-Admin-Token", "s3cret".parse().unwrap());
+        assert!(has_admin_token(&headers, &config));
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_is_not_retried() {
+        let (endpoint, hits) = stub_appwrite(vec![401]).await;
+        let err = fetch_appwrite_account(&endpoint, "p", "t", &fast_policy()).await.unwrap_err();
+        assert!(matches!(err, AppwriteError::Rejected(_)));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_transient_server_error_is_retried() {
+        let (endpoint, hits) = stub_appwrite(vec![503, 500, 200]).await;
+        let claims = fetch_appwrite_account(&endpoint, "p", "t", &fast_policy()).await.unwrap();
+        assert_eq!(claims.user_id, "user-1");
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_persistent_server_error_is_unavailable() {
+        let (endpoint, hits) = stub_appwrite(vec![502]).await;
+        let err = fetch_appwrite_account(&endpoint, "p", "t", &fast_policy()).await.unwrap_err();
+        assert!(matches!(err, AppwriteError::Unavailable(_)));
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_connection_failure_is_unavailable() {
+        // Bind then drop to get a port nobody is listening on
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let endpoint = format!("http://{}", addr);
+        let err = fetch_appwrite_account(&endpoint, "p", "t", &fast_policy()).await.unwrap_err();
+        assert!(matches!(err, AppwriteError::Unavailable(_)));
+    }
+
+    #[test]
+    fn test_service_unavailable_maps_to_503() {
+        let response = AuthError::ServiceUnavailable.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(AuthError::InvalidToken.into_response().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(AuthError::ServiceUnavailable.code(), "auth_unavailable");
+    }
+}
@@ -0,0 +1,101 @@
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Seconds clients are told to wait (`Retry-After`) when every stream slot is taken
+pub const RETRY_AFTER_SECS: u64 = 5;
+
+/// Caps concurrent chats (`MAX_CONCURRENT_CHATS`, 0 = unlimited): SSE streams and
+/// `/chat/complete` requests alike. Each holds an embedding permit and Gemini
+/// connections for its whole length, so past the cap new chats are refused rather
+/// than queued.
+#[derive(Clone)]
+pub struct ChatStreams {
+    permits: Option<Arc<Semaphore>>,
+    max: usize,
+    active: Arc<AtomicUsize>,
+}
+
+/// Open streams, reported by the readiness probe
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ChatStreamStats {
+    pub active: usize,
+    /// 0 = unlimited
+    pub max: usize,
+}
+
+/// Every stream slot is taken
+#[derive(Debug, thiserror::Error)]
+#[error("Too many concurrent chats ({max}), retry later")]
+pub struct ChatStreamsFull {
+    pub max: usize,
+}
+
+impl ChatStreams {
+    pub fn new(max: usize) -> Self {
+        Self {
+            permits: (max > 0).then(|| Arc::new(Semaphore::new(max))),
+            max,
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Claim a slot for one stream; it is released when the permit drops, i.e.
+    /// when the stream finishes or the client disconnects
+    pub fn try_acquire(&self) -> Result<ChatStreamPermit, ChatStreamsFull> {
+        let permit = match &self.permits {
+            Some(permits) => Some(permits.clone().try_acquire_owned().map_err(|_| ChatStreamsFull { max: self.max })?),
+            None => None,
+        };
+
+        self.active.fetch_add(1, Ordering::SeqCst);
+        Ok(ChatStreamPermit { _permit: permit, active: self.active.clone() })
+    }
+
+    pub fn stats(&self) -> ChatStreamStats {
+        ChatStreamStats { active: self.active.load(Ordering::SeqCst), max: self.max }
+    }
+}
+
+/// A claimed stream slot (see `ChatStreams::try_acquire`)
+pub struct ChatStreamPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for ChatStreamPermit {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permits_are_released_on_drop() {
+        let streams = ChatStreams::new(2);
+        let first = streams.try_acquire().unwrap();
+        let _second = streams.try_acquire().unwrap();
+
+        let error = streams.try_acquire().err().unwrap();
+        assert_eq!(error.max, 2);
+        assert_eq!(streams.stats(), ChatStreamStats { active: 2, max: 2 });
+
+        drop(first);
+        assert_eq!(streams.stats().active, 1);
+        assert!(streams.try_acquire().is_ok());
+    }
+
+    #[test]
+    fn test_zero_is_unlimited_but_still_counted() {
+        let streams = ChatStreams::new(0);
+        let permits: Vec<_> = (0..100).map(|_| streams.try_acquire().unwrap()).collect();
+        assert_eq!(streams.stats(), ChatStreamStats { active: 100, max: 0 });
+
+        drop(permits);
+        assert_eq!(streams.stats().active, 0);
+    }
+}
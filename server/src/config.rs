@@ -0,0 +1,288 @@
+use anyhow::Result;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::auth::AuthConfig;
+use crate::chat::ChatConfig;
+use crate::media_ingestion::archive::ArchiveMemberPolicy;
+use crate::media_ingestion::quota::QuotaPolicy;
+use crate::media_ingestion::validation::FileTypePolicy;
+use crate::orphanet_loader::OrphanetConfig;
+
+/// Resolves a setting by name; `std::env::var` in production, a map in tests
+pub type Lookup<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+/// Runtime settings read once at startup and shared through `AppState`, so request
+/// handling never touches the environment. Components built once at startup
+/// (Gemini client, vector store, embedding provider, upload sessions) still read
+/// their own settings in their `from_env` constructors.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub chat: ChatConfig,
+    pub auth: AuthConfig,
+    pub orphanet: OrphanetConfig,
+    /// Skip re-processing uploads whose bytes match an existing file (`UPLOAD_DEDUP`)
+    pub upload_dedup: bool,
+    /// Reject a zip upload over one bad member, or skip past it (`ZIP_INVALID_MEMBERS`)
+    pub archive_member_policy: ArchiveMemberPolicy,
+    /// Accepted upload formats and their internal types (`FILE_TYPE_POLICY_PATH`)
+    pub file_types: FileTypePolicy,
+    /// Per-user file count and size limits (`UPLOAD_QUOTA_*`)
+    pub upload_quota: QuotaPolicy,
+    pub upload_body_limit: usize,
+    pub chat_body_limit: usize,
+    /// Concurrent SSE chat streams before new ones get 503 (`MAX_CONCURRENT_CHATS`, 0 = unlimited)
+    pub max_concurrent_chats: usize,
+    /// Decimals kept in scores shown by chat and inspect responses (`SCORE_DECIMALS`,
+    /// unset = full precision)
+    pub score_decimals: Option<u32>,
+    pub skip_warmup: bool,
+    /// How long shutdown waits for in-flight file processing before interrupting
+    /// it for the next boot to resume (`SHUTDOWN_GRACE_SECS`)
+    pub shutdown_grace: Duration,
+    /// Files `pending`/`processing` with no heartbeat this long at startup are failed
+    /// (`STALE_PROCESSING_SECS`, 0 = never)
+    pub stale_processing_after: Option<Duration>,
+    /// Start without retrieval instead of exiting when the embedding model can't load
+    /// (`EMBEDDINGS_OPTIONAL`)
+    pub embeddings_optional: bool,
+    /// Log lengths and hashes in place of patient text and file names (`LOG_REDACT_PII`)
+    pub log_redact_pii: bool,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self> {
+        Self::from_lookup(&|name| std::env::var(name).ok())
+    }
+
+    pub fn from_lookup(lookup: Lookup<'_>) -> Result<Self> {
+        Ok(Self {
+            chat: ChatConfig::from_lookup(lookup),
+            auth: AuthConfig::from_lookup(lookup),
+            orphanet: OrphanetConfig::from_lookup(lookup)?,
+            upload_dedup: flag(lookup, "UPLOAD_DEDUP", true),
+            archive_member_policy: ArchiveMemberPolicy::parse(lookup("ZIP_INVALID_MEMBERS")),
+            file_types: FileTypePolicy::from_lookup(lookup)?,
+            upload_quota: QuotaPolicy::from_lookup(lookup)?,
+            // 50MB file cap plus multipart overhead
+            upload_body_limit: parse_or(lookup, "UPLOAD_BODY_LIMIT_BYTES", 55 * 1024 * 1024),
+            chat_body_limit: parse_or(lookup, "CHAT_BODY_LIMIT_BYTES", 64 * 1024),
+            max_concurrent_chats: parse_or(lookup, "MAX_CONCURRENT_CHATS", 64),
+            score_decimals: lookup("SCORE_DECIMALS").and_then(|v| v.parse().ok()),
+            skip_warmup: flag(lookup, "SKIP_WARMUP", false),
+            shutdown_grace: Duration::from_secs(parse_or(lookup, "SHUTDOWN_GRACE_SECS", 30)),
+            stale_processing_after: Some(parse_or(lookup, "STALE_PROCESSING_SECS", 3600))
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            embeddings_optional: flag(lookup, "EMBEDDINGS_OPTIONAL", false),
+            log_redact_pii: flag(lookup, "LOG_REDACT_PII", true),
+        })
+    }
+}
+
+impl axum::extract::FromRef<crate::AppState> for Arc<Config> {
+    fn from_ref(state: &crate::AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+/// `name` parsed as `T`, or `default` when unset or unparsable
+pub fn parse_or<T: FromStr>(lookup: Lookup<'_>, name: &str, default: T) -> T {
+    lookup(name).and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// `true` only for a case-insensitive "true"; `default` when unset
+pub fn flag(lookup: Lookup<'_>, name: &str, default: bool) -> bool {
+    lookup(name).map_or(default, |v| v.to_lowercase() == "true")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{GroundingMode, KbEmptyMode, NoMatchMode};
+    use crate::processing::HpoWeighting;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    fn load(vars: &[(&str, &str)]) -> Result<Config> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Config::from_lookup(&|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_from_lookup_reads_every_setting() {
+        let config = load(&[
+            ("ENABLE_EMBEDDINGS", "false"),
+            ("GROUNDING_CHECK", "retry"),
+            ("NO_MATCH_MODE", "canned"),
+            ("NO_MATCH_MIN_SIMILARITY", "0.4"),
+            ("KB_EMPTY_MODE", "stop"),
+            ("NORMALIZE_FALLBACK", "FALSE"),
+            ("RAW_QUERY_WEIGHT", "1.5"),
+            ("DISCLAIMER_TEXT", "Not advice."),
+            ("ALLOW_OMIT_DISCLAIMER", "true"),
+            ("THINKING_STEPS", "2"),
+            ("THINKING_STEP_MAX_CHARS", "60"),
+            ("MULTI_QUERY", "true"),
+            ("MULTI_QUERY_MAX", "3"),
+            ("HPO_ID_SEARCH", "true"),
+            ("HPO_ID_SEARCH_MAX", "2"),
+            ("SELECT_SNIPPET_CHARS", "120"),
+            ("CANDIDATES_MIN_ORPHANET", "3"),
+            ("CANDIDATES_MIN_USER_FILES", "2"),
+            ("SELECT_SKIP_MARGIN", "0.3"),
+            ("HEURISTIC_FALLBACK", "true"),
+            ("ANSWER_CACHE", "true"),
+            ("ANSWER_CACHE_TTL_SECS", "60"),
+            ("ANSWER_CACHE_MAX_ENTRIES", "10"),
+            ("CONTEXT_MAX_CHARS", "6000"),
+            ("CONTEXT_MAX_RESULT_CHARS", "250"),
+            ("DEFAULT_LANGUAGE", " FR "),
+            ("APPWRITE_ENDPOINT", "http://appwrite.local/v1"),
+            ("APPWRITE_PROJECT_ID", "quwa"),
+            ("APPWRITE_SERVICE_KEYS", "ingest=k1"),
+            ("APPWRITE_TIMEOUT_MS", "750"),
+            ("APPWRITE_MAX_RETRIES", "4"),
+            ("ADMIN_TOKEN", "s3cret"),
+            ("PROTECT_INSPECT", "false"),
+            ("LOAD_ORPHANET", "true"),
+            ("ORPHANET_DATASET_PATH", "/data/product4.xml, /data/extra.xml"),
+            ("ORPHANET_LIMIT", "100"),
+            ("ORPHANET_BATCH_SIZE", "25"),
+            ("ORPHANET_MIN_HPO", "3"),
+            ("HPO_WEIGHTING", "summary"),
+            ("ORPHANET_DRY_RUN", "true"),
+            ("READY_REQUIRES_KNOWLEDGE_BASE", "true"),
+            ("UPLOAD_DEDUP", "false"),
+            ("ZIP_INVALID_MEMBERS", "skip"),
+            ("UPLOAD_QUOTA_FILES", "200"),
+            ("UPLOAD_BODY_LIMIT_BYTES", "1024"),
+            ("CHAT_BODY_LIMIT_BYTES", "512"),
+            ("MAX_CONCURRENT_CHATS", "8"),
+            ("SCORE_DECIMALS", "3"),
+            ("SKIP_WARMUP", "true"),
+            ("SHUTDOWN_GRACE_SECS", "5"),
+            ("STALE_PROCESSING_SECS", "600"),
+            ("EMBEDDINGS_OPTIONAL", "true"),
+            ("LOG_REDACT_PII", "false"),
+        ])
+        .unwrap();
+
+        let chat = &config.chat;
+        assert!(!chat.enable_embeddings);
+        assert_eq!(chat.grounding_mode, GroundingMode::Retry);
+        assert_eq!(chat.no_match_mode, NoMatchMode::Canned);
+        assert_eq!(chat.no_match_min_similarity, 0.4);
+        assert_eq!(chat.kb_empty_mode, KbEmptyMode::Stop);
+        assert!(!chat.normalize_fallback);
+        assert_eq!(chat.raw_query_weight, 1.0);
+        assert_eq!(chat.disclaimer, "Not advice.");
+        assert!(chat.allow_omit_disclaimer);
+        assert_eq!(chat.thinking_steps, 2);
+        assert_eq!(chat.thinking_step_max_chars, 60);
+        assert!(chat.multi_query);
+        assert_eq!(chat.max_sub_queries, 3);
+        assert!(chat.hpo_id_search);
+        assert_eq!(chat.hpo_id_max_hits, 2);
+        assert_eq!(chat.snippet_chars, 120);
+        assert_eq!(chat.min_orphanet_candidates, 3);
+        assert_eq!(chat.min_user_file_candidates, 2);
+        assert_eq!(chat.select_skip_margin, Some(0.3));
+        assert!(chat.heuristic_fallback);
+        assert!(chat.answer_cache);
+        assert_eq!(chat.answer_cache_ttl, Duration::from_secs(60));
+        assert_eq!(chat.answer_cache_max_entries, 10);
+        assert_eq!(chat.context_budget.max_total_chars, Some(6000));
+        assert_eq!(chat.context_budget.max_result_chars, 250);
+        assert_eq!(chat.default_language, "fr");
+
+        let auth = &config.auth;
+        assert_eq!(auth.appwrite_endpoint, "http://appwrite.local/v1");
+        assert_eq!(auth.appwrite_project_id.as_deref(), Some("quwa"));
+        assert_eq!(auth.service_keys, "ingest=k1");
+        assert_eq!(auth.admin_token, "s3cret");
+        assert!(!auth.protect_inspect);
+        assert_eq!(auth.retry.timeout, Duration::from_millis(750));
+        assert_eq!(auth.retry.max_retries, 4);
+
+        let orphanet = &config.orphanet;
+        assert!(orphanet.load);
+        assert_eq!(orphanet.dataset_paths, vec![Path::new("/data/product4.xml"), Path::new("/data/extra.xml")]);
+        assert_eq!(orphanet.limit, Some(100));
+        assert_eq!(orphanet.batch_size, 25);
+        assert_eq!(orphanet.min_hpo_associations, 3);
+        assert_eq!(orphanet.weighting, HpoWeighting::Summary);
+        assert!(orphanet.gate_readiness);
+        assert!(orphanet.dry_run);
+        assert!(!orphanet.skip);
+
+        assert!(!config.upload_dedup);
+        assert_eq!(config.archive_member_policy, ArchiveMemberPolicy::SkipBad);
+        assert_eq!(config.upload_quota.default.max_files, 200);
+        assert_eq!(config.upload_body_limit, 1024);
+        assert_eq!(config.chat_body_limit, 512);
+        assert_eq!(config.max_concurrent_chats, 8);
+        assert_eq!(config.score_decimals, Some(3));
+        assert!(config.skip_warmup);
+        assert_eq!(config.shutdown_grace, Duration::from_secs(5));
+        assert_eq!(config.stale_processing_after, Some(Duration::from_secs(600)));
+        assert!(config.embeddings_optional);
+        assert!(!config.log_redact_pii);
+    }
+
+    #[test]
+    fn test_defaults_when_unset() {
+        let config = load(&[]).unwrap();
+
+        assert!(config.chat.enable_embeddings);
+        assert_eq!(config.chat.grounding_mode, GroundingMode::Annotate);
+        assert_eq!(config.chat.no_match_mode, NoMatchMode::Off);
+        assert_eq!(config.chat.kb_empty_mode, KbEmptyMode::Continue);
+        assert_eq!(config.chat.thinking_steps, 6);
+        assert!(config.chat.normalize_fallback);
+        assert_eq!(config.chat.raw_query_weight, 0.0);
+        assert!(!config.chat.allow_omit_disclaimer);
+        assert_eq!(config.chat.select_skip_margin, None);
+        assert!(!config.chat.heuristic_fallback);
+        assert!(!config.chat.answer_cache);
+        assert_eq!(config.chat.default_language, "en");
+        assert_eq!(config.chat.answer_cache_ttl, Duration::from_secs(3600));
+        assert_eq!(config.auth.appwrite_endpoint, "https://cloud.appwrite.io/v1");
+        assert!(config.auth.appwrite_project_id.is_none());
+        assert!(config.auth.protect_inspect);
+        assert!(!config.orphanet.load);
+        assert_eq!(config.orphanet.dataset_paths, vec![Path::new("dataset/en_product4.xml")]);
+        assert_eq!(config.orphanet.batch_size, 50);
+        assert_eq!(config.orphanet.min_hpo_associations, 1);
+        assert_eq!(config.orphanet.weighting, HpoWeighting::Off);
+        assert!(!config.orphanet.gate_readiness);
+        assert!(!config.orphanet.dry_run);
+        assert!(config.upload_dedup);
+        assert_eq!(config.archive_member_policy, ArchiveMemberPolicy::FailAll);
+        assert_eq!(config.file_types, FileTypePolicy::default());
+        assert_eq!(config.upload_body_limit, 55 * 1024 * 1024);
+        assert_eq!(config.max_concurrent_chats, 64);
+        assert_eq!(config.score_decimals, None);
+        assert!(!config.skip_warmup);
+        assert_eq!(config.shutdown_grace, Duration::from_secs(30));
+        assert_eq!(config.stale_processing_after, Some(Duration::from_secs(3600)));
+        assert_eq!(load(&[("STALE_PROCESSING_SECS", "0")]).unwrap().stale_processing_after, None);
+        assert!(!config.embeddings_optional);
+        assert!(config.log_redact_pii);
+    }
+
+    #[test]
+    fn test_missing_file_type_policy_is_rejected() {
+        assert!(load(&[("FILE_TYPE_POLICY_PATH", "/nonexistent/file-types.json")]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_batch_size_is_rejected() {
+        assert!(load(&[("ORPHANET_BATCH_SIZE", "0")]).is_err());
+        assert!(load(&[("ORPHANET_BATCH_SIZE", "many")]).is_err());
+    }
+}
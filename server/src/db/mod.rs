@@ -5,7 +5,7 @@ use std::time::Duration;
 pub mod models;
 pub mod queries;
 
-pub async fn create_pool(database_url: &str) -> Result<PgPool> {
+fn pool_options() -> PgPoolOptions {
     let max_connections = std::env::var("DB_MAX_CONNECTIONS")
         .ok()
         .and_then(|v| v.parse::<u32>().ok())
@@ -16,12 +16,14 @@ pub async fn create_pool(database_url: &str) -> Result<PgPool> {
         .and_then(|v| v.parse::<u64>().ok())
         .unwrap_or(10);
 
-    let pool = PgPoolOptions::new()
+    PgPoolOptions::new()
         .max_connections(max_connections)
         .acquire_timeout(Duration::from_secs(acquire_timeout_secs))
         .acquire_slow_threshold(Duration::from_secs(2))
-        .connect(database_url)
-        .await?;
+}
+
+pub async fn create_pool(database_url: &str) -> Result<PgPool> {
+    let pool = pool_options().connect(database_url).await?;
     
     // Run migrations
     sqlx::migrate!("./migrations")
@@ -30,3 +32,8 @@ pub async fn create_pool(database_url: &str) -> Result<PgPool> {
     
     Ok(pool)
 }
+
+/// Pool for a read replica: same settings, but no migrations (replicas are read-only)
+pub async fn create_read_pool(database_url: &str) -> Result<PgPool> {
+    Ok(pool_options().connect(database_url).await?)
+}
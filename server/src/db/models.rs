@@ -1,8 +1,11 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use sqlx::types::Json;
 use uuid::Uuid;
 
+use crate::processing::orphanet::HPOAssociation;
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct User {
     pub id: i32,
@@ -27,6 +30,16 @@ pub struct UploadedFile {
     pub upload_date: DateTime<Utc>,
     pub processed_at: Option<DateTime<Utc>>,
     pub error_message: Option<String>,
+    /// Hex SHA-256 of the file bytes
+    pub content_hash: Option<String>,
+    /// Start of the extracted text (PDFs) or the image description, set during processing
+    pub text_preview: Option<String>,
+    /// Shared by the files extracted from one zip upload
+    pub document_set_id: Option<Uuid>,
+    /// `PDF_EXTRACTORS` entry that produced the PDF text (NULL for images)
+    pub text_extractor: Option<String>,
+    /// `FILE_STORAGE` backend holding the original bytes; NULL when they weren't kept
+    pub storage_backend: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -37,6 +50,9 @@ pub struct EmbeddingMetadata {
     pub chunk_text: String,
     pub embedding_id: String,
     pub created_at: DateTime<Utc>,
+    /// Char range of the chunk in the extracted document text (NULL for older rows)
+    pub start_offset: Option<i32>,
+    pub end_offset: Option<i32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -85,3 +101,58 @@ impl<'r> sqlx::FromRow<'r, sqlx::postgres::PgRow> for Embedding {
         })
     }
 }
+
+/// Row returned by a similarity search over `embeddings`
+#[derive(Debug, Clone, FromRow)]
+pub struct EmbeddingSearchRow {
+    pub text: String,
+    pub similarity: f64,
+    pub source_type: String,
+    pub source_id: String,
+    pub file_name: Option<String>,
+    pub orpha_code: Option<String>,
+    pub hpo_terms: Option<Json<Vec<HPOAssociation>>>,
+    pub start_offset: Option<i32>,
+    pub end_offset: Option<i32>,
+    pub modality: Option<String>,
+    pub region: Option<String>,
+}
+
+/// A single stored row looked up by id (admin document view)
+#[derive(Debug, Clone, FromRow)]
+pub struct EmbeddingDocumentRow {
+    pub id: Uuid,
+    pub text: String,
+    pub source_type: String,
+    pub source_id: String,
+    pub file_name: Option<String>,
+    pub orpha_code: Option<String>,
+    pub hpo_terms: Option<Json<Vec<HPOAssociation>>>,
+    pub start_offset: Option<i32>,
+    pub end_offset: Option<i32>,
+    pub modality: Option<String>,
+    pub region: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub dimension: Option<i32>,
+    /// Only selected when the caller asks for the raw vector
+    pub embedding: Option<Vec<f32>>,
+}
+
+/// Search row plus the raw terms behind the cosine score (inspect debug mode)
+#[derive(Debug, Clone, FromRow)]
+pub struct EmbeddingDebugRow {
+    #[sqlx(flatten)]
+    pub hit: EmbeddingSearchRow,
+    pub raw_dot: f64,
+    pub doc_magnitude: f64,
+}
+
+/// Aggregate of one sampled similarity scan; the aggregates are NULL for an empty sample
+#[derive(Debug, Clone, FromRow)]
+pub struct SimilarityStatsRow {
+    pub sampled: i64,
+    pub mean: Option<f64>,
+    pub stddev: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
@@ -1,7 +1,11 @@
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 use anyhow::Result;
 use uuid::Uuid;
+use sqlx::types::Json;
+use std::time::Duration;
 use super::models::*;
+use crate::media_ingestion::quota::{QuotaExceeded, StorageQuota, StorageUsage};
+use crate::rag::vector_store::{DocumentMetadata, ImportCounts, VectorDocument};
 
 pub async fn get_or_create_user(
     pool: &PgPool,
@@ -24,10 +28,48 @@ pub async fn get_or_create_user(
     Ok(user)
 }
 
-pub async fn create_uploaded_file(pool: &PgPool, file: &UploadedFile) -> Result<UploadedFile> {
+/// Outcome of `create_uploaded_file`
+#[derive(Debug)]
+pub enum RecordedUpload {
+    Created(UploadedFile),
+    /// With `dedup`, the owner's existing upload of the same bytes; nothing was inserted
+    Duplicate(UploadedFile),
+}
+
+/// Record an upload. With `dedup` (and a `content_hash`) an existing upload of
+/// the same bytes is returned instead; with a `quota`, the owner's usage is
+/// checked, a refusal being a `QuotaExceeded` error. Both checks run in the
+/// insert's transaction under a per-user advisory lock, so concurrent uploads
+/// can't both pass them.
+pub async fn create_uploaded_file(
+    pool: &PgPool,
+    file: &UploadedFile,
+    quota: Option<StorageQuota>,
+    dedup: bool,
+) -> Result<RecordedUpload> {
+    let mut tx = pool.begin().await?;
+    let dedup_hash = file.content_hash.as_deref().filter(|_| dedup);
+    if quota.is_some() || dedup_hash.is_some() {
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('uploaded_files'), $1)")
+            .bind(file.user_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    if let Some(hash) = dedup_hash
+        && let Some(existing) = find_file_by_hash(&mut *tx, file.user_id, hash).await?
+    {
+        return Ok(RecordedUpload::Duplicate(existing));
+    }
+    if let Some(quota) = quota {
+        let usage = get_user_storage_usage(&mut *tx, file.user_id).await?;
+        quota
+            .check(usage, 1, file.file_size_bytes.unwrap_or(0) as u64)
+            .map_err(QuotaExceeded)?;
+    }
+
     let file = sqlx::query_as::<_, UploadedFile>(
-        "INSERT INTO uploaded_files (id, user_id, file_name, file_type, mime_type, file_size_bytes, appwrite_file_id, appwrite_bucket_id)
-         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "INSERT INTO uploaded_files (id, user_id, file_name, file_type, mime_type, file_size_bytes, appwrite_file_id, appwrite_bucket_id, content_hash, document_set_id, storage_backend)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
          RETURNING *"
     )
     .bind(file.id)
@@ -38,10 +80,14 @@ pub async fn create_uploaded_file(pool: &PgPool, file: &UploadedFile) -> Result<
     .bind(file.file_size_bytes)
     .bind(&file.appwrite_file_id)
     .bind(&file.appwrite_bucket_id)
-    .fetch_one(pool)
+    .bind(&file.content_hash)
+    .bind(file.document_set_id)
+    .bind(&file.storage_backend)
+    .fetch_one(&mut *tx)
     .await?;
+    tx.commit().await?;
     
-    Ok(file)
+    Ok(RecordedUpload::Created(file))
 }
 
 pub async fn update_file_status(
@@ -62,17 +108,158 @@ pub async fn update_file_status(
     Ok(())
 }
 
-pub async fn get_user_files(pool: &PgPool, user_id: i32) -> Result<Vec<UploadedFile>> {
+/// Record what was extracted from the file, as soon as extraction finishes
+pub async fn set_file_text_preview(pool: &PgPool, file_id: Uuid, preview: &str) -> Result<()> {
+    sqlx::query("UPDATE uploaded_files SET text_preview = $1 WHERE id = $2")
+        .bind(preview)
+        .bind(file_id)
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+/// Record which PDF extractor produced the file's text
+pub async fn set_file_text_extractor(pool: &PgPool, file_id: Uuid, extractor: &str) -> Result<()> {
+    sqlx::query("UPDATE uploaded_files SET text_extractor = $1 WHERE id = $2")
+        .bind(extractor)
+        .bind(file_id)
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+/// Flag files whose processing was cut short by a shutdown, unless they finished
+/// in the meantime. Returns the ids that were marked.
+pub async fn mark_files_interrupted(pool: &PgPool, file_ids: &[Uuid]) -> Result<Vec<Uuid>> {
+    let marked = sqlx::query_scalar::<_, Uuid>(
+        "UPDATE uploaded_files
+         SET processing_status = 'interrupted', error_message = 'Processing was interrupted by a server shutdown'
+         WHERE id = ANY($1) AND processing_status IN ('pending', 'processing')
+         RETURNING id"
+    )
+    .bind(file_ids)
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(marked)
+}
+
+/// Fail files left `pending` or `processing` with no status change for longer
+/// than `older_than` (their worker is gone). Returns how many were failed.
+pub async fn fail_stale_files(pool: &PgPool, older_than: std::time::Duration, message: &str) -> Result<u64> {
+    let result = sqlx::query(
+        "UPDATE uploaded_files
+         SET processing_status = 'failed', error_message = $2, processed_at = NOW()
+         WHERE processing_status IN ('pending', 'processing')
+           AND GREATEST(heartbeat_at, processed_at, upload_date) < NOW() - make_interval(secs => $1)"
+    )
+    .bind(older_than.as_secs_f64())
+    .bind(message)
+    .execute(pool)
+    .await?;
+    
+    Ok(result.rows_affected())
+}
+
+/// Mark `ids` as still being worked on, for `fail_stale_files` on other instances
+pub async fn touch_file_heartbeats(pool: &PgPool, ids: &[Uuid]) -> Result<()> {
+    sqlx::query("UPDATE uploaded_files SET heartbeat_at = NOW() WHERE id = ANY($1)")
+        .bind(ids)
+        .execute(pool)
+        .await?;
+    
+    Ok(())
+}
+
+/// Every file in `status`, oldest first
+pub async fn get_files_by_status(pool: &PgPool, status: &str) -> Result<Vec<UploadedFile>> {
+    let files = sqlx::query_as::<_, UploadedFile>(
+        "SELECT * FROM uploaded_files WHERE processing_status = $1 ORDER BY upload_date"
+    )
+    .bind(status)
+    .fetch_all(pool)
+    .await?;
+    
+    Ok(files)
+}
+
+/// Drop a file's chunk metadata, e.g. before reprocessing it from scratch
+pub async fn delete_embedding_metadata(pool: &PgPool, file_id: Uuid) -> Result<u64> {
+    let result = sqlx::query("DELETE FROM embeddings_metadata WHERE file_id = $1")
+        .bind(file_id)
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected())
+}
+
+/// `file_id` if it was uploaded by the Appwrite user `appwrite_id`
+pub async fn get_file_for_user(pool: &PgPool, file_id: Uuid, appwrite_id: &str) -> Result<Option<UploadedFile>> {
+    let file = sqlx::query_as::<_, UploadedFile>(
+        "SELECT f.* FROM uploaded_files f
+         JOIN users u ON u.id = f.user_id
+         WHERE f.id = $1 AND u.appwrite_id = $2"
+    )
+    .bind(file_id)
+    .bind(appwrite_id)
+    .fetch_optional(pool)
+    .await?;
+    
+    Ok(file)
+}
+
+/// A user's files, newest first; with `since`, only those uploaded at or after it,
+/// and with `document_set_id`, only those extracted from that zip
+pub async fn get_user_files(
+    pool: &PgPool,
+    user_id: i32,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    document_set_id: Option<Uuid>,
+) -> Result<Vec<UploadedFile>> {
     let files = sqlx::query_as::<_, UploadedFile>(
-        "SELECT * FROM uploaded_files WHERE user_id = $1 ORDER BY upload_date DESC"
+        "SELECT * FROM uploaded_files
+         WHERE user_id = $1
+           AND ($2::timestamptz IS NULL OR upload_date >= $2)
+           AND ($3::uuid IS NULL OR document_set_id = $3)
+         ORDER BY upload_date DESC"
     )
     .bind(user_id)
+    .bind(since)
+    .bind(document_set_id)
     .fetch_all(pool)
     .await?;
     
     Ok(files)
 }
 
+/// Number and total size of `user_id`'s uploads, whatever their status
+pub async fn get_user_storage_usage<'e>(executor: impl PgExecutor<'e>, user_id: i32) -> Result<StorageUsage> {
+    let (files, bytes) = sqlx::query_as::<_, (i64, i64)>(
+        "SELECT COUNT(*), COALESCE(SUM(file_size_bytes), 0)::BIGINT
+         FROM uploaded_files WHERE user_id = $1"
+    )
+    .bind(user_id)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(StorageUsage { files: files as u64, bytes: bytes as u64 })
+}
+
+/// Which of `hashes` `user_id` has already uploaded
+pub async fn find_existing_hashes(pool: &PgPool, user_id: i32, hashes: &[String]) -> Result<Vec<String>> {
+    let existing = sqlx::query_scalar::<_, String>(
+        "SELECT DISTINCT content_hash FROM uploaded_files WHERE user_id = $1 AND content_hash = ANY($2)"
+    )
+    .bind(user_id)
+    .bind(hashes)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(existing)
+}
+
 pub async fn get_file_by_id(pool: &PgPool, file_id: Uuid) -> Result<Option<UploadedFile>> {
     let file = sqlx::query_as::<_, UploadedFile>(
         "SELECT * FROM uploaded_files WHERE id = $1"
@@ -84,22 +271,45 @@ pub async fn get_file_by_id(pool: &PgPool, file_id: Uuid) -> Result<Option<Uploa
     Ok(file)
 }
 
+/// Most recent non-failed upload by `user_id` with the same content hash
+pub async fn find_file_by_hash<'e>(
+    executor: impl PgExecutor<'e>,
+    user_id: i32,
+    content_hash: &str,
+) -> Result<Option<UploadedFile>> {
+    let file = sqlx::query_as::<_, UploadedFile>(
+        "SELECT * FROM uploaded_files
+         WHERE user_id = $1 AND content_hash = $2 AND processing_status <> 'failed'
+         ORDER BY upload_date DESC
+         LIMIT 1"
+    )
+    .bind(user_id)
+    .bind(content_hash)
+    .fetch_optional(executor)
+    .await?;
+    
+    Ok(file)
+}
+
 pub async fn create_embedding_metadata(
     pool: &PgPool,
     file_id: Uuid,
     chunk_index: i32,
     chunk_text: &str,
     embedding_id: &str,
+    offsets: Option<(i32, i32)>,
 ) -> Result<EmbeddingMetadata> {
     let metadata = sqlx::query_as::<_, EmbeddingMetadata>(
-        "INSERT INTO embeddings_metadata (file_id, chunk_index, chunk_text, embedding_id)
-         VALUES ($1, $2, $3, $4)
+        "INSERT INTO embeddings_metadata (file_id, chunk_index, chunk_text, embedding_id, start_offset, end_offset)
+         VALUES ($1, $2, $3, $4, $5, $6)
          RETURNING *"
     )
     .bind(file_id)
     .bind(chunk_index)
     .bind(chunk_text)
     .bind(embedding_id)
+    .bind(offsets.map(|(start, _)| start))
+    .bind(offsets.map(|(_, end)| end))
     .fetch_one(pool)
     .await?;
     
@@ -107,75 +317,554 @@ pub async fn create_embedding_metadata(
 }
 
 // Vector database operations
+//
+// The vector table name is configurable (VECTOR_TABLE), so it is interpolated
+// into the SQL. Callers must pass a name validated by `RagVectorStore`.
+
+/// Create `table` with the same columns, defaults and indexes as `embeddings`
+pub async fn create_embeddings_table_like(pool: &PgPool, table: &str) -> Result<()> {
+    let sql = format!("CREATE TABLE IF NOT EXISTS {} (LIKE embeddings INCLUDING ALL)", table);
+    sqlx::query(&sql).execute(pool).await?;
+
+    Ok(())
+}
+
+/// Columns later migrations add to `embeddings`. A table cloned from it earlier
+/// never sees those migrations, so `upgrade_embeddings_table` adds them.
+const EMBEDDINGS_ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("hpo_terms", "JSONB"),
+    ("start_offset", "INTEGER"),
+    ("end_offset", "INTEGER"),
+    ("modality", "TEXT"),
+    ("region", "TEXT"),
+];
+
+/// Indexes later migrations add to `embeddings`, as `(name suffix, definition)`;
+/// a clone's copy is named `idx_<table>_<suffix>`
+const EMBEDDINGS_ADDED_INDEXES: &[(&str, &str)] = &[
+    ("modality", "(modality) WHERE modality IS NOT NULL"),
+    ("hpo_terms", "USING GIN (hpo_terms jsonb_path_ops)"),
+];
+
+/// Bring a `VECTOR_TABLE` clone up to the current `embeddings` columns and indexes
+pub async fn upgrade_embeddings_table(pool: &PgPool, table: &str) -> Result<()> {
+    for (column, column_type) in EMBEDDINGS_ADDED_COLUMNS {
+        let sql = format!("ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {}", table, column, column_type);
+        sqlx::query(&sql).execute(pool).await?;
+    }
+    for (suffix, definition) in EMBEDDINGS_ADDED_INDEXES {
+        let sql = format!("CREATE INDEX IF NOT EXISTS idx_{}_{} ON {} {}", table, suffix, table, definition);
+        sqlx::query(&sql).execute(pool).await?;
+    }
+
+    Ok(())
+}
 
 pub async fn add_embedding(
     pool: &PgPool,
+    table: &str,
     text: String,
     embedding: Vec<f32>,
-    source_type: String,
-    source_id: String,
-    file_name: Option<String>,
-    orpha_code: Option<String>,
+    metadata: DocumentMetadata,
 ) -> Result<Embedding> {
-    let embedding = sqlx::query_as::<_, Embedding>(
-        "INSERT INTO embeddings (text, embedding, source_type, source_id, file_name, orpha_code)
-         VALUES ($1, $2, $3, $4, $5, $6)
-         RETURNING id, text, embedding, source_type, source_id, file_name, orpha_code, created_at"
-    )
-    .bind(&text)
-    .bind(&embedding)
-    .bind(&source_type)
-    .bind(&source_id)
-    .bind(&file_name)
-    .bind(&orpha_code)
-    .fetch_one(pool)
-    .await?;
+    let hpo_terms = (!metadata.hpo_associations.is_empty())
+        .then_some(Json(metadata.hpo_associations));
+
+    let sql = format!(
+        "INSERT INTO {} (text, embedding, source_type, source_id, file_name, orpha_code, hpo_terms, start_offset, end_offset, modality, region)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+         RETURNING id, text, embedding, source_type, source_id, file_name, orpha_code, created_at",
+        table
+    );
+    let embedding = sqlx::query_as::<_, Embedding>(&sql)
+        .bind(&text)
+        .bind(&embedding)
+        .bind(&metadata.source_type)
+        .bind(&metadata.source_id)
+        .bind(&metadata.file_name)
+        .bind(&metadata.orpha_code)
+        .bind(&hpo_terms)
+        .bind(metadata.start_offset)
+        .bind(metadata.end_offset)
+        .bind(&metadata.modality)
+        .bind(&metadata.region)
+        .fetch_one(pool)
+        .await?;
     
     Ok(embedding)
 }
 
+/// `SET LOCAL` so the timeout only applies to the surrounding search transaction
+fn statement_timeout_sql(timeout: Option<Duration>) -> Option<String> {
+    timeout.map(|t| format!("SET LOCAL statement_timeout = {}", t.as_millis().max(1)))
+}
+
+/// `WHERE` clause for a search restricted to `source_type` and/or to the uploaded
+/// files in `user_file_ids` (other sources pass), numbering parameters from `$3`
+fn search_filter_sql(source_type: bool, user_file_ids: bool) -> String {
+    let mut conditions = vec![];
+    if source_type {
+        conditions.push("source_type = $3".to_string());
+    }
+    if user_file_ids {
+        let param = 3 + source_type as usize;
+        conditions.push(format!("(source_type <> 'user_file' OR source_id = ANY(${}))", param));
+    }
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    }
+}
+
 pub async fn search_embeddings(
     pool: &PgPool,
+    table: &str,
     query_embedding: Vec<f32>,
     limit: i64,
-) -> Result<Vec<(String, f64, String, String, Option<String>, Option<String>)>> {
+    timeout: Option<Duration>,
+    source_type: Option<&str>,
+    user_file_ids: Option<&[String]>,
+) -> Result<Vec<EmbeddingSearchRow>> {
     // Use cosine distance operator (<=>)
     // Lower distance = higher similarity
     // We convert to similarity score (1 - distance) for consistency
     // IMPORTANT: Cast the parameter to vector type using ::vector
     // PostgreSQL returns FLOAT8 (f64) for distance calculations
-    let results = sqlx::query_as::<_, (String, f64, String, String, Option<String>, Option<String>)>(
+    let sql = format!(
         "SELECT text, 
                 1 - (embedding <=> $1::vector) as similarity,
                 source_type, 
                 source_id, 
                 file_name, 
-                orpha_code
-         FROM embeddings
+                orpha_code,
+                hpo_terms,
+                start_offset,
+                end_offset,
+                modality,
+                region
+         FROM {}
+         {}
          ORDER BY embedding <=> $1::vector
-         LIMIT $2"
-    )
-    .bind(&query_embedding)
-    .bind(limit)
-    .fetch_all(pool)
-    .await?;
+         LIMIT $2",
+        table,
+        // Only add the filter when asked so the unfiltered plan stays a pure index scan
+        search_filter_sql(source_type.is_some(), user_file_ids.is_some())
+    );
+    let mut tx = pool.begin().await?;
+    if let Some(timeout_sql) = statement_timeout_sql(timeout) {
+        sqlx::query(&timeout_sql).execute(&mut *tx).await?;
+    }
+    let mut query = sqlx::query_as::<_, EmbeddingSearchRow>(&sql)
+        .bind(&query_embedding)
+        .bind(limit);
+    if let Some(source_type) = source_type {
+        query = query.bind(source_type);
+    }
+    if let Some(user_file_ids) = user_file_ids {
+        query = query.bind(user_file_ids);
+    }
+    let results = query.fetch_all(&mut *tx).await?;
+    tx.commit().await?;
     
     Ok(results)
 }
 
-pub async fn count_embeddings(pool: &PgPool) -> Result<i64> {
-    let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM embeddings")
+/// Rows whose `hpo_terms` list any of `hpo_ids`, most IDs matched first and
+/// then closest to `query_embedding`. The similarity column is the cosine
+/// similarity, as in `search_embeddings`.
+pub async fn search_embeddings_by_hpo_ids(
+    pool: &PgPool,
+    table: &str,
+    hpo_ids: &[String],
+    query_embedding: Vec<f32>,
+    limit: i64,
+    timeout: Option<Duration>,
+) -> Result<Vec<EmbeddingSearchRow>> {
+    // Containment (@>) is what idx_embeddings_hpo_terms serves; the count only
+    // runs on the rows it finds
+    let sql = format!(
+        "SELECT text,
+                1 - (embedding <=> $4::vector) as similarity,
+                source_type,
+                source_id,
+                file_name,
+                orpha_code,
+                hpo_terms,
+                start_offset,
+                end_offset,
+                modality,
+                region
+         FROM {}
+         WHERE hpo_terms @> ANY($2::jsonb[])
+         ORDER BY (SELECT COUNT(*) FROM jsonb_array_elements(hpo_terms) term
+                   WHERE term->>'hpo_id' = ANY($1)) DESC,
+                  embedding <=> $4::vector
+         LIMIT $3",
+        table
+    );
+    let mut tx = pool.begin().await?;
+    if let Some(timeout_sql) = statement_timeout_sql(timeout) {
+        sqlx::query(&timeout_sql).execute(&mut *tx).await?;
+    }
+    let results = sqlx::query_as::<_, EmbeddingSearchRow>(&sql)
+        .bind(hpo_ids)
+        .bind(crate::rag::hpo_search::containment_patterns(hpo_ids))
+        .bind(limit)
+        .bind(&query_embedding)
+        .fetch_all(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(results)
+}
+
+/// Like `search_embeddings`, but also returns the dot product and stored vector norm.
+/// `<#>` is pgvector's negative inner product, hence the sign flip.
+pub async fn search_embeddings_debug(
+    pool: &PgPool,
+    table: &str,
+    query_embedding: Vec<f32>,
+    limit: i64,
+    timeout: Option<Duration>,
+) -> Result<Vec<EmbeddingDebugRow>> {
+    let sql = format!(
+        "SELECT text, 
+                1 - (embedding <=> $1::vector) as similarity,
+                source_type, 
+                source_id, 
+                file_name, 
+                orpha_code,
+                hpo_terms,
+                start_offset,
+                end_offset,
+                modality,
+                region,
+                -(embedding <#> $1::vector) as raw_dot,
+                vector_norm(embedding) as doc_magnitude
+         FROM {}
+         ORDER BY embedding <=> $1::vector
+         LIMIT $2",
+        table
+    );
+    let mut tx = pool.begin().await?;
+    if let Some(timeout_sql) = statement_timeout_sql(timeout) {
+        sqlx::query(&timeout_sql).execute(&mut *tx).await?;
+    }
+    let results = sqlx::query_as::<_, EmbeddingDebugRow>(&sql)
+        .bind(&query_embedding)
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    
+    Ok(results)
+}
+
+/// Share of table blocks (in percent) to sample for about `sample_size` rows out
+/// of `estimated_rows`. Block sampling is lumpy, so it asks for twice as many;
+/// a table never analyzed (estimate <= 0) is read in full.
+fn sample_percent(sample_size: i64, estimated_rows: f64) -> f64 {
+    if estimated_rows <= 0.0 {
+        return 100.0;
+    }
+    (200.0 * sample_size as f64 / estimated_rows).min(100.0)
+}
+
+/// Mean, spread and range of the similarity between `query_embedding` and up to
+/// `sample_size` randomly chosen rows. `TABLESAMPLE SYSTEM` reads only a random
+/// share of the table's blocks (sized from the planner's row estimate), so cost
+/// follows the sample rather than the table, and only the aggregate comes back.
+pub async fn sample_similarity_stats(
+    pool: &PgPool,
+    table: &str,
+    query_embedding: Vec<f32>,
+    sample_size: i64,
+    timeout: Option<Duration>,
+) -> Result<SimilarityStatsRow> {
+    let sql = format!(
+        "SELECT COUNT(*) as sampled,
+                AVG(similarity) as mean,
+                STDDEV_POP(similarity) as stddev,
+                MIN(similarity) as min,
+                MAX(similarity) as max
+         FROM (
+             SELECT 1 - (embedding <=> $1::vector) as similarity
+             FROM {} TABLESAMPLE SYSTEM ($3)
+             LIMIT $2
+         ) sample",
+        table
+    );
+    let mut tx = pool.begin().await?;
+    if let Some(timeout_sql) = statement_timeout_sql(timeout) {
+        sqlx::query(&timeout_sql).execute(&mut *tx).await?;
+    }
+    let estimated_rows = sqlx::query_scalar::<_, f32>("SELECT reltuples FROM pg_class WHERE oid = $1::regclass")
+        .bind(table)
+        .fetch_one(&mut *tx)
+        .await?;
+    let stats = sqlx::query_as::<_, SimilarityStatsRow>(&sql)
+        .bind(&query_embedding)
+        .bind(sample_size)
+        .bind(sample_percent(sample_size, estimated_rows as f64) as f32)
+        .fetch_one(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(stats)
+}
+
+/// Id and vector of one randomly chosen row, if the table has any: the first id
+/// at or after a random UUID, wrapping around to the smallest. Ids are random
+/// UUIDs, so this is close to uniform and only walks the primary key index.
+pub async fn random_embedding(pool: &PgPool, table: &str) -> Result<Option<(Uuid, Vec<f32>)>> {
+    let sql = format!(
+        "(SELECT id, embedding::real[] FROM {table} WHERE id >= $1 ORDER BY id LIMIT 1)
+         UNION ALL
+         (SELECT id, embedding::real[] FROM {table} ORDER BY id LIMIT 1)
+         LIMIT 1",
+        table = table
+    );
+    let row = sqlx::query_as::<_, (Uuid, Vec<f32>)>(&sql)
+        .bind(Uuid::new_v4())
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row)
+}
+
+/// One stored row by id. The vector is cast to `real[]` (and only when requested)
+/// since that's the shape sqlx can decode.
+pub async fn get_embedding_by_id(
+    pool: &PgPool,
+    table: &str,
+    id: Uuid,
+    include_vector: bool,
+) -> Result<Option<EmbeddingDocumentRow>> {
+    let sql = format!(
+        "SELECT id, text, source_type, source_id, file_name, orpha_code, hpo_terms,
+                start_offset, end_offset, modality, region, created_at,
+                vector_dims(embedding) as dimension,
+                CASE WHEN $2 THEN embedding::real[] END as embedding
+         FROM {}
+         WHERE id = $1",
+        table
+    );
+    let row = sqlx::query_as::<_, EmbeddingDocumentRow>(&sql)
+        .bind(id)
+        .bind(include_vector)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row)
+}
+
+/// Every stored row, read through a cursor so memory stays bounded for large tables
+pub fn stream_embeddings(
+    pool: PgPool,
+    table: String,
+    include_vector: bool,
+) -> impl futures::Stream<Item = Result<EmbeddingDocumentRow>> + Send + 'static {
+    async_stream::try_stream! {
+        let sql = format!(
+            "SELECT id, text, source_type, source_id, file_name, orpha_code, hpo_terms,
+                    start_offset, end_offset, modality, region, created_at,
+                    vector_dims(embedding) as dimension,
+                    CASE WHEN $1 THEN embedding::real[] END as embedding
+             FROM {}",
+            table
+        );
+        let mut rows = sqlx::query_as::<_, EmbeddingDocumentRow>(&sql)
+            .bind(include_vector)
+            .fetch(&pool);
+        while let Some(row) = futures::TryStreamExt::try_next(&mut rows).await? {
+            yield row;
+        }
+    }
+}
+
+/// Store exported documents in one transaction, keeping their original ids and
+/// timestamps. A document matches an existing row by `source_type` + `source_id`
+/// (+ `start_offset`, since chunks of one upload share a `source_id`); matches are
+/// skipped, or overwritten when `upsert` is set. Every document must carry its vector.
+pub async fn import_embeddings(
+    pool: &PgPool,
+    table: &str,
+    documents: &[VectorDocument],
+    upsert: bool,
+) -> Result<ImportCounts> {
+    let find_sql = format!(
+        "SELECT id FROM {}
+         WHERE source_type = $1 AND source_id = $2 AND start_offset IS NOT DISTINCT FROM $3
+         LIMIT 1",
+        table
+    );
+    let update_sql = format!(
+        "UPDATE {} SET text = $2, embedding = $3, file_name = $4, orpha_code = $5, hpo_terms = $6, end_offset = $7, modality = $8, region = $9
+         WHERE id = $1",
+        table
+    );
+    let insert_sql = format!(
+        "INSERT INTO {} (id, text, embedding, source_type, source_id, file_name, orpha_code, hpo_terms, start_offset, end_offset, modality, region, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+         ON CONFLICT (id) DO NOTHING",
+        table
+    );
+
+    let mut counts = ImportCounts::default();
+    let mut tx = pool.begin().await?;
+
+    for document in documents {
+        let metadata = &document.metadata;
+        let embedding = document
+            .embedding
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("document {} has no embedding", document.id))?;
+        let hpo_terms = (!metadata.hpo_associations.is_empty())
+            .then(|| Json(metadata.hpo_associations.clone()));
+
+        let existing: Option<Uuid> = sqlx::query_scalar(&find_sql)
+            .bind(&metadata.source_type)
+            .bind(&metadata.source_id)
+            .bind(metadata.start_offset)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        match existing {
+            Some(_) if !upsert => counts.skipped += 1,
+            Some(id) => {
+                sqlx::query(&update_sql)
+                    .bind(id)
+                    .bind(&document.text)
+                    .bind(embedding)
+                    .bind(&metadata.file_name)
+                    .bind(&metadata.orpha_code)
+                    .bind(&hpo_terms)
+                    .bind(metadata.end_offset)
+                    .bind(&metadata.modality)
+                    .bind(&metadata.region)
+                    .execute(&mut *tx)
+                    .await?;
+                counts.updated += 1;
+            }
+            None => {
+                let result = sqlx::query(&insert_sql)
+                    .bind(document.id)
+                    .bind(&document.text)
+                    .bind(embedding)
+                    .bind(&metadata.source_type)
+                    .bind(&metadata.source_id)
+                    .bind(&metadata.file_name)
+                    .bind(&metadata.orpha_code)
+                    .bind(&hpo_terms)
+                    .bind(metadata.start_offset)
+                    .bind(metadata.end_offset)
+                    .bind(&metadata.modality)
+                    .bind(&metadata.region)
+                    .bind(document.created_at)
+                    .execute(&mut *tx)
+                    .await?;
+
+                // Same id under a different key: already imported from another dump
+                if result.rows_affected() > 0 {
+                    counts.inserted += 1;
+                } else {
+                    counts.skipped += 1;
+                }
+            }
+        }
+    }
+
+    tx.commit().await?;
+    Ok(counts)
+}
+
+/// Declared dimension of `embeddings.embedding`, or `None` if the column is unconstrained
+pub async fn embedding_column_dimension(pool: &PgPool, table: &str) -> Result<Option<i32>> {
+    // pgvector stores the declared dimension in atttypmod (-1 when unconstrained)
+    let row: Option<(i32,)> = sqlx::query_as(
+        "SELECT atttypmod FROM pg_attribute
+         WHERE attrelid = $1::regclass AND attname = 'embedding'"
+    )
+    .bind(table)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|(typmod,)| typmod).filter(|dim| *dim > 0))
+}
+
+pub async fn count_embeddings(pool: &PgPool, table: &str) -> Result<i64> {
+    let sql = format!("SELECT COUNT(*) FROM {}", table);
+    let count: (i64,) = sqlx::query_as(&sql)
         .fetch_one(pool)
         .await?;
     
     Ok(count.0)
 }
 
-pub async fn count_embeddings_by_source(pool: &PgPool, source_type: &str) -> Result<i64> {
-    let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM embeddings WHERE source_type = $1")
+pub async fn count_embeddings_by_source(pool: &PgPool, table: &str, source_type: &str) -> Result<i64> {
+    let sql = format!("SELECT COUNT(*) FROM {} WHERE source_type = $1", table);
+    let count: (i64,) = sqlx::query_as(&sql)
         .bind(source_type)
         .fetch_one(pool)
         .await?;
     
     Ok(count.0)
 }
+
+pub async fn delete_embeddings_by_source(
+    pool: &PgPool,
+    table: &str,
+    source_type: &str,
+    source_id: &str,
+) -> Result<u64> {
+    let sql = format!("DELETE FROM {} WHERE source_type = $1 AND source_id = $2", table);
+    let result = sqlx::query(&sql)
+        .bind(source_type)
+        .bind(source_id)
+        .execute(pool)
+        .await?;
+    
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_timeout_is_applied() {
+        assert_eq!(
+            statement_timeout_sql(Some(Duration::from_millis(1500))).as_deref(),
+            Some("SET LOCAL statement_timeout = 1500")
+        );
+        // 0 would mean "no timeout" to Postgres, so sub-millisecond values round up
+        assert_eq!(
+            statement_timeout_sql(Some(Duration::from_micros(10))).as_deref(),
+            Some("SET LOCAL statement_timeout = 1")
+        );
+        assert_eq!(statement_timeout_sql(None), None);
+    }
+
+    #[test]
+    fn test_sample_percent() {
+        assert_eq!(sample_percent(500, 100_000.0), 1.0);
+        assert_eq!(sample_percent(5000, 10_000.0), 100.0);
+        assert_eq!(sample_percent(10, -1.0), 100.0);
+        assert_eq!(sample_percent(10, 0.0), 100.0);
+    }
+
+    #[test]
+    fn test_search_filter_sql() {
+        assert_eq!(search_filter_sql(false, false), "");
+        assert_eq!(search_filter_sql(true, false), "WHERE source_type = $3");
+        assert_eq!(
+            search_filter_sql(false, true),
+            "WHERE (source_type <> 'user_file' OR source_id = ANY($3))"
+        );
+        assert_eq!(
+            search_filter_sql(true, true),
+            "WHERE source_type = $3 AND (source_type <> 'user_file' OR source_id = ANY($4))"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::{Result, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::gemini::GeminiClient;
+use crate::redact::pii;
+
+/// `batchEmbedContents` accepts at most 100 requests per call
+const MAX_BATCH_REQUESTS: usize = 100;
+
+/// Native vector length of text-embedding-004
+const DEFAULT_DIMENSION: usize = 768;
+
+/// Remote embedding service backed by the Gemini embeddings API (text-embedding-004)
+pub struct GeminiEmbeddingService {
+    client: GeminiClient,
+    /// Requested vector length; `None` keeps the model's native 768
+    output_dimensionality: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbedContentRequest {
+    model: String,
+    content: EmbedContent,
+    #[serde(rename = "outputDimensionality", skip_serializing_if = "Option::is_none")]
+    output_dimensionality: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbedContent {
+    parts: Vec<EmbedPart>,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbedPart {
+    text: String,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchEmbedContentsRequest {
+    requests: Vec<EmbedContentRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbedContentResponse {
+    embedding: ContentEmbedding,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchEmbedContentsResponse {
+    embeddings: Vec<ContentEmbedding>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContentEmbedding {
+    values: Vec<f32>,
+}
+
+impl GeminiEmbeddingService {
+    pub fn new(client: GeminiClient) -> Self {
+        tracing::info!("Using Gemini embeddings ({})", client.embedding_model());
+        Self { client, output_dimensionality: None }
+    }
+
+    /// Ask the API for shorter vectors (text-embedding-004 supports truncating to any
+    /// length up to 768), e.g. to match the local model's 384 dimensions
+    pub fn with_output_dimensionality(mut self, dimension: Option<usize>) -> Self {
+        self.output_dimensionality = dimension;
+        self
+    }
+
+    /// Generate embedding for a single text
+    pub async fn embed_text(&self, text: &str) -> Result<Vec<f32>> {
+        let body = self.request_for(text.to_string());
+
+        let res = self.client.embed_content(&body).await?;
+        let status = res.status();
+        let body = res.text().await?;
+
+        if !status.is_success() {
+            anyhow::bail!("Gemini embedding error {}: {}", status, pii(&body));
+        }
+
+        let parsed: EmbedContentResponse = serde_json::from_str(&body)
+            .context("Failed to parse Gemini embedding response")?;
+
+        Ok(parsed.embedding.values)
+    }
+
+    /// Generate embeddings for multiple texts (batch processing)
+    pub async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+
+        for chunk in texts.chunks(MAX_BATCH_REQUESTS) {
+            let body = BatchEmbedContentsRequest {
+                requests: chunk.iter().map(|t| self.request_for(t.clone())).collect(),
+            };
+
+            let res = self.client.batch_embed_contents(&body).await?;
+            let status = res.status();
+            let body = res.text().await?;
+
+            if !status.is_success() {
+                anyhow::bail!("Gemini batch embedding error {}: {}", status, pii(&body));
+            }
+
+            let parsed: BatchEmbedContentsResponse = serde_json::from_str(&body)
+                .context("Failed to parse Gemini batch embedding response")?;
+
+            if parsed.embeddings.len() != chunk.len() {
+                anyhow::bail!(
+                    "Gemini returned {} embeddings for {} texts",
+                    parsed.embeddings.len(),
+                    chunk.len()
+                );
+            }
+
+            embeddings.extend(parsed.embeddings.into_iter().map(|e| e.values));
+        }
+
+        Ok(embeddings)
+    }
+
+    /// Get the embedding dimension (768 for text-embedding-004 unless reduced)
+    pub fn dimension(&self) -> usize {
+        self.output_dimensionality.unwrap_or(DEFAULT_DIMENSION)
+    }
+
+    fn request_for(&self, text: String) -> EmbedContentRequest {
+        EmbedContentRequest {
+            model: format!("models/{}", self.client.embedding_model()),
+            content: EmbedContent {
+                parts: vec![EmbedPart { text }],
+            },
+            output_dimensionality: self.output_dimensionality,
+        }
+    }
+}
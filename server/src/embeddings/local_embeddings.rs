@@ -1,38 +1,139 @@
 use anyhow::{Result, Context};
 use fastembed::{TextEmbedding, InitOptions, EmbeddingModel};
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::sync::{Arc, OnceLock};
 use tokio::sync::Mutex;
 
+/// Tokens all-MiniLM-L6-v2 attends to; anything beyond is silently cut by the model
+pub const MAX_SEQUENCE_LENGTH: usize = 256;
+
+/// Rough characters per token for English clinical text
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Default `LOCAL_EMBEDDING_SLICE`
+const DEFAULT_BATCH_SLICE: usize = 16;
+
 /// Local embedding service using FastEmbed (all-MiniLM-L6-v2)
 /// This allows fast, offline embeddings without API calls
 pub struct LocalEmbeddingService {
-    model: Arc<Mutex<TextEmbedding>>,
+    model: OnceLock<ModelLock<TextEmbedding>>,
+    /// Inputs are cut to this many chars before embedding (`LOCAL_EMBEDDING_MAX_CHARS`)
+    max_input_chars: usize,
+    /// Batch inputs embedded per turn on the model (`LOCAL_EMBEDDING_SLICE`), which
+    /// bounds how long a chat query can wait behind a loader or upload batch
+    batch_slice: usize,
+}
+
+/// Turn-taking access to a model that can only run one call at a time.
+///
+/// Waiters are served in arrival order (tokio's mutex is fair), and each call runs
+/// on a blocking thread so inference never stalls the async workers. The guard
+/// moves into that thread: a caller that gives up mid-call leaves the job to finish
+/// and release the model, rather than abandoning it half-used.
+struct ModelLock<M>(Arc<Mutex<M>>);
+
+impl<M: Send + 'static> ModelLock<M> {
+    fn new(model: M) -> Self {
+        Self(Arc::new(Mutex::new(model)))
+    }
+
+    async fn run<R, F>(&self, job: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&M) -> R + Send + 'static,
+    {
+        let model = self.0.clone().lock_owned().await;
+        tokio::task::spawn_blocking(move || job(&model))
+            .await
+            .context("Embedding task panicked")
+    }
 }
 
 impl LocalEmbeddingService {
     /// Initialize the local embedding model
     /// Downloads model on first run (~50MB), then cached locally
     pub fn new() -> Result<Self> {
+        let service = Self::deferred();
+        let _ = service.model.set(ModelLock::new(Self::load_model()?));
+        Ok(service)
+    }
+
+    /// Create the service without loading the model yet; call `load` before embedding.
+    /// Lets the server start listening (and report not-ready) while the model downloads.
+    pub fn deferred() -> Self {
+        let max_input_chars = std::env::var("LOCAL_EMBEDDING_MAX_CHARS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(MAX_SEQUENCE_LENGTH * CHARS_PER_TOKEN);
+        let batch_slice = std::env::var("LOCAL_EMBEDDING_SLICE")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(DEFAULT_BATCH_SLICE);
+
+        Self { model: OnceLock::new(), max_input_chars, batch_slice }
+    }
+
+    /// Load the model on a blocking thread (no-op if already loaded)
+    pub async fn load(&self) -> Result<()> {
+        if self.is_loaded() {
+            return Ok(());
+        }
+
+        let model = tokio::task::spawn_blocking(Self::load_model)
+            .await
+            .context("Embedding model loader task panicked")??;
+        let _ = self.model.set(ModelLock::new(model));
+
+        Ok(())
+    }
+
+    /// Whether the model has finished loading
+    pub fn is_loaded(&self) -> bool {
+        self.model.get().is_some()
+    }
+
+    fn load_model() -> Result<TextEmbedding> {
         tracing::info!("Initializing local embedding model (all-MiniLM-L6-v2)...");
-        
+
         let model = TextEmbedding::try_new(
             InitOptions::new(EmbeddingModel::AllMiniLML6V2)
                 .with_show_download_progress(true)
         ).context("Failed to initialize local embedding model")?;
-        
+
         tracing::info!("Local embedding model loaded successfully");
-        
-        Ok(Self {
-            model: Arc::new(Mutex::new(model)),
-        })
+
+        Ok(model)
+    }
+
+    fn model(&self) -> Result<&ModelLock<TextEmbedding>> {
+        self.model
+            .get()
+            .ok_or_else(|| anyhow::anyhow!("Local embedding model is still loading"))
+    }
+    
+    /// Characters kept per input; the rest would be dropped by the model anyway
+    pub fn max_input_chars(&self) -> usize {
+        self.max_input_chars
     }
     
     /// Generate embedding for a single text
     pub async fn embed_text(&self, text: &str) -> Result<Vec<f32>> {
-        let model = self.model.lock().await;
+        let input = truncate_input(text, self.max_input_chars);
+        if let Cow::Owned(_) = input {
+            tracing::warn!(
+                "Embedding input truncated from {} to {} chars",
+                text.chars().count(),
+                self.max_input_chars
+            );
+        }
         
-        let embeddings = model
-            .embed(vec![text.to_string()], None)
+        let input = input.into_owned();
+        let embeddings = self
+            .model()?
+            .run(move |model| model.embed(vec![input], None))
+            .await?
             .context("Failed to generate embedding")?;
         
         let embedding = embeddings
@@ -48,13 +149,42 @@ impl LocalEmbeddingService {
             return Ok(vec![]);
         }
         
-        let model = self.model.lock().await;
+        let mut truncated = 0;
+        let texts: Vec<String> = texts
+            .into_iter()
+            .map(|text| match truncate_input(&text, self.max_input_chars) {
+                Cow::Borrowed(_) => text,
+                Cow::Owned(cut) => {
+                    truncated += 1;
+                    cut
+                }
+            })
+            .collect();
+        if truncated > 0 {
+            tracing::warn!(
+                "{} of {} embedding inputs truncated to {} chars",
+                truncated,
+                texts.len(),
+                self.max_input_chars
+            );
+        }
+        
+        let model = self.model()?;
         
         tracing::debug!("Generating {} embeddings in batch", texts.len());
         
-        let embeddings = model
-            .embed(texts, None)
-            .context("Failed to generate batch embeddings")?;
+        // Give the model back between slices so queued chat queries get a turn
+        let mut embeddings = Vec::with_capacity(texts.len());
+        let mut rest = texts;
+        while !rest.is_empty() {
+            let tail = rest.split_off(rest.len().min(self.batch_slice));
+            let slice = std::mem::replace(&mut rest, tail);
+            let slice_embeddings = model
+                .run(move |model| model.embed(slice, None))
+                .await?
+                .context("Failed to generate batch embeddings")?;
+            embeddings.extend(slice_embeddings);
+        }
         
         Ok(embeddings)
     }
@@ -65,10 +195,64 @@ impl LocalEmbeddingService {
     }
 }
 
+/// Cut `text` to at most `max_chars` characters, preferring a word boundary
+fn truncate_input(text: &str, max_chars: usize) -> Cow<'_, str> {
+    let Some((byte_end, _)) = text.char_indices().nth(max_chars) else {
+        return Cow::Borrowed(text);
+    };
+
+    let head = &text[..byte_end];
+    // Back off to the last whitespace unless that would throw away most of the input
+    let cut = head
+        .rfind(char::is_whitespace)
+        .filter(|idx| *idx >= byte_end / 2)
+        .unwrap_or(byte_end);
+
+    Cow::Owned(head[..cut].trim_end().to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_truncate_input() {
+        assert!(matches!(truncate_input("short text", 100), Cow::Borrowed("short text")));
+        assert_eq!(truncate_input("alpha beta gamma", 12), "alpha beta");
+        assert_eq!(truncate_input("abcdefghij", 4), "abcd");
+        // Multi-byte characters are counted as characters, not bytes
+        assert_eq!(truncate_input("ééééé", 3), "ééé");
+    }
+
+    /// A slow batch run in slices must not hold the model for its whole duration
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_interactive_call_waits_for_one_slice_only() {
+        use std::time::{Duration, Instant};
+
+        const SLICE_COST: Duration = Duration::from_millis(40);
+        const SLICES: u32 = 10;
+        let model = Arc::new(ModelLock::new(()));
+
+        let batch = {
+            let model = model.clone();
+            tokio::spawn(async move {
+                for _ in 0..SLICES {
+                    model.run(|_| std::thread::sleep(SLICE_COST)).await.unwrap();
+                }
+            })
+        };
+
+        tokio::time::sleep(SLICE_COST / 2).await;
+        let started = Instant::now();
+        model.run(|_| ()).await.unwrap();
+        let waited = started.elapsed();
+
+        // Holding the lock for the whole batch would make this ~SLICES * SLICE_COST
+        assert!(waited < SLICE_COST * 3, "interactive call waited {:?}", waited);
+        assert!(!batch.is_finished());
+        batch.await.unwrap();
+    }
+
     #[tokio::test]
     async fn test_embed_text() {
         let service = LocalEmbeddingService::new().unwrap();
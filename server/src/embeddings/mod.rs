@@ -1,3 +1,7 @@
 pub mod local_embeddings;
+pub mod gemini_embeddings;
+pub mod provider;
 
 pub use local_embeddings::LocalEmbeddingService;
+pub use gemini_embeddings::GeminiEmbeddingService;
+pub use provider::{EmbeddingProvider, provider_from_env};
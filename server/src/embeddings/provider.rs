@@ -0,0 +1,372 @@
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{GeminiEmbeddingService, LocalEmbeddingService};
+use crate::gemini::GeminiClient;
+
+/// Common interface over the embedding backends.
+///
+/// Every piece of content (Orphanet disorders, PDF chunks, image descriptions
+/// and chat queries) must be embedded by the same provider, otherwise the
+/// vectors live in different spaces and similarity scores are meaningless.
+pub trait EmbeddingProvider: Send + Sync {
+    /// Short identifier used in logs (e.g. "local", "gemini")
+    fn name(&self) -> &str;
+
+    /// Length of the vectors produced by this provider
+    fn dimension(&self) -> usize;
+
+    /// Longest input (in chars) embedded in full; longer inputs are truncated. `None` if unknown.
+    fn max_input_chars(&self) -> Option<usize> {
+        None
+    }
+
+    /// Finish any slow setup (e.g. loading a local model). Embedding calls fail until this completes.
+    fn initialize(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Generate embedding for a single text
+    fn embed_text<'a>(&'a self, text: &'a str) -> BoxFuture<'a, Result<Vec<f32>>>;
+
+    /// Generate embeddings for multiple texts, preserving input order
+    fn embed_batch(&self, texts: Vec<String>) -> BoxFuture<'_, Result<Vec<Vec<f32>>>>;
+
+    /// Like `embed_batch`, for vectors that get stored. Rows don't record which model
+    /// produced them, so this must only ever use the provider the table was built with.
+    fn embed_for_storage(&self, texts: Vec<String>) -> BoxFuture<'_, Result<Vec<Vec<f32>>>> {
+        self.embed_batch(texts)
+    }
+}
+
+impl EmbeddingProvider for LocalEmbeddingService {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    fn dimension(&self) -> usize {
+        LocalEmbeddingService::dimension(self)
+    }
+
+    fn max_input_chars(&self) -> Option<usize> {
+        Some(LocalEmbeddingService::max_input_chars(self))
+    }
+
+    fn initialize(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.load())
+    }
+
+    fn embed_text<'a>(&'a self, text: &'a str) -> BoxFuture<'a, Result<Vec<f32>>> {
+        Box::pin(LocalEmbeddingService::embed_text(self, text))
+    }
+
+    fn embed_batch(&self, texts: Vec<String>) -> BoxFuture<'_, Result<Vec<Vec<f32>>>> {
+        Box::pin(LocalEmbeddingService::embed_batch(self, texts))
+    }
+}
+
+impl EmbeddingProvider for GeminiEmbeddingService {
+    fn name(&self) -> &str {
+        "gemini"
+    }
+
+    fn dimension(&self) -> usize {
+        GeminiEmbeddingService::dimension(self)
+    }
+
+    fn embed_text<'a>(&'a self, text: &'a str) -> BoxFuture<'a, Result<Vec<f32>>> {
+        Box::pin(GeminiEmbeddingService::embed_text(self, text))
+    }
+
+    fn embed_batch(&self, texts: Vec<String>) -> BoxFuture<'_, Result<Vec<Vec<f32>>>> {
+        Box::pin(GeminiEmbeddingService::embed_batch(self, texts))
+    }
+}
+
+/// Retries the primary provider, then hands the request to a fallback provider.
+///
+/// Both providers must produce vectors of the same length, but vectors from
+/// different models are not comparable. The fallback therefore only embeds
+/// queries: `embed_for_storage` fails once the primary's retries run out rather
+/// than write fallback vectors into the primary's table. Every fallback is
+/// logged as a warning.
+pub struct FallbackEmbeddingProvider {
+    primary: Arc<dyn EmbeddingProvider>,
+    fallback: Option<Arc<dyn EmbeddingProvider>>,
+    retries: usize,
+    backoff: Duration,
+    name: String,
+}
+
+impl FallbackEmbeddingProvider {
+    pub fn new(
+        primary: Arc<dyn EmbeddingProvider>,
+        fallback: Option<Arc<dyn EmbeddingProvider>>,
+        retries: usize,
+    ) -> Result<Self> {
+        if let Some(fallback) = &fallback
+            && fallback.dimension() != primary.dimension()
+        {
+            anyhow::bail!(
+                "Fallback embedding provider {} produces {}-dim vectors but {} produces {}",
+                fallback.name(),
+                fallback.dimension(),
+                primary.name(),
+                primary.dimension()
+            );
+        }
+
+        let name = match &fallback {
+            Some(fallback) => format!("{} (fallback: {})", primary.name(), fallback.name()),
+            None => primary.name().to_string(),
+        };
+
+        Ok(Self { primary, fallback, retries, backoff: Duration::from_millis(500), name })
+    }
+
+    /// Delay before the first retry; doubles on each further attempt
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Run `call` against the primary with retries, then once against the fallback
+    /// when `use_fallback`
+    async fn run<'a, T>(
+        &'a self,
+        use_fallback: bool,
+        call: impl Fn(&'a dyn EmbeddingProvider) -> BoxFuture<'a, Result<T>>,
+    ) -> Result<T> {
+        let mut attempt = 0;
+        let error = loop {
+            match call(self.primary.as_ref()).await {
+                Ok(result) => return Ok(result),
+                Err(e) if attempt < self.retries => {
+                    let delay = self.backoff * 2u32.saturating_pow(attempt as u32);
+                    tracing::warn!(
+                        "Embedding provider {} failed (attempt {}), retrying in {}ms: {:#}",
+                        self.primary.name(),
+                        attempt + 1,
+                        delay.as_millis(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => break e,
+            }
+        };
+
+        let Some(fallback) = self.fallback.as_ref().filter(|_| use_fallback) else {
+            return Err(error);
+        };
+        tracing::warn!(
+            "Embedding provider {} failed after {} attempt(s), falling back to {}: {:#}",
+            self.primary.name(),
+            attempt + 1,
+            fallback.name(),
+            error
+        );
+        call(fallback.as_ref())
+            .await
+            .with_context(|| format!("Fallback embedding provider {} also failed", fallback.name()))
+    }
+}
+
+impl EmbeddingProvider for FallbackEmbeddingProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn dimension(&self) -> usize {
+        self.primary.dimension()
+    }
+
+    /// The tighter of the two limits, since either may end up embedding the input
+    fn max_input_chars(&self) -> Option<usize> {
+        let fallback = self.fallback.as_ref().and_then(|f| f.max_input_chars());
+        match (self.primary.max_input_chars(), fallback) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn initialize(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.primary.initialize().await?;
+            if let Some(fallback) = &self.fallback {
+                fallback.initialize().await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn embed_text<'a>(&'a self, text: &'a str) -> BoxFuture<'a, Result<Vec<f32>>> {
+        Box::pin(self.run(true, move |provider| provider.embed_text(text)))
+    }
+
+    fn embed_batch(&self, texts: Vec<String>) -> BoxFuture<'_, Result<Vec<Vec<f32>>>> {
+        Box::pin(async move {
+            let texts = &texts;
+            self.run(true, move |provider| provider.embed_batch(texts.clone())).await
+        })
+    }
+
+    fn embed_for_storage(&self, texts: Vec<String>) -> BoxFuture<'_, Result<Vec<Vec<f32>>>> {
+        Box::pin(async move {
+            let texts = &texts;
+            self.run(false, move |provider| provider.embed_batch(texts.clone())).await
+        })
+    }
+}
+
+/// Build the embedding provider selected by `EMBEDDING_PROVIDER` (`local` or `gemini`, default `local`).
+///
+/// `EMBEDDING_RETRIES` retries the provider on failure and `EMBEDDING_FALLBACK=local`
+/// falls back to fastembed for chat queries once retries run out (stored content is
+/// never embedded by the fallback). With a local fallback, Gemini is asked
+/// for 384-dim vectors (unless `GEMINI_EMBEDDING_DIMENSION` says otherwise) so both
+/// providers fit the same table.
+///
+/// The returned provider is not initialized yet; call `initialize` before embedding.
+pub fn provider_from_env(gemini: &GeminiClient) -> Result<Arc<dyn EmbeddingProvider>> {
+    let provider = std::env::var("EMBEDDING_PROVIDER")
+        .unwrap_or_else(|_| "local".to_string())
+        .to_lowercase();
+    let fallback = std::env::var("EMBEDDING_FALLBACK")
+        .unwrap_or_default()
+        .to_lowercase();
+    let retries = std::env::var("EMBEDDING_RETRIES")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let fallback: Option<Arc<dyn EmbeddingProvider>> = match fallback.as_str() {
+        "" | "none" => None,
+        "local" if provider == "local" => {
+            anyhow::bail!("EMBEDDING_FALLBACK=local has no effect when EMBEDDING_PROVIDER=local")
+        }
+        "local" => Some(Arc::new(LocalEmbeddingService::deferred())),
+        other => anyhow::bail!(
+            "Unknown EMBEDDING_FALLBACK '{}'. Valid options: local, none",
+            other
+        ),
+    };
+
+    let primary: Arc<dyn EmbeddingProvider> = match provider.as_str() {
+        "local" => Arc::new(LocalEmbeddingService::deferred()),
+        "gemini" => {
+            let dimension = std::env::var("GEMINI_EMBEDDING_DIMENSION")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .or(fallback.as_ref().map(|f| f.dimension()));
+            Arc::new(GeminiEmbeddingService::new(gemini.clone()).with_output_dimensionality(dimension))
+        }
+        other => anyhow::bail!(
+            "Unknown EMBEDDING_PROVIDER '{}'. Valid options: local, gemini",
+            other
+        ),
+    };
+
+    if fallback.is_none() && retries == 0 {
+        return Ok(primary);
+    }
+    Ok(Arc::new(FallbackEmbeddingProvider::new(primary, fallback, retries)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Returns `value`-filled vectors, failing the first `failures` calls
+    struct StubProvider {
+        name: &'static str,
+        dimension: usize,
+        value: f32,
+        failures: usize,
+        calls: AtomicUsize,
+    }
+
+    impl StubProvider {
+        fn new(name: &'static str, dimension: usize, value: f32, failures: usize) -> Arc<Self> {
+            Arc::new(Self { name, dimension, value, failures, calls: AtomicUsize::new(0) })
+        }
+    }
+
+    impl EmbeddingProvider for StubProvider {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn dimension(&self) -> usize {
+            self.dimension
+        }
+
+        fn embed_text<'a>(&'a self, _text: &'a str) -> BoxFuture<'a, Result<Vec<f32>>> {
+            Box::pin(async move {
+                if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                    anyhow::bail!("{} unavailable", self.name);
+                }
+                Ok(vec![self.value; self.dimension])
+            })
+        }
+
+        fn embed_batch(&self, texts: Vec<String>) -> BoxFuture<'_, Result<Vec<Vec<f32>>>> {
+            Box::pin(async move {
+                if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                    anyhow::bail!("{} unavailable", self.name);
+                }
+                Ok(vec![vec![self.value; self.dimension]; texts.len()])
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_before_falling_back() {
+        let primary = StubProvider::new("primary", 4, 1.0, 1);
+        let fallback = StubProvider::new("fallback", 4, 2.0, 0);
+        let provider = FallbackEmbeddingProvider::new(primary.clone(), Some(fallback.clone()), 1)
+            .unwrap()
+            .with_backoff(Duration::ZERO);
+
+        assert_eq!(provider.embed_text("q").await.unwrap(), vec![1.0; 4]);
+        assert_eq!(primary.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(fallback.calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_when_primary_keeps_failing() {
+        let primary = StubProvider::new("primary", 4, 1.0, usize::MAX);
+        let fallback = StubProvider::new("fallback", 4, 2.0, 0);
+        let provider = FallbackEmbeddingProvider::new(primary.clone(), Some(fallback), 2)
+            .unwrap()
+            .with_backoff(Duration::ZERO);
+
+        let batch = provider.embed_batch(vec!["a".into(), "b".into()]).await.unwrap();
+        assert_eq!(batch, vec![vec![2.0; 4]; 2]);
+        assert_eq!(primary.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_stored_content_never_uses_fallback() {
+        let primary = StubProvider::new("primary", 4, 1.0, usize::MAX);
+        let fallback = StubProvider::new("fallback", 4, 2.0, 0);
+        let provider = FallbackEmbeddingProvider::new(primary.clone(), Some(fallback.clone()), 1)
+            .unwrap()
+            .with_backoff(Duration::ZERO);
+
+        assert!(provider.embed_for_storage(vec!["chunk".into()]).await.is_err());
+        assert_eq!(primary.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(fallback.calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_rejects_fallback_with_other_dimension() {
+        let primary = StubProvider::new("primary", 768, 1.0, 0);
+        let fallback = StubProvider::new("fallback", 384, 2.0, 0);
+        assert!(FallbackEmbeddingProvider::new(primary, Some(fallback), 0).is_err());
+    }
+}
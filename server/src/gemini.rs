@@ -0,0 +1,299 @@
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::gemini_scheduler::{GeminiScheduler, SchedulerSettings};
+
+const GEMINI_API_BASE: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+/// Generation models accepted by default; override with `GEMINI_ALLOWED_MODELS`
+const KNOWN_MODELS: &[&str] = &[
+    "gemini-2.5-pro",
+    "gemini-2.5-flash",
+    "gemini-2.5-flash-lite",
+    "gemini-2.0-flash",
+    "gemini-2.0-flash-lite",
+    "gemini-1.5-pro",
+    "gemini-1.5-flash",
+    "gemini-1.5-flash-8b",
+];
+
+/// Output token ceiling of the Gemini 2.x flash models
+const MAX_OUTPUT_TOKENS_LIMIT: u32 = 8192;
+
+/// Input token limit of the Gemini 2.x flash models
+const DEFAULT_MAX_INPUT_TOKENS: u32 = 1_048_576;
+
+/// Sampling and length settings sent with every generation request
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerationSettings {
+    /// Token budget for user-facing answers
+    pub answer_max_output_tokens: u32,
+    /// Token budget for the JSON-only passes (normalize, select, thinking)
+    pub compact_max_output_tokens: u32,
+    /// Largest prompt the model accepts; longer answer prompts lose retrieved context to fit
+    pub max_input_tokens: u32,
+    pub top_p: Option<f32>,
+    pub top_k: Option<u32>,
+}
+
+impl Default for GenerationSettings {
+    fn default() -> Self {
+        Self {
+            answer_max_output_tokens: 1024,
+            compact_max_output_tokens: 256,
+            max_input_tokens: DEFAULT_MAX_INPUT_TOKENS,
+            top_p: None,
+            top_k: None,
+        }
+    }
+}
+
+impl GenerationSettings {
+    /// Read `GEMINI_ANSWER_MAX_TOKENS`, `GEMINI_COMPACT_MAX_TOKENS`, `GEMINI_MAX_INPUT_TOKENS`,
+    /// `GEMINI_TOP_P` and `GEMINI_TOP_K`, rejecting values Gemini would refuse
+    pub fn from_env() -> anyhow::Result<Self> {
+        let defaults = Self::default();
+        let settings = Self {
+            answer_max_output_tokens: env_parse("GEMINI_ANSWER_MAX_TOKENS")?
+                .unwrap_or(defaults.answer_max_output_tokens),
+            compact_max_output_tokens: env_parse("GEMINI_COMPACT_MAX_TOKENS")?
+                .unwrap_or(defaults.compact_max_output_tokens),
+            max_input_tokens: env_parse("GEMINI_MAX_INPUT_TOKENS")?
+                .unwrap_or(defaults.max_input_tokens),
+            top_p: env_parse("GEMINI_TOP_P")?,
+            top_k: env_parse("GEMINI_TOP_K")?,
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, tokens) in [
+            ("GEMINI_ANSWER_MAX_TOKENS", self.answer_max_output_tokens),
+            ("GEMINI_COMPACT_MAX_TOKENS", self.compact_max_output_tokens),
+        ] {
+            anyhow::ensure!(
+                (1..=MAX_OUTPUT_TOKENS_LIMIT).contains(&tokens),
+                "{} must be between 1 and {}, got {}",
+                name,
+                MAX_OUTPUT_TOKENS_LIMIT,
+                tokens
+            );
+        }
+        anyhow::ensure!(self.max_input_tokens >= 1, "GEMINI_MAX_INPUT_TOKENS must be at least 1");
+        if let Some(top_p) = self.top_p {
+            anyhow::ensure!((0.0..=1.0).contains(&top_p), "GEMINI_TOP_P must be between 0 and 1, got {}", top_p);
+        }
+        if let Some(top_k) = self.top_k {
+            anyhow::ensure!(top_k >= 1, "GEMINI_TOP_K must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Reject unknown generation models at boot; a typo would otherwise surface as a 404 on the first chat
+fn validate_model(model: &str, allowed: &[String]) -> anyhow::Result<()> {
+    if allowed.iter().any(|m| m == model) {
+        return Ok(());
+    }
+    anyhow::bail!(
+        "Unknown GEMINI_MODEL '{}'. Valid options: {}. \
+         Set GEMINI_ALLOWED_MODELS to extend the list or GEMINI_ALLOW_ANY_MODEL=true to skip this check.",
+        model,
+        allowed.join(", ")
+    )
+}
+
+/// Parse an optional env var, failing on present-but-invalid values
+fn env_parse<T: std::str::FromStr>(name: &str) -> anyhow::Result<Option<T>> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse::<T>()
+            .map(Some)
+            .map_err(|_| anyhow::anyhow!("{} has an invalid value '{}'", name, value)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Shared Gemini API client.
+///
+/// Cloning is cheap: the underlying `reqwest::Client` pools connections
+/// internally and the configuration strings are reference-counted, so a single
+/// instance lives in `AppState` and is handed to every component that talks to
+/// Gemini. Every clone shares one `GeminiScheduler`, so all requests count
+/// against the same global rate limit.
+#[derive(Clone)]
+pub struct GeminiClient {
+    http_client: reqwest::Client,
+    api_key: Arc<String>,
+    model: Arc<String>,
+    embedding_model: Arc<String>,
+    generation: Arc<GenerationSettings>,
+    scheduler: Arc<GeminiScheduler>,
+}
+
+impl GeminiClient {
+    pub fn new(
+        api_key: String,
+        model: String,
+        embedding_model: String,
+        generation: GenerationSettings,
+    ) -> Self {
+        Self {
+            http_client: reqwest::Client::new(),
+            api_key: Arc::new(api_key),
+            model: Arc::new(model),
+            embedding_model: Arc::new(embedding_model),
+            generation: Arc::new(generation),
+            scheduler: Arc::new(GeminiScheduler::unlimited()),
+        }
+    }
+
+    /// Pace every request through `scheduler`
+    pub fn with_scheduler(mut self, scheduler: GeminiScheduler) -> Self {
+        self.scheduler = Arc::new(scheduler);
+        self
+    }
+
+    /// Build the client from `GEMINI_API_KEY`, `GEMINI_MODEL`, `GEMINI_EMBEDDING_MODEL`,
+    /// the generation settings (see `GenerationSettings::from_env`) and the global
+    /// rate limit (see `SchedulerSettings::from_lookup`)
+    pub fn from_env() -> anyhow::Result<Self> {
+        let api_key = std::env::var("GEMINI_API_KEY")
+            .map_err(|_| anyhow::anyhow!("GEMINI_API_KEY not set, Set it in .env file"))?;
+        let model = std::env::var("GEMINI_MODEL")
+            .unwrap_or_else(|_| "gemini-2.0-flash".to_string());
+        let embedding_model = std::env::var("GEMINI_EMBEDDING_MODEL")
+            .unwrap_or_else(|_| "text-embedding-004".to_string());
+
+        let allow_any_model = std::env::var("GEMINI_ALLOW_ANY_MODEL")
+            .unwrap_or_else(|_| "false".to_string())
+            .to_lowercase() == "true";
+        if !allow_any_model {
+            let allowed: Vec<String> = match std::env::var("GEMINI_ALLOWED_MODELS") {
+                Ok(list) => list.split(',').map(|m| m.trim().to_string()).filter(|m| !m.is_empty()).collect(),
+                Err(_) => KNOWN_MODELS.iter().map(|m| m.to_string()).collect(),
+            };
+            validate_model(&model, &allowed)?;
+        }
+
+        let generation = GenerationSettings::from_env()?;
+        let scheduler = SchedulerSettings::from_lookup(&|name| std::env::var(name).ok());
+
+        Ok(Self::new(api_key, model, embedding_model, generation)
+            .with_scheduler(GeminiScheduler::new(scheduler)))
+    }
+
+    /// Generation model used for the chat pipeline passes
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Model used for Gemini-backed embeddings
+    pub fn embedding_model(&self) -> &str {
+        &self.embedding_model
+    }
+
+    /// Length and sampling settings for generation requests
+    pub fn generation(&self) -> &GenerationSettings {
+        &self.generation
+    }
+
+    /// POST a `generateContent` request for the configured generation model.
+    /// Fails with `SchedulerBusy` when the global queue is too long.
+    pub async fn generate_content<T: Serialize + ?Sized>(
+        &self,
+        body: &T,
+    ) -> anyhow::Result<reqwest::Response> {
+        self.scheduler.acquire().await?;
+        let res = self.http_client
+            .post(self.endpoint(&self.model, "generateContent"))
+            .json(body)
+            .send()
+            .await?;
+        Ok(res)
+    }
+
+    /// POST a `streamGenerateContent` request for the configured generation
+    /// model. The response body is server-sent events, one partial
+    /// `GenerateContentResponse` per `data:` line.
+    pub async fn stream_generate_content<T: Serialize + ?Sized>(
+        &self,
+        body: &T,
+    ) -> anyhow::Result<reqwest::Response> {
+        self.scheduler.acquire().await?;
+        let res = self.http_client
+            .post(format!("{}&alt=sse", self.endpoint(&self.model, "streamGenerateContent")))
+            .json(body)
+            .send()
+            .await?;
+        Ok(res)
+    }
+
+    /// POST an `embedContent` request for the configured embedding model
+    pub async fn embed_content<T: Serialize + ?Sized>(
+        &self,
+        body: &T,
+    ) -> anyhow::Result<reqwest::Response> {
+        self.scheduler.acquire().await?;
+        let res = self.http_client
+            .post(self.endpoint(&self.embedding_model, "embedContent"))
+            .json(body)
+            .send()
+            .await?;
+        Ok(res)
+    }
+
+    /// POST a `batchEmbedContents` request for the configured embedding model
+    pub async fn batch_embed_contents<T: Serialize + ?Sized>(
+        &self,
+        body: &T,
+    ) -> anyhow::Result<reqwest::Response> {
+        self.scheduler.acquire().await?;
+        let res = self.http_client
+            .post(self.endpoint(&self.embedding_model, "batchEmbedContents"))
+            .json(body)
+            .send()
+            .await?;
+        Ok(res)
+    }
+
+    fn endpoint(&self, model: &str, method: &str) -> String {
+        format!("{}/models/{}:{}?key={}", GEMINI_API_BASE, model, method, self.api_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_model() {
+        let known: Vec<String> = KNOWN_MODELS.iter().map(|m| m.to_string()).collect();
+        assert!(validate_model("gemini-2.0-flash", &known).is_ok());
+
+        let err = validate_model("gemini-1.5-pr", &known).unwrap_err().to_string();
+        assert!(err.contains("gemini-1.5-pr"));
+        assert!(err.contains("gemini-1.5-pro"));
+
+        assert!(validate_model("gemini-3.0-flash", &["gemini-3.0-flash".to_string()]).is_ok());
+    }
+
+    #[test]
+    fn test_generation_settings_validation() {
+        assert!(GenerationSettings::default().validate().is_ok());
+
+        let too_long = GenerationSettings { answer_max_output_tokens: 100_000, ..Default::default() };
+        assert!(too_long.validate().is_err());
+
+        let zero = GenerationSettings { compact_max_output_tokens: 0, ..Default::default() };
+        assert!(zero.validate().is_err());
+
+        let no_input = GenerationSettings { max_input_tokens: 0, ..Default::default() };
+        assert!(no_input.validate().is_err());
+
+        let bad_top_p = GenerationSettings { top_p: Some(1.5), ..Default::default() };
+        assert!(bad_top_p.validate().is_err());
+    }
+}
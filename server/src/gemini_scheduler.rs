@@ -0,0 +1,205 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::config::{Lookup, parse_or};
+
+/// Global pacing for Gemini requests (`GEMINI_RPM`, `GEMINI_BURST`, `GEMINI_MAX_QUEUE_WAIT_MS`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SchedulerSettings {
+    /// Sustained request rate across the whole server; 0 disables pacing
+    pub requests_per_minute: u32,
+    /// Requests allowed back-to-back after an idle period
+    pub burst: u32,
+    /// Callers that would queue longer than this fail fast instead
+    pub max_queue_wait: Duration,
+}
+
+impl Default for SchedulerSettings {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: 0,
+            burst: 5,
+            max_queue_wait: Duration::from_secs(10),
+        }
+    }
+}
+
+impl SchedulerSettings {
+    pub fn from_lookup(lookup: Lookup<'_>) -> Self {
+        let defaults = Self::default();
+        Self {
+            requests_per_minute: parse_or(lookup, "GEMINI_RPM", defaults.requests_per_minute),
+            burst: parse_or(lookup, "GEMINI_BURST", defaults.burst).max(1),
+            max_queue_wait: Duration::from_millis(parse_or(
+                lookup,
+                "GEMINI_MAX_QUEUE_WAIT_MS",
+                defaults.max_queue_wait.as_millis() as u64,
+            )),
+        }
+    }
+}
+
+/// The queue ahead of a caller is longer than `max_queue_wait`. Retryable: the
+/// quota frees up by itself, so clients should back off and try again.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "Gemini rate limit: request would wait {}ms for a slot (limit {}ms), retry later",
+    .wait.as_millis(),
+    .max_wait.as_millis()
+)]
+pub struct SchedulerBusy {
+    pub wait: Duration,
+    pub max_wait: Duration,
+}
+
+/// Token bucket shared by every Gemini call (generation and embeddings), so
+/// concurrent chats and background ingestion stay under one global RPM instead
+/// of each call site discovering the quota through 429s.
+///
+/// Callers that find the bucket empty reserve the next free slot and sleep until
+/// it arrives; reservations are handed out in arrival order, which makes the
+/// bucket's negative balance the queue.
+pub struct GeminiScheduler {
+    settings: SchedulerSettings,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    /// Available requests; negative while callers are queued
+    tokens: f64,
+    updated: Instant,
+}
+
+impl GeminiScheduler {
+    pub fn new(settings: SchedulerSettings) -> Self {
+        if settings.requests_per_minute > 0 {
+            tracing::info!(
+                "Gemini scheduler: {} requests/min, burst {}, max queue wait {}ms",
+                settings.requests_per_minute,
+                settings.burst,
+                settings.max_queue_wait.as_millis()
+            );
+        }
+        Self {
+            settings,
+            bucket: Mutex::new(Bucket {
+                tokens: settings.burst as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// No pacing; every `acquire` succeeds immediately
+    pub fn unlimited() -> Self {
+        Self::new(SchedulerSettings::default())
+    }
+
+    /// Wait for a request slot, or fail fast if the queue is too long
+    pub async fn acquire(&self) -> Result<(), SchedulerBusy> {
+        let wait = self.reserve(Instant::now())?;
+        if !wait.is_zero() {
+            tracing::debug!("Gemini request queued for {}ms", wait.as_millis());
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    /// Claim the next slot at `now`, returning how long to wait for it
+    fn reserve(&self, now: Instant) -> Result<Duration, SchedulerBusy> {
+        let SchedulerSettings { requests_per_minute, burst, max_queue_wait } = self.settings;
+        if requests_per_minute == 0 {
+            return Ok(Duration::ZERO);
+        }
+        let per_second = requests_per_minute as f64 / 60.0;
+
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let refill = now.saturating_duration_since(bucket.updated).as_secs_f64() * per_second;
+        bucket.tokens = (bucket.tokens + refill).min(burst as f64);
+        bucket.updated = now;
+
+        let wait = if bucket.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - bucket.tokens) / per_second)
+        };
+        if wait > max_queue_wait {
+            return Err(SchedulerBusy { wait, max_wait: max_queue_wait });
+        }
+
+        bucket.tokens -= 1.0;
+        Ok(wait)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn scheduler(requests_per_minute: u32, burst: u32, max_wait_secs: u64) -> Arc<GeminiScheduler> {
+        Arc::new(GeminiScheduler::new(SchedulerSettings {
+            requests_per_minute,
+            burst,
+            max_queue_wait: Duration::from_secs(max_wait_secs),
+        }))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_callers_are_paced_and_overflow_fails_fast() {
+        // 60/min = one slot per second, two free up front, at most 5s in the queue
+        let scheduler = scheduler(60, 2, 5);
+        let started = Instant::now();
+
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let scheduler = scheduler.clone();
+                tokio::spawn(async move {
+                    scheduler.acquire().await.map(|_| started.elapsed().as_secs())
+                })
+            })
+            .collect();
+
+        let mut admitted = Vec::new();
+        let mut rejected = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(at) => admitted.push(at),
+                Err(busy) => {
+                    assert!(busy.wait > busy.max_wait);
+                    rejected += 1;
+                }
+            }
+        }
+
+        admitted.sort();
+        assert_eq!(admitted, vec![0, 0, 1, 2, 3, 4, 5]);
+        assert_eq!(rejected, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bucket_refills_after_idle_up_to_burst() {
+        let scheduler = scheduler(60, 3, 0);
+        for _ in 0..3 {
+            scheduler.acquire().await.unwrap();
+        }
+        assert!(scheduler.acquire().await.is_err());
+
+        // A long idle period only restores `burst` slots
+        tokio::time::advance(Duration::from_secs(60)).await;
+        for _ in 0..3 {
+            scheduler.acquire().await.unwrap();
+        }
+        assert!(scheduler.acquire().await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unlimited_never_waits() {
+        let scheduler = GeminiScheduler::unlimited();
+        let started = Instant::now();
+        for _ in 0..1000 {
+            scheduler.acquire().await.unwrap();
+        }
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+}
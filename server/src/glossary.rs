@@ -0,0 +1,305 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::redact::pii;
+
+/// Curated lay-term → clinical-term mapping applied around the normalization pass,
+/// so known phrasings map the same way on every request whatever the LLM does.
+///
+/// Files are JSON (`{"blue lips": "cyanosis", ...}`) or two-column CSV
+/// (`lay,clinical`, optional header, `#` comments). Terms match whole words,
+/// case-insensitively.
+#[derive(Debug, Default)]
+pub struct Glossary {
+    /// Normalized lay term → clinical term
+    terms: HashMap<String, String>,
+    /// Normalized lay terms, longest (in words) first, so "muscle weakness in arms"
+    /// wins over "muscle weakness"
+    by_length: Vec<String>,
+}
+
+/// A lay term found in the patient's message
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlossaryMatch {
+    pub lay: String,
+    pub clinical: String,
+}
+
+impl Glossary {
+    pub fn from_pairs<I, S>(pairs: I) -> Self
+    where
+        I: IntoIterator<Item = (S, S)>,
+        S: AsRef<str>,
+    {
+        let terms: HashMap<String, String> = pairs
+            .into_iter()
+            .map(|(lay, clinical)| (normalize(lay.as_ref()), clinical.as_ref().trim().to_string()))
+            .filter(|(lay, clinical)| !lay.is_empty() && !clinical.is_empty())
+            .collect();
+
+        let mut by_length: Vec<String> = terms.keys().cloned().collect();
+        by_length.sort_by(|a, b| b.split(' ').count().cmp(&a.split(' ').count()).then_with(|| a.cmp(b)));
+
+        Self { terms, by_length }
+    }
+
+    /// Parse by extension: `.json`, anything else as CSV
+    pub fn parse(path: &Path, content: &str) -> Result<Self> {
+        let is_json = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        if is_json {
+            let terms: HashMap<String, String> =
+                serde_json::from_str(content).context("Glossary JSON must be an object of lay term to clinical term")?;
+            return Ok(Self::from_pairs(terms));
+        }
+
+        let mut pairs = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (lay, clinical) = line
+                .split_once(',')
+                .with_context(|| format!("Glossary line {} has no comma: {}", index + 1, line))?;
+            let (lay, clinical) = (unquote(lay), unquote(clinical));
+            if index == 0 && lay.eq_ignore_ascii_case("lay") {
+                continue;
+            }
+            pairs.push((lay.to_string(), clinical.to_string()));
+        }
+
+        Ok(Self::from_pairs(pairs))
+    }
+
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Lay terms in `message`, longest first; words already claimed by a longer
+    /// term aren't matched again
+    pub fn find_terms(&self, message: &str) -> Vec<GlossaryMatch> {
+        let words: Vec<String> = normalize(message).split(' ').map(str::to_string).collect();
+        let mut claimed = vec![false; words.len()];
+        let mut matches = Vec::new();
+
+        for lay in &self.by_length {
+            let term: Vec<&str> = lay.split(' ').collect();
+            let Some(start) = (0..=words.len().saturating_sub(term.len())).find(|&start| {
+                words.len() >= term.len()
+                    && words[start..start + term.len()].iter().zip(&term).all(|(w, t)| w == t)
+                    && !claimed[start..start + term.len()].iter().any(|c| *c)
+            }) else {
+                continue;
+            };
+
+            claimed[start..start + term.len()].iter_mut().for_each(|c| *c = true);
+            matches.push(GlossaryMatch { lay: lay.clone(), clinical: self.terms[lay].clone() });
+        }
+
+        matches
+    }
+
+    /// Check the LLM's symptoms against the glossary: lay terms it kept are swapped
+    /// for their clinical term, clinical terms for `matches` it dropped are added,
+    /// and duplicates are removed. Returns the clinical terms that had to be added.
+    pub fn correct_symptoms(&self, symptoms: &mut Vec<String>, matches: &[GlossaryMatch]) -> Vec<String> {
+        for symptom in symptoms.iter_mut() {
+            if let Some(clinical) = self.terms.get(&normalize(symptom)) {
+                tracing::debug!("Glossary: replacing symptom '{}' with '{}'", pii(symptom), pii(clinical));
+                *symptom = clinical.clone();
+            }
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        symptoms.retain(|s| seen.insert(normalize(s)));
+
+        let mut added = Vec::new();
+        for found in matches {
+            if seen.insert(normalize(&found.clinical)) {
+                symptoms.push(found.clinical.clone());
+                added.push(found.clinical.clone());
+            }
+        }
+        added
+    }
+}
+
+/// Lowercase words separated by single spaces (punctuation dropped)
+fn normalize(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn unquote(field: &str) -> &str {
+    let field = field.trim();
+    field
+        .strip_prefix('"')
+        .and_then(|f| f.strip_suffix('"'))
+        .unwrap_or(field)
+}
+
+/// The configured glossary (`GLOSSARY_PATH`, none when unset), reloaded when the
+/// file changes. Changes are noticed on use, at most once per `GLOSSARY_RELOAD_SECS`
+/// (0 = never reload); an edit that fails to parse keeps the previous glossary.
+#[derive(Clone)]
+pub struct GlossaryStore {
+    path: Option<PathBuf>,
+    reload_every: Option<Duration>,
+    state: Arc<Mutex<LoadedGlossary>>,
+}
+
+struct LoadedGlossary {
+    glossary: Arc<Glossary>,
+    modified: Option<SystemTime>,
+    checked_at: Instant,
+}
+
+impl GlossaryStore {
+    /// A bad file at startup is a configuration error; later ones only warn
+    pub fn load(path: Option<PathBuf>, reload_every: Option<Duration>) -> Result<Self> {
+        let (glossary, modified) = match &path {
+            Some(path) => {
+                let (glossary, modified) = read_glossary(path)?;
+                tracing::info!("Loaded {} glossary terms from {:?}", glossary.len(), path);
+                (glossary, modified)
+            }
+            None => (Glossary::default(), None),
+        };
+
+        Ok(Self {
+            path,
+            reload_every,
+            state: Arc::new(Mutex::new(LoadedGlossary {
+                glossary: Arc::new(glossary),
+                modified,
+                checked_at: Instant::now(),
+            })),
+        })
+    }
+
+    pub fn from_env() -> Result<Self> {
+        let path = std::env::var("GLOSSARY_PATH").ok().filter(|p| !p.trim().is_empty()).map(PathBuf::from);
+        let reload_secs = std::env::var("GLOSSARY_RELOAD_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(30);
+
+        Self::load(path, Some(reload_secs).filter(|s| *s > 0).map(Duration::from_secs))
+    }
+
+    /// The glossary to use for one request
+    pub fn current(&self) -> Arc<Glossary> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let (Some(path), Some(every)) = (&self.path, self.reload_every)
+            && state.checked_at.elapsed() >= every
+        {
+            state.checked_at = Instant::now();
+            let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+            if modified != state.modified {
+                match read_glossary(path) {
+                    Ok((glossary, modified)) => {
+                        tracing::info!("Reloaded {} glossary terms from {:?}", glossary.len(), path);
+                        state.glossary = Arc::new(glossary);
+                        state.modified = modified;
+                    }
+                    Err(e) => {
+                        tracing::warn!("Keeping the previous glossary, reload of {:?} failed: {:#}", path, e);
+                        state.modified = modified;
+                    }
+                }
+            }
+        }
+
+        state.glossary.clone()
+    }
+}
+
+fn read_glossary(path: &Path) -> Result<(Glossary, Option<SystemTime>)> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read glossary {:?}", path))?;
+    let glossary = Glossary::parse(path, &content).with_context(|| format!("Invalid glossary {:?}", path))?;
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    Ok((glossary, modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glossary() -> Glossary {
+        Glossary::from_pairs([
+            ("blue lips", "cyanosis"),
+            ("weak arms", "proximal muscle weakness"),
+            ("weak", "asthenia"),
+            ("  Fits ", "seizures"),
+        ])
+    }
+
+    #[test]
+    fn test_find_terms_matches_whole_words_longest_first() {
+        let matches = glossary().find_terms("My son has BLUE lips, weak arms and fits. Bluer lipstick?");
+        let clinical: Vec<&str> = matches.iter().map(|m| m.clinical.as_str()).collect();
+        assert_eq!(clinical, vec!["cyanosis", "proximal muscle weakness", "seizures"]);
+
+        assert!(glossary().find_terms("fitness is fine").is_empty());
+        assert!(Glossary::default().find_terms("blue lips").is_empty());
+    }
+
+    #[test]
+    fn test_correct_symptoms_replaces_adds_and_dedups() {
+        let glossary = glossary();
+        let matches = glossary.find_terms("blue lips and fits");
+        let mut symptoms = vec!["Blue lips".to_string(), "Cyanosis".to_string(), "hypotonia".to_string()];
+
+        let added = glossary.correct_symptoms(&mut symptoms, &matches);
+        assert_eq!(symptoms, vec!["cyanosis", "hypotonia", "seizures"]);
+        assert_eq!(added, vec!["seizures"]);
+    }
+
+    #[test]
+    fn test_parse_json_and_csv() {
+        let json = Glossary::parse(Path::new("g.JSON"), r#"{"blue lips": "cyanosis"}"#).unwrap();
+        assert_eq!(json.find_terms("blue lips")[0].clinical, "cyanosis");
+        assert!(Glossary::parse(Path::new("g.json"), r#"["blue lips"]"#).is_err());
+
+        let csv = "lay,clinical\n# comment\n\"blue lips\", cyanosis\n\nfits,\"seizures, generalized\"\n";
+        let csv = Glossary::parse(Path::new("g.csv"), csv).unwrap();
+        assert_eq!(csv.len(), 2);
+        assert_eq!(csv.find_terms("fits")[0].clinical, "seizures, generalized");
+        assert!(Glossary::parse(Path::new("g.csv"), "no comma here").is_err());
+    }
+
+    #[test]
+    fn test_store_reloads_changed_file_and_keeps_last_good() {
+        let path = std::env::temp_dir().join(format!("glossary-{}.csv", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "blue lips,cyanosis\n").unwrap();
+        let store = GlossaryStore::load(Some(path.clone()), Some(Duration::ZERO)).unwrap();
+        assert_eq!(store.current().len(), 1);
+
+        // Make sure the modification time differs on coarse-grained filesystems
+        let touch = |content: &str, secs: u64| {
+            std::fs::write(&path, content).unwrap();
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(SystemTime::now() + Duration::from_secs(secs)).unwrap();
+        };
+        touch("blue lips,cyanosis\nfits,seizures\n", 10);
+        assert_eq!(store.current().len(), 2);
+
+        touch("broken", 20);
+        assert_eq!(store.current().len(), 2);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(GlossaryStore::load(Some(path), None).is_err());
+        assert!(GlossaryStore::load(None, None).unwrap().current().is_empty());
+    }
+}
@@ -1,5 +1,9 @@
-use axum::Json;
+use axum::{extract::State, http::StatusCode, Json};
 use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::AppState;
 
 #[derive(Serialize)]
 pub struct HealthCheckResponse {
@@ -11,4 +15,167 @@ pub async fn health_check() -> Json<HealthCheckResponse> {
         status: "ok".to_string(),
     };
     Json(response)
-}
\ No newline at end of file
+}
+
+/// Progress of the startup Orphanet load, as reported by `/health/ready`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum KnowledgeBaseState {
+    #[default]
+    NotStarted,
+    /// `total` is 0 until the dataset has been parsed
+    Loading { done: usize, total: usize },
+    Complete,
+    Failed { error: String },
+    /// `SKIP_ORPHANET`: no dataset is loaded and none is searched
+    Disabled,
+}
+
+/// Startup readiness shared between the background startup task and `/health/ready`
+#[derive(Clone, Default)]
+pub struct Readiness {
+    embedding_ready: Arc<AtomicBool>,
+    /// The embedding model failed to load and `EMBEDDINGS_OPTIONAL` let the server
+    /// run on without retrieval
+    embeddings_degraded: Arc<AtomicBool>,
+    knowledge_base: Arc<Mutex<KnowledgeBaseState>>,
+    /// Report ready only once the knowledge base is complete (`READY_REQUIRES_KNOWLEDGE_BASE`)
+    require_knowledge_base: bool,
+}
+
+impl Readiness {
+    pub fn new(require_knowledge_base: bool) -> Self {
+        Self { require_knowledge_base, ..Self::default() }
+    }
+
+    pub fn set_embedding_ready(&self) {
+        self.embedding_ready.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_embedding_ready(&self) -> bool {
+        self.embedding_ready.load(Ordering::SeqCst)
+    }
+
+    pub fn set_embeddings_degraded(&self) {
+        self.embeddings_degraded.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_embeddings_degraded(&self) -> bool {
+        self.embeddings_degraded.load(Ordering::SeqCst)
+    }
+
+    pub fn set_knowledge_base(&self, state: KnowledgeBaseState) {
+        *self.knowledge_base.lock().unwrap_or_else(|e| e.into_inner()) = state;
+    }
+
+    pub fn knowledge_base(&self) -> KnowledgeBaseState {
+        self.knowledge_base.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Orphanet is being loaded into the vector store
+    pub fn is_knowledge_base_loading(&self) -> bool {
+        matches!(self.knowledge_base(), KnowledgeBaseState::Loading { .. })
+    }
+
+    /// Degraded mode is ready by choice: nothing further will load, so waiting
+    /// for the knowledge base would hold the probe at 503 forever
+    pub fn is_ready(&self) -> bool {
+        self.is_embeddings_degraded()
+            || (self.is_embedding_ready()
+                && (!self.require_knowledge_base || self.knowledge_base() == KnowledgeBaseState::Complete))
+    }
+}
+
+#[derive(Serialize)]
+pub struct ReadinessResponse {
+    status: String,
+    embedding_ready: bool,
+    /// Serving without retrieval because the embedding model failed to load
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    degraded: bool,
+    /// Chat works meanwhile, but answers have no disorders to draw on
+    knowledge_base_loading: bool,
+    knowledge_base: KnowledgeBaseState,
+    /// Background file processing load
+    processing: crate::media_ingestion::queue::QueueStats,
+    /// Open SSE chat streams against `MAX_CONCURRENT_CHATS`
+    chats: crate::chat_streams::ChatStreamStats,
+}
+
+/// Readiness probe: 503 until the embedding model can serve requests (and, with
+/// `READY_REQUIRES_KNOWLEDGE_BASE`, until the Orphanet load has completed)
+pub async fn readiness_check(
+    State(state): State<AppState>,
+) -> (StatusCode, Json<ReadinessResponse>) {
+    let readiness = &state.readiness;
+    let (status_code, status) = if readiness.is_embeddings_degraded() {
+        (StatusCode::OK, "degraded")
+    } else if readiness.is_ready() {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "starting")
+    };
+
+    let response = ReadinessResponse {
+        status: status.to_string(),
+        embedding_ready: readiness.is_embedding_ready(),
+        degraded: readiness.is_embeddings_degraded(),
+        knowledge_base_loading: readiness.is_knowledge_base_loading(),
+        knowledge_base: readiness.knowledge_base(),
+        processing: state.processing.stats(),
+        chats: state.chat_streams.stats(),
+    };
+    (status_code, Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_knowledge_base_gate() {
+        let ungated = Readiness::new(false);
+        ungated.set_embedding_ready();
+        ungated.set_knowledge_base(KnowledgeBaseState::Loading { done: 50, total: 200 });
+        assert!(ungated.is_ready());
+        assert!(ungated.is_knowledge_base_loading());
+
+        let gated = Readiness::new(true);
+        gated.set_embedding_ready();
+        assert!(!gated.is_ready());
+        gated.set_knowledge_base(KnowledgeBaseState::Loading { done: 200, total: 200 });
+        assert!(!gated.is_ready());
+        gated.set_knowledge_base(KnowledgeBaseState::Complete);
+        assert!(gated.is_ready());
+        gated.set_knowledge_base(KnowledgeBaseState::Failed { error: "parse error".to_string() });
+        assert!(!gated.is_ready());
+        assert!(!gated.is_knowledge_base_loading());
+    }
+
+    #[test]
+    fn test_degraded_mode_is_ready_without_embeddings() {
+        let readiness = Readiness::new(true);
+        assert!(!readiness.is_ready());
+
+        readiness.set_embeddings_degraded();
+        assert!(readiness.is_ready());
+        assert!(!readiness.is_embedding_ready());
+    }
+
+    #[test]
+    fn test_knowledge_base_state_serialization() {
+        let json = |state: KnowledgeBaseState| serde_json::to_value(state).unwrap();
+
+        assert_eq!(json(KnowledgeBaseState::NotStarted), serde_json::json!({"state": "not_started"}));
+        assert_eq!(
+            json(KnowledgeBaseState::Loading { done: 50, total: 200 }),
+            serde_json::json!({"state": "loading", "done": 50, "total": 200})
+        );
+        assert_eq!(json(KnowledgeBaseState::Complete), serde_json::json!({"state": "complete"}));
+        assert_eq!(json(KnowledgeBaseState::Disabled), serde_json::json!({"state": "disabled"}));
+        assert_eq!(
+            json(KnowledgeBaseState::Failed { error: "boom".to_string() }),
+            serde_json::json!({"state": "failed", "error": "boom"})
+        );
+    }
+}
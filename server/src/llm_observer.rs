@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use crate::redact;
+
+/// An outgoing generation request, as seen by an observer
+#[derive(Debug)]
+pub struct LlmRequest<'a> {
+    /// Pipeline stage making the call ("normalize", "select", "thinking", "answer", ...)
+    pub call: &'a str,
+    pub model: &'a str,
+    /// The `systemInstruction`: standing rules and output format
+    pub system: &'a str,
+    /// The user turn: patient text and retrieved context
+    pub prompt: &'a str,
+}
+
+/// The outcome of a generation request
+#[derive(Debug)]
+pub struct LlmResponse<'a> {
+    pub call: &'a str,
+    pub model: &'a str,
+    pub latency: Duration,
+    /// HTTP status, or `None` when the request never got a response
+    pub status: Option<u16>,
+    /// Extracted text on success, error message on failure
+    pub outcome: Result<&'a str, &'a str>,
+}
+
+/// Hook invoked around every Gemini generation call.
+///
+/// Implementations must be cheap: they run inline on the request path. An
+/// observer that persists to a database should hand the data off (e.g. via
+/// `tokio::spawn` or a channel) rather than awaiting the write.
+pub trait LlmObserver: Send + Sync {
+    fn on_request(&self, request: &LlmRequest<'_>);
+    fn on_response(&self, response: &LlmResponse<'_>);
+}
+
+/// Default observer: one log line per call with sizes and latency.
+/// Prompt and response text are only logged (at debug) when `log_content` is set
+/// and `LOG_REDACT_PII` is off, since prompts carry the patient's text.
+pub struct LoggingObserver {
+    log_content: bool,
+}
+
+impl LoggingObserver {
+    pub fn new(log_content: bool) -> Self {
+        Self { log_content }
+    }
+
+    /// `LLM_LOG_CONTENT=true` also logs full prompts and responses
+    pub fn from_env() -> Self {
+        let log_content = std::env::var("LLM_LOG_CONTENT")
+            .unwrap_or_else(|_| "false".to_string())
+            .to_lowercase() == "true";
+        Self::new(log_content)
+    }
+}
+
+impl LlmObserver for LoggingObserver {
+    fn on_request(&self, request: &LlmRequest<'_>) {
+        tracing::info!(
+            "Gemini {} request ({}, {} system + {} prompt chars)",
+            request.call,
+            request.model,
+            request.system.chars().count(),
+            request.prompt.chars().count()
+        );
+        if self.log_content && !redact::is_enabled() {
+            tracing::debug!("Gemini {} system instruction:\n{}", request.call, request.system);
+            tracing::debug!("Gemini {} prompt:\n{}", request.call, request.prompt);
+        }
+    }
+
+    fn on_response(&self, response: &LlmResponse<'_>) {
+        let status = response
+            .status
+            .map_or_else(|| "no response".to_string(), |s| s.to_string());
+        match response.outcome {
+            Ok(text) => {
+                tracing::info!(
+                    "Gemini {} response {} in {}ms ({} chars)",
+                    response.call,
+                    status,
+                    response.latency.as_millis(),
+                    text.chars().count()
+                );
+                if self.log_content && !redact::is_enabled() {
+                    tracing::debug!("Gemini {} response:\n{}", response.call, text);
+                }
+            }
+            Err(error) => tracing::warn!(
+                "Gemini {} failed ({}) after {}ms: {}",
+                response.call,
+                status,
+                response.latency.as_millis(),
+                error
+            ),
+        }
+    }
+}
@@ -1,6 +1,8 @@
 pub mod health;
 pub mod auth;
 pub mod chat;
+pub mod chat_streams;
+pub mod answer_cache;
 pub mod db;
 pub mod rag;
 pub mod processing;
@@ -8,26 +10,54 @@ pub mod media_ingestion;
 pub mod request_counter;
 pub mod embeddings;
 pub mod orphanet_loader;
+pub mod gemini;
+pub mod gemini_scheduler;
+pub mod answer_parser;
+pub mod llm_observer;
+pub mod config;
+pub mod glossary;
+pub mod storage;
+pub mod redact;
 
 use anyhow::Result;
-use axum::{Router, routing::{get, post}};
+use axum::{Router, extract::DefaultBodyLimit, routing::{get, post, put}};
 use dotenvy::dotenv;
 use tokio::net::TcpListener;
 use std::sync::Arc;
 
-use crate::{chat::chat_handler, health::health_check};
+use crate::{chat::{chat_complete_handler, chat_handler}, health::{health_check, readiness_check}};
 
 #[derive(Clone)]
 pub struct AppState {
+    /// Runtime settings, read once at startup
+    pub config: Arc<config::Config>,
     pub db_pool: sqlx::PgPool,
     pub vector_store: Arc<rag::vector_store::RagVectorStore>,
     pub pdf_processor: Arc<processing::PdfProcessor>,
     pub image_processor: Arc<processing::ImageProcessor>,
-    pub gemini_http_client: reqwest::Client,
-    pub gemini_api_key: Arc<String>,
-    pub gemini_model: Arc<String>,
-    pub embedding_service: Arc<embeddings::LocalEmbeddingService>,
+    pub gemini: gemini::GeminiClient,
+    /// Sees every Gemini generation call (audit/debug hook)
+    pub llm_observer: Arc<dyn llm_observer::LlmObserver>,
+    pub embedding_service: Arc<dyn embeddings::EmbeddingProvider>,
     pub request_counter: request_counter::RequestCounter,
+    pub uploads: media_ingestion::resumable::UploadSessions,
+    /// Original bytes of uploads (`FILE_STORAGE`)
+    pub file_storage: storage::FileStorage,
+    /// Bounded worker pool for background file processing
+    pub processing: media_ingestion::queue::ProcessingQueue,
+    /// Files the pool hasn't finished, handed to the next boot if shutdown can't wait
+    pub in_flight: media_ingestion::recovery::InFlightFiles,
+    /// Lay-to-clinical terms applied around symptom normalization (`GLOSSARY_PATH`)
+    pub glossary: glossary::GlossaryStore,
+    /// Open `/chat` SSE streams, capped at `MAX_CONCURRENT_CHATS`
+    pub chat_streams: chat_streams::ChatStreams,
+    /// Orphanet conditions never returned (`EXCLUDED_ORPHA_CODES`), shared with the vector store
+    pub exclusions: rag::exclusions::ConditionExclusions,
+    /// Disease names in other languages (`ORPHANET_NAME_FILES`)
+    pub localized_names: Arc<processing::localized_names::LocalizedNames>,
+    /// Recent answers replayed for repeated questions (`ANSWER_CACHE`)
+    pub answer_cache: answer_cache::AnswerCache,
+    pub readiness: health::Readiness,
 }
 
 #[tokio::main]
@@ -41,34 +71,43 @@ async fn main() -> Result<()> {
     let database_url = std::env::var("DATABASE_URL")
         .expect("DATABASE_URL not set, Set it in .env file");
 
-    let gemini_api_key = std::env::var("GEMINI_API_KEY")
-        .expect("GEMINI_API_KEY not set, Set it in .env file");
-    let gemini_model = std::env::var("GEMINI_MODEL")
-        .unwrap_or_else(|_| "gemini-2.0-flash".to_string());
+    let config = Arc::new(config::Config::from_env()?);
+    redact::set_enabled(config.log_redact_pii);
+    let gemini = gemini::GeminiClient::from_env()?;
 
     // Initialize PostgreSQL
     tracing::info!("Connecting to PostgreSQL...");
     let db_pool = db::create_pool(&database_url).await?;
     tracing::info!("PostgreSQL connected successfully");
 
-    // Initialize local embedding service
-    tracing::info!("Initializing local embedding service...");
-    let embedding_service = Arc::new(embeddings::LocalEmbeddingService::new()?);
-    tracing::info!("Embedding service ready (dimension: {})", embedding_service.dimension());
+    // Create the embedding provider shared by all content types.
+    // Slow setup (model download/load) happens in the background startup task.
+    let embedding_service = embeddings::provider_from_env(&gemini)?;
+    tracing::info!(
+        "Embedding service created (provider: {}, dimension: {})",
+        embedding_service.name(),
+        embedding_service.dimension()
+    );
 
     // Initialize PostgreSQL vector store with pgvector
     tracing::info!("Initializing PostgreSQL vector store...");
+    let exclusions = rag::exclusions::ConditionExclusions::from_env()?;
     let vector_store = Arc::new(
-        rag::vector_store::RagVectorStore::new(&db_pool, embedding_service.clone()).await?
+        rag::vector_store::RagVectorStore::from_env(&db_pool, embedding_service.clone())
+            .await?
+            .with_exclusions(exclusions.clone())
     );
     let vector_count = vector_store.count().await;
     tracing::info!("Vector store initialized ({} existing documents)", vector_count);
 
-    // Initialize Gemini client settings
-    tracing::info!("Initializing Gemini HTTP client with model {}...", gemini_model);
-    let gemini_http_client = reqwest::Client::new();
+    // Shared Gemini client (one connection pool for the whole server)
+    tracing::info!(
+        "Initializing Gemini client with model {} (embeddings: {})...",
+        gemini.model(),
+        gemini.embedding_model()
+    );
 
-    // Initialize processors with local embeddings
+    // Initialize processors with the selected embedding provider (EMBEDDING_PROVIDER: local or gemini)
     let pdf_processor = Arc::new(processing::PdfProcessor::new(embedding_service.clone())?);
     let image_processor = Arc::new(processing::ImageProcessor::new(embedding_service.clone())?);
 
@@ -78,30 +117,235 @@ async fn main() -> Result<()> {
 
     // Create application state
     let state = AppState {
+        config: config.clone(),
         db_pool,
         vector_store: vector_store.clone(),
         pdf_processor,
         image_processor,
-        gemini_http_client,
-        gemini_api_key: Arc::new(gemini_api_key),
-        gemini_model: Arc::new(gemini_model),
-        embedding_service: embedding_service.clone(),
+        gemini,
+        llm_observer: Arc::new(llm_observer::LoggingObserver::from_env()),
+        embedding_service,
         request_counter,
+        uploads: media_ingestion::resumable::UploadSessions::from_env(),
+        file_storage: storage::FileStorage::from_env(&config.auth)?,
+        processing: media_ingestion::queue::ProcessingQueue::from_env(),
+        in_flight: media_ingestion::recovery::InFlightFiles::from_env(),
+        chat_streams: chat_streams::ChatStreams::new(config.max_concurrent_chats),
+        glossary: glossary::GlossaryStore::from_env()?,
+        exclusions,
+        localized_names: Arc::new(processing::localized_names::LocalizedNames::from_env()?),
+        answer_cache: answer_cache::AnswerCache::new(
+            config.chat.answer_cache_ttl,
+            config.chat.answer_cache_max_entries,
+        ),
+        // Without a startup load there is no progress to wait for
+        readiness: health::Readiness::new(
+            config.orphanet.load && !config.orphanet.dry_run && config.orphanet.gate_readiness,
+        ),
+    };
+
+    // Embedding model + dataset loading run in the background so the server can
+    // answer health/readiness probes while they complete. A fatal failure there
+    // shuts the server down the same way a signal does.
+    let startup_failed = Arc::new(tokio::sync::Notify::new());
+    let startup = tokio::spawn(run_startup_tasks(state.clone(), startup_failed.clone()));
+    if let Some(threshold) = config.stale_processing_after {
+        tokio::spawn(media_ingestion::recovery::heartbeat_in_flight(state.clone(), threshold));
+    }
+
+    // Request body limits (exceeding them yields 413 before any handler logic runs)
+    let upload_body_limit = config.upload_body_limit;
+    let chat_body_limit = config.chat_body_limit;
+    tracing::info!(
+        "Body limits: upload {} bytes, chat {} bytes",
+        upload_body_limit,
+        chat_body_limit
+    );
+
+    // Build router
+    let app = Router::new()
+        .route("/api/health", get(health_check))
+        .route("/api/health/ready", get(readiness_check))
+        .route("/api/protected", get(auth::protected))
+        .route(
+            "/api/chat",
+            post(chat_handler).layer(DefaultBodyLimit::max(chat_body_limit)),
+        )
+        .route(
+            "/api/chat/complete",
+            post(chat_complete_handler).layer(DefaultBodyLimit::max(chat_body_limit)),
+        )
+        .route(
+            "/api/upload",
+            post(media_ingestion::handle_file_upload)
+                .layer(DefaultBodyLimit::max(upload_body_limit)),
+        )
+        .route("/api/upload/capabilities", get(media_ingestion::capabilities::upload_capabilities))
+        .route("/api/files", get(media_ingestion::list_files))
+        .route("/api/files/{id}", get(media_ingestion::file_status))
+        .route("/api/files/{id}/download", get(media_ingestion::download_file))
+        // Resumable uploads: init, send parts (retrying any that fail), then complete
+        .route("/api/uploads/init", post(media_ingestion::resumable::init_upload))
+        .route("/api/uploads/{id}", get(media_ingestion::resumable::upload_status))
+        .route(
+            "/api/uploads/{id}/part/{n}",
+            put(media_ingestion::resumable::upload_part)
+                .layer(DefaultBodyLimit::max(media_ingestion::resumable::MAX_PART_SIZE)),
+        )
+        .route("/api/uploads/{id}/complete", post(media_ingestion::resumable::complete_upload))
+        .route("/api/vector/inspect", post(rag::inspect::inspect_vectors))
+        .route("/admin/embeddings/export", get(rag::export::export_embeddings))
+        .route(
+            "/admin/embeddings/import",
+            post(rag::export::import_embeddings).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/admin/embeddings/similarity-stats", post(rag::inspect::similarity_stats))
+        .route("/admin/exclusions", get(rag::exclusions::list_exclusions))
+        .route("/admin/exclusions/reload", post(rag::exclusions::reload_exclusions))
+        .route("/admin/embeddings/{id}", get(rag::inspect::get_document))
+        .with_state(state.clone());
+
+    let listener = TcpListener::bind("127.0.0.1:3000").await?;
+    tracing::info!("Server listening on {}", listener.local_addr()?);
+    println!("Server running at http://localhost:3000");
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal(startup_failed))
+        .await?;
+
+    // New uploads are refused from here on; let accepted ones finish if they can
+    tracing::info!("Waiting up to {:?} for file processing to finish...", config.shutdown_grace);
+    if !state.processing.drain(config.shutdown_grace).await {
+        match media_ingestion::recovery::interrupt_in_flight(&state).await {
+            Ok(count) => tracing::warn!("Interrupted processing of {} files; they resume on next start", count),
+            Err(e) => tracing::error!("Failed to record interrupted files: {:#}", e),
+        }
+    }
+
+    // Exit non-zero when startup is what ended the server
+    if startup.is_finished() {
+        startup.await??;
+    }
+
+    Ok(())
+}
+
+/// Resolves on Ctrl+C, (on Unix) SIGTERM, or when startup tasks fail fatally
+async fn shutdown_signal(startup_failed: Arc<tokio::sync::Notify>) {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
     };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+        _ = startup_failed.notified() => {
+            tracing::error!("Startup failed, shutting down");
+        }
+    }
+    tracing::info!("Shutdown signal received, no longer accepting connections");
+}
+
+/// Slow startup work that must not block the listener. On a fatal error it
+/// notifies `failed` (so the server drains and stops) and returns the error.
+async fn run_startup_tasks(state: AppState, failed: Arc<tokio::sync::Notify>) -> Result<()> {
+    // A crash loses processing with no spooled bytes to resume from; don't leave it spinning
+    if let Some(threshold) = state.config.stale_processing_after {
+        match media_ingestion::recovery::fail_stale(&state, threshold).await {
+            Ok(0) => {}
+            Ok(count) => tracing::warn!("Marked {} files stuck in processing for over {:?} as failed", count, threshold),
+            Err(e) => tracing::error!("Failed to sweep stale files: {:#}", e),
+        }
+    }
+
+    tracing::info!("Initializing embedding service...");
+    if let Err(e) = state.embedding_service.initialize().await {
+        tracing::error!("Failed to initialize embedding service: {:#}", e);
+        if !state.config.embeddings_optional {
+            failed.notify_one();
+            return Err(e.context("Failed to initialize embedding service"));
+        }
+        // Nothing below can run without embeddings (warmup, Orphanet load)
+        tracing::warn!("EMBEDDINGS_OPTIONAL=true: serving chat without retrieval");
+        state.readiness.set_embeddings_degraded();
+        return Ok(());
+    }
+
+    if state.config.skip_warmup {
+        tracing::info!("Warmup skipped (SKIP_WARMUP=true)");
+    } else if let Err(e) = warmup(&state).await {
+        // A failed warmup only costs first-request latency, so still report ready
+        tracing::warn!("Warmup failed: {:#}", e);
+    }
+
+    state.readiness.set_embedding_ready();
+    tracing::info!(
+        "Embedding service ready (provider: {}, dimension: {})",
+        state.embedding_service.name(),
+        state.embedding_service.dimension()
+    );
+
+    // Files a previous shutdown cut short need embeddings, so resume them only now
+    match media_ingestion::recovery::resume_interrupted(&state).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("Re-queued {} files interrupted by the last shutdown", count),
+        Err(e) => tracing::error!("Failed to resume interrupted files: {:#}", e),
+    }
 
     // Load Orphanet data if enabled
-    let load_orphanet = std::env::var("LOAD_ORPHANET")
-        .unwrap_or_else(|_| "false".to_string())
-        .to_lowercase() == "true";
+    if state.config.orphanet.skip {
+        // Loud on purpose: chat with no disease candidates looks like a data bug otherwise
+        tracing::warn!("SKIP_ORPHANET=true: Orphanet retrieval is DISABLED, chat searches uploaded files only");
+        state.readiness.set_knowledge_base(health::KnowledgeBaseState::Disabled);
+    } else if state.config.orphanet.dry_run {
+        let parsed = orphanet_loader::load_orphanet_data(
+            &state.vector_store,
+            state.embedding_service.as_ref(),
+            &state.readiness,
+            &state.config.orphanet,
+            true,
+        ).await;
 
-    if load_orphanet {
+        match parsed {
+            Ok(count) => tracing::info!("Orphanet dry run: {} disorders would be loaded", count),
+            Err(e) => tracing::error!("Orphanet dry run failed: {:#}", e),
+        }
+    } else if state.config.orphanet.load {
         tracing::info!("Loading Orphanet dataset...");
-        match orphanet_loader::load_orphanet_from_env(&vector_store, &embedding_service).await {
+        let loaded = orphanet_loader::load_orphanet_data(
+            &state.vector_store,
+            state.embedding_service.as_ref(),
+            &state.readiness,
+            &state.config.orphanet,
+            false,
+        ).await;
+
+        match loaded {
             Ok(count) => {
                 tracing::info!("✓ Loaded {} Orphanet disorders", count);
+                state.answer_cache.invalidate();
             }
             Err(e) => {
                 tracing::error!("Failed to load Orphanet data: {}", e);
+                state.readiness.set_knowledge_base(health::KnowledgeBaseState::Failed { error: e.to_string() });
                 tracing::warn!("Continuing without Orphanet data...");
             }
         }
@@ -109,20 +353,17 @@ async fn main() -> Result<()> {
         tracing::info!("Orphanet loading disabled (set LOAD_ORPHANET=true to enable)");
     }
 
-    // Build router
-    let app = Router::new()
-        .route("/api/health", get(health_check))
-        .route("/api/protected", get(auth::protected))
-        .route("/api/chat", post(chat_handler))
-        .route("/api/upload", post(media_ingestion::handle_file_upload))
-        .route("/api/vector/inspect", post(rag::inspect::inspect_vectors))
-        .with_state(state);
+    Ok(())
+}
 
-    let listener = TcpListener::bind("127.0.0.1:3000").await?;
-    tracing::info!("Server listening on {}", listener.local_addr()?);
-    println!("Server running at http://localhost:3000");
+/// Run a throwaway embedding and vector search so the first real request
+/// doesn't pay for model/session initialization and cold query plans
+async fn warmup(state: &AppState) -> Result<()> {
+    let started = std::time::Instant::now();
 
-    axum::serve(listener, app).await?;
+    let embedding = state.embedding_service.embed_text("warmup").await?;
+    state.vector_store.search(embedding, 1).await?;
 
+    tracing::info!("Warmup completed in {}ms", started.elapsed().as_millis());
     Ok(())
 }
@@ -0,0 +1,214 @@
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+use std::io::{Cursor, Read};
+
+use super::validation::{ARCHIVE, FileTypePolicy, MAX_FILE_SIZE, validate_file};
+
+/// Members read from one archive; keeps a zip of tiny files from fanning out
+/// into an unbounded number of processing jobs
+pub const MAX_ARCHIVE_MEMBERS: usize = 100;
+
+/// Uncompressed bytes read from one archive across all members
+pub const MAX_ARCHIVE_TOTAL_SIZE: u64 = 4 * MAX_FILE_SIZE as u64;
+
+/// What to do with zip members that fail validation (`ZIP_INVALID_MEMBERS`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveMemberPolicy {
+    /// Reject the whole upload (default)
+    FailAll,
+    /// Process the valid members and report the rest as skipped
+    SkipBad,
+}
+
+impl ArchiveMemberPolicy {
+    pub fn parse(value: Option<String>) -> Self {
+        match value.as_deref().map(str::to_lowercase).as_deref() {
+            Some("skip") => Self::SkipBad,
+            _ => Self::FailAll,
+        }
+    }
+}
+
+/// A file extracted from an archive that passed `validate_file`
+#[derive(Debug)]
+pub struct ArchiveMember {
+    pub file_name: String,
+    pub content_type: String,
+    /// Internal type assigned by the `FileTypePolicy`
+    pub file_type: String,
+    pub data: Bytes,
+}
+
+/// A member left out under `ArchiveMemberPolicy::SkipBad`
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SkippedMember {
+    pub file_name: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ExtractedArchive {
+    pub members: Vec<ArchiveMember>,
+    pub skipped: Vec<SkippedMember>,
+}
+
+/// Extract and validate every file in a zip against `file_types`. Directories
+/// are ignored, nested archives count as disallowed types, and member names lose
+/// their folder path.
+/// Fails when the archive is unreadable, exceeds the member or size limits, has
+/// an invalid member under `FailAll`, or leaves nothing to process.
+///
+/// Decompression is blocking; async callers run this under `spawn_blocking`.
+pub fn extract_archive(
+    data: &Bytes,
+    policy: ArchiveMemberPolicy,
+    file_types: &FileTypePolicy,
+) -> Result<ExtractedArchive> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data.as_ref())).context("Not a readable zip archive")?;
+    let mut extracted = ExtractedArchive::default();
+    let mut total_size = 0u64;
+    let mut seen = 0;
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).context("Failed to read zip entry")?;
+        if entry.is_dir() {
+            continue;
+        }
+        seen += 1;
+        if seen > MAX_ARCHIVE_MEMBERS {
+            bail!("Archive has more than {} files", MAX_ARCHIVE_MEMBERS);
+        }
+
+        let file_name = entry
+            .enclosed_name()
+            .and_then(|path| path.file_name().map(|name| name.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| format!("member-{}", index + 1));
+
+        let content_type = mime_guess::from_path(&file_name).first_or_octet_stream().to_string();
+        let member = read_member(&mut entry, &file_name, &mut total_size).and_then(|data| {
+            validate_member(file_types, &file_name, &content_type, &data).map(|file_type| (file_type, data))
+        });
+        match member {
+            Ok((file_type, data)) => extracted.members.push(ArchiveMember {
+                file_name,
+                content_type,
+                file_type,
+                data,
+            }),
+            Err(e) if policy == ArchiveMemberPolicy::SkipBad && !is_archive_limit(&e) => {
+                extracted.skipped.push(SkippedMember { file_name, reason: e.to_string() })
+            }
+            Err(e) => bail!("{}: {}", file_name, e),
+        }
+    }
+
+    if extracted.members.is_empty() {
+        bail!("Archive contains no supported files");
+    }
+
+    Ok(extracted)
+}
+
+/// Raised when the archive as a whole is too big, which no policy can skip past
+#[derive(Debug, thiserror::Error)]
+#[error("Archive exceeds the {} MB uncompressed limit", MAX_ARCHIVE_TOTAL_SIZE / (1024 * 1024))]
+struct ArchiveTooLarge;
+
+fn is_archive_limit(error: &anyhow::Error) -> bool {
+    error.is::<ArchiveTooLarge>()
+}
+
+/// Read one member, trusting neither its declared size nor its compression ratio
+fn read_member<R: Read>(entry: &mut R, file_name: &str, total_size: &mut u64) -> Result<Bytes> {
+    let mut data = Vec::new();
+    entry
+        .take(MAX_FILE_SIZE as u64 + 1)
+        .read_to_end(&mut data)
+        .with_context(|| format!("Failed to extract {}", file_name))?;
+
+    *total_size += data.len() as u64;
+    if *total_size > MAX_ARCHIVE_TOTAL_SIZE {
+        return Err(ArchiveTooLarge.into());
+    }
+
+    Ok(Bytes::from(data))
+}
+
+fn validate_member(file_types: &FileTypePolicy, file_name: &str, content_type: &str, data: &Bytes) -> Result<String> {
+    let file_type = validate_file(file_types, file_name, content_type, data)?;
+    if file_type == ARCHIVE {
+        bail!("Nested archives are not supported");
+    }
+    Ok(file_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn zip_of(files: &[(&str, &[u8])]) -> Bytes {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            if name.ends_with('/') {
+                writer.add_directory(*name, SimpleFileOptions::default()).unwrap();
+            } else {
+                writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+                writer.write_all(data).unwrap();
+            }
+        }
+        Bytes::from(writer.finish().unwrap().into_inner())
+    }
+
+    #[test]
+    fn test_extracts_valid_members_with_guessed_types() {
+        let data = zip_of(&[("scans/", b""), ("scans/mri.png", b"\x89PNG\r\n\x1a\n"), ("report.PDF", b"%PDF")]);
+        let extracted = extract_archive(&data, ArchiveMemberPolicy::FailAll, &FileTypePolicy::default()).unwrap();
+
+        let members: Vec<(&str, &str, &str)> = extracted
+            .members
+            .iter()
+            .map(|m| (m.file_name.as_str(), m.content_type.as_str(), m.file_type.as_str()))
+            .collect();
+        assert_eq!(members, vec![("mri.png", "image/png", "image"), ("report.PDF", "application/pdf", "pdf")]);
+        assert!(extracted.skipped.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_member_fails_all_or_is_skipped() {
+        let data = zip_of(&[
+            ("report.pdf", b"%PDF"),
+            ("notes.exe", b"MZ"),
+            ("empty.png", b""),
+            ("inner.zip", b"PK\x03\x04"),
+            ("fake.png", b"%PDF"),
+        ]);
+
+        let error = extract_archive(&data, ArchiveMemberPolicy::FailAll, &FileTypePolicy::default()).unwrap_err();
+        assert!(error.to_string().starts_with("notes.exe:"), "{}", error);
+
+        let extracted = extract_archive(&data, ArchiveMemberPolicy::SkipBad, &FileTypePolicy::default()).unwrap();
+        assert_eq!(extracted.members.len(), 1);
+        let skipped: Vec<&str> = extracted.skipped.iter().map(|s| s.file_name.as_str()).collect();
+        assert_eq!(skipped, vec!["notes.exe", "empty.png", "inner.zip", "fake.png"]);
+    }
+
+    #[test]
+    fn test_rejects_unusable_archives() {
+        let only_bad = zip_of(&[("notes.txt", b"hello")]);
+        assert!(extract_archive(&only_bad, ArchiveMemberPolicy::SkipBad, &FileTypePolicy::default()).is_err());
+        assert!(extract_archive(&Bytes::from_static(b"not a zip"), ArchiveMemberPolicy::SkipBad, &FileTypePolicy::default()).is_err());
+
+        let names: Vec<String> = (0..=MAX_ARCHIVE_MEMBERS).map(|i| format!("{}.pdf", i)).collect();
+        let files: Vec<(&str, &[u8])> = names.iter().map(|n| (n.as_str(), b"%PDF".as_slice())).collect();
+        assert!(extract_archive(&zip_of(&files), ArchiveMemberPolicy::SkipBad, &FileTypePolicy::default()).is_err());
+    }
+
+    #[test]
+    fn test_policy_parse() {
+        assert_eq!(ArchiveMemberPolicy::parse(Some("SKIP".to_string())), ArchiveMemberPolicy::SkipBad);
+        assert_eq!(ArchiveMemberPolicy::parse(Some("fail".to_string())), ArchiveMemberPolicy::FailAll);
+        assert_eq!(ArchiveMemberPolicy::parse(None), ArchiveMemberPolicy::FailAll);
+    }
+}
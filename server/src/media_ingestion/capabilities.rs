@@ -0,0 +1,122 @@
+use axum::{extract::State, response::Json};
+use serde::Serialize;
+
+use crate::AppState;
+use crate::processing::pdf::PdfExtractor;
+use super::archive::MAX_ARCHIVE_TOTAL_SIZE;
+use super::resumable::MAX_PART_SIZE;
+use super::upload::PROCESSED_FILE_TYPES;
+use super::validation::{ARCHIVE, FileTypePolicy, MAX_FILE_SIZE};
+
+/// What clients may upload, read from the same policy and limits the
+/// validator enforces
+#[derive(Debug, Serialize)]
+pub struct UploadCapabilities {
+    pub max_file_size_bytes: usize,
+    /// Uncompressed total across the members of one zip upload
+    pub max_archive_total_bytes: u64,
+    /// Largest `POST /api/upload` body (`UPLOAD_BODY_LIMIT_BYTES`); use resumable uploads above it
+    pub upload_body_limit_bytes: usize,
+    /// Largest part accepted by `PUT /api/uploads/{id}/part/{n}`
+    pub max_part_size_bytes: usize,
+    pub file_types: Vec<FileTypeCapability>,
+    pub processors: ProcessorCapabilities,
+}
+
+/// One accepted format (a `FileTypeRule`)
+#[derive(Debug, Serialize, PartialEq)]
+pub struct FileTypeCapability {
+    pub file_type: String,
+    pub extensions: Vec<String>,
+    pub mime_types: Vec<String>,
+    /// Made searchable after upload; false for types that are only stored
+    pub processed: bool,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ProcessorCapabilities {
+    /// Text extractors tried in order for PDFs (`PDF_EXTRACTORS`)
+    pub pdf_extractors: Vec<&'static str>,
+    /// Scanned PDFs can be read (no OCR backend exists yet)
+    pub ocr: bool,
+    /// Images are described by Gemini Vision
+    pub vision: bool,
+    pub dicom: bool,
+    pub archives: bool,
+}
+
+impl UploadCapabilities {
+    pub fn new(policy: &FileTypePolicy, upload_body_limit: usize, pdf_extractors: &[PdfExtractor]) -> Self {
+        let file_types: Vec<FileTypeCapability> = policy
+            .rules()
+            .iter()
+            .map(|rule| FileTypeCapability {
+                file_type: rule.file_type.clone(),
+                extensions: rule.extensions.clone(),
+                mime_types: rule.mime_types.clone(),
+                processed: rule.file_type == ARCHIVE || PROCESSED_FILE_TYPES.contains(&rule.file_type.as_str()),
+            })
+            .collect();
+        let accepts = |file_type: &str| file_types.iter().any(|t| t.file_type == file_type && t.processed);
+
+        let processors = ProcessorCapabilities {
+            pdf_extractors: pdf_extractors.iter().map(|e| e.as_str()).collect(),
+            ocr: false,
+            vision: accepts("image"),
+            dicom: accepts("dicom"),
+            archives: accepts(ARCHIVE),
+        };
+
+        Self {
+            max_file_size_bytes: MAX_FILE_SIZE,
+            max_archive_total_bytes: MAX_ARCHIVE_TOTAL_SIZE,
+            upload_body_limit_bytes: upload_body_limit,
+            max_part_size_bytes: MAX_PART_SIZE,
+            file_types,
+            processors,
+        }
+    }
+}
+
+/// `GET /api/upload/capabilities`: accepted file types and size limits, for
+/// upload UIs and client-side validation
+pub async fn upload_capabilities(State(state): State<AppState>) -> Json<UploadCapabilities> {
+    Json(UploadCapabilities::new(
+        &state.config.file_types,
+        state.config.upload_body_limit,
+        state.pdf_processor.extractors(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media_ingestion::validation::FileTypeRule;
+
+    #[test]
+    fn test_capabilities_follow_the_policy() {
+        let capabilities = UploadCapabilities::new(&FileTypePolicy::default(), 1024, &[PdfExtractor::Lopdf]);
+
+        assert_eq!(capabilities.max_file_size_bytes, MAX_FILE_SIZE);
+        assert_eq!(capabilities.upload_body_limit_bytes, 1024);
+        let types: Vec<(&str, bool)> = capabilities.file_types
+            .iter()
+            .map(|t| (t.file_type.as_str(), t.processed))
+            .collect();
+        assert_eq!(types, vec![("pdf", true), ("image", true), ("image", true), ("dicom", false), (ARCHIVE, true)]);
+        assert_eq!(capabilities.file_types[0].mime_types, vec!["application/pdf"]);
+        assert_eq!(
+            capabilities.processors,
+            ProcessorCapabilities { pdf_extractors: vec!["lopdf"], ocr: false, vision: true, dicom: false, archives: true }
+        );
+
+        let pdf_only: Vec<FileTypeRule> = serde_json::from_str(
+            r#"[{"file_type": "pdf", "extensions": ["pdf"], "mime_types": ["application/pdf"]}]"#,
+        )
+        .unwrap();
+        let capabilities = UploadCapabilities::new(&FileTypePolicy::new(pdf_only).unwrap(), 1024, &[PdfExtractor::Lopdf]);
+        assert_eq!(capabilities.file_types.len(), 1);
+        assert!(!capabilities.processors.vision);
+        assert!(!capabilities.processors.archives);
+    }
+}
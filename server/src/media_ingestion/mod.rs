@@ -1,5 +1,11 @@
 pub mod upload;
+pub mod archive;
+pub mod resumable;
+pub mod queue;
+pub mod recovery;
 pub mod validation;
+pub mod capabilities;
+pub mod quota;
 
 pub use upload::*;
 pub use validation::*;
@@ -0,0 +1,206 @@
+use serde::Serialize;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// Bounded pool for background file processing. At most `concurrency` jobs run
+/// at once (`PROCESSING_CONCURRENCY`) and at most `max_queued` wait behind them
+/// (`PROCESSING_QUEUE_LIMIT`, 0 = never wait); further uploads are refused until
+/// the queue drains.
+#[derive(Clone)]
+pub struct ProcessingQueue {
+    permits: Arc<Semaphore>,
+    concurrency: usize,
+    max_queued: usize,
+    /// Reserved, waiting or running jobs
+    in_flight: Arc<AtomicUsize>,
+    active: Arc<AtomicUsize>,
+}
+
+/// Queue depth, reported by the readiness probe
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct QueueStats {
+    pub active: usize,
+    pub queued: usize,
+    pub concurrency: usize,
+    pub max_queued: usize,
+}
+
+/// Every worker is busy and the wait queue is full
+#[derive(Debug, thiserror::Error)]
+#[error("File processing queue is full ({max_queued} waiting), retry later")]
+pub struct QueueFull {
+    pub max_queued: usize,
+}
+
+impl ProcessingQueue {
+    pub fn new(concurrency: usize, max_queued: usize) -> Self {
+        let concurrency = concurrency.max(1);
+        Self {
+            permits: Arc::new(Semaphore::new(concurrency)),
+            concurrency,
+            max_queued,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let concurrency = std::env::var("PROCESSING_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(2);
+        let max_queued = std::env::var("PROCESSING_QUEUE_LIMIT")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(32);
+
+        tracing::info!("File processing: {} workers, up to {} queued", concurrency, max_queued);
+        Self::new(concurrency, max_queued)
+    }
+
+    /// Claim a place in the queue before doing any work for the upload, so a
+    /// full queue is refused up front. Dropping the ticket unused releases it.
+    pub fn reserve(&self) -> Result<QueueTicket, QueueFull> {
+        let capacity = self.concurrency + self.max_queued;
+        let ahead = self
+            .in_flight
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < capacity).then_some(n + 1))
+            .map_err(|_| QueueFull { max_queued: self.max_queued })?;
+
+        Ok(QueueTicket { queue: Some(self.clone()), ahead })
+    }
+
+    /// Wait up to `timeout` for every reserved, queued and running job to finish.
+    /// Returns `false` if some were still in flight when time ran out.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, async {
+            while self.in_flight.load(Ordering::SeqCst) > 0 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let active = self.active.load(Ordering::SeqCst);
+        QueueStats {
+            active,
+            queued: self.in_flight.load(Ordering::SeqCst).saturating_sub(active),
+            concurrency: self.concurrency,
+            max_queued: self.max_queued,
+        }
+    }
+}
+
+/// A reserved queue place (see `ProcessingQueue::reserve`)
+pub struct QueueTicket {
+    queue: Option<ProcessingQueue>,
+    /// Jobs already in flight when this one was reserved
+    ahead: usize,
+}
+
+impl QueueTicket {
+    /// Whether the job will have to wait for a worker
+    pub fn will_wait(&self) -> bool {
+        self.queue.as_ref().is_some_and(|q| self.ahead >= q.concurrency)
+    }
+
+    /// Run `job` on the pool once a worker is free
+    pub fn spawn<F>(mut self, job: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let queue = self.queue.take().expect("ticket spawned twice");
+
+        tokio::spawn(async move {
+            let _in_flight = CountGuard(queue.in_flight.clone());
+            let Ok(_permit) = queue.permits.clone().acquire_owned().await else {
+                return;
+            };
+            queue.active.fetch_add(1, Ordering::SeqCst);
+            let _active = CountGuard(queue.active.clone());
+
+            job.await;
+        });
+    }
+}
+
+impl Drop for QueueTicket {
+    fn drop(&mut self) {
+        if let Some(queue) = &self.queue {
+            queue.in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Decrements a counter when the job ends, even if it panics
+struct CountGuard(Arc<AtomicUsize>);
+
+impl Drop for CountGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn test_queue_bounds_concurrency_and_rejects_when_full() {
+        let queue = ProcessingQueue::new(1, 2);
+        let (release_tx, release_rx) = oneshot::channel::<()>();
+        let (started_tx, started_rx) = oneshot::channel::<()>();
+
+        // First job occupies the only worker until released
+        queue.reserve().unwrap().spawn(async move {
+            let _ = started_tx.send(());
+            let _ = release_rx.await;
+        });
+        started_rx.await.unwrap();
+
+        let second = queue.reserve().unwrap();
+        assert!(second.will_wait());
+        assert_eq!(queue.stats().queued, 1);
+        let (done_tx, done_rx) = oneshot::channel::<()>();
+        second.spawn(async move {
+            let _ = done_tx.send(());
+        });
+        let third = queue.reserve().unwrap();
+
+        assert!(queue.reserve().is_err());
+        assert_eq!(queue.stats(), QueueStats { active: 1, queued: 2, concurrency: 1, max_queued: 2 });
+
+        // An abandoned reservation gives its place back
+        drop(third);
+        assert_eq!(queue.stats().queued, 1);
+
+        release_tx.send(()).unwrap();
+        done_rx.await.unwrap();
+        while queue.stats().active > 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(queue.stats().queued, 0);
+        assert!(!queue.reserve().unwrap().will_wait());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drain_waits_for_jobs_up_to_timeout() {
+        let queue = ProcessingQueue::new(1, 1);
+        let (release_tx, release_rx) = oneshot::channel::<()>();
+        queue.reserve().unwrap().spawn(async move {
+            let _ = release_rx.await;
+        });
+
+        assert!(!queue.drain(Duration::from_secs(5)).await);
+
+        release_tx.send(()).unwrap();
+        assert!(queue.drain(Duration::from_secs(5)).await);
+        assert_eq!(queue.stats().queued, 0);
+    }
+}
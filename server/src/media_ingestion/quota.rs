@@ -0,0 +1,154 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::auth::AppwriteClaims;
+use crate::config::{Lookup, parse_or};
+
+/// Limits on what one user may keep uploaded; 0 means unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StorageQuota {
+    pub max_files: u64,
+    pub max_bytes: u64,
+}
+
+/// What a user currently has uploaded, failed files included
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StorageUsage {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl StorageQuota {
+    /// Refuse an upload of `files` files totalling `bytes` that would take `usage`
+    /// past either limit, with a message saying which one
+    pub fn check(&self, usage: StorageUsage, files: u64, bytes: u64) -> Result<(), String> {
+        if self.max_files > 0 && usage.files + files > self.max_files {
+            return Err(format!(
+                "Upload quota exceeded: {} of {} files used, this upload adds {}.",
+                usage.files, self.max_files, files
+            ));
+        }
+        if self.max_bytes > 0 && usage.bytes + bytes > self.max_bytes {
+            return Err(format!(
+                "Upload quota exceeded: {} of {} bytes used, this upload adds {}.",
+                usage.bytes, self.max_bytes, bytes
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `create_uploaded_file` refused a row that would take its owner past their quota
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct QuotaExceeded(pub String);
+
+/// Per-user upload limits (`UPLOAD_QUOTA_FILES`, `UPLOAD_QUOTA_BYTES`), with
+/// overrides for users carrying an Appwrite label named after a plan
+/// (`UPLOAD_QUOTA_PLANS`). Service callers are trusted and never limited.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QuotaPolicy {
+    pub default: StorageQuota,
+    /// `(label, quota)` in configured order; the first label the user has wins
+    pub plans: Vec<(String, StorageQuota)>,
+}
+
+impl QuotaPolicy {
+    pub fn from_lookup(lookup: Lookup<'_>) -> Result<Self> {
+        let default = StorageQuota {
+            max_files: parse_or(lookup, "UPLOAD_QUOTA_FILES", 0),
+            max_bytes: parse_or(lookup, "UPLOAD_QUOTA_BYTES", 0),
+        };
+        let plans = lookup("UPLOAD_QUOTA_PLANS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(parse_plan)
+            .collect::<Result<_>>()?;
+        Ok(Self { default, plans })
+    }
+
+    /// The quota for `claims`, or `None` when nothing limits them
+    pub fn for_claims(&self, claims: &AppwriteClaims) -> Option<StorageQuota> {
+        if claims.is_service {
+            return None;
+        }
+        let quota = self
+            .plans
+            .iter()
+            .find(|(plan, _)| claims.labels.iter().any(|label| label.eq_ignore_ascii_case(plan)))
+            .map_or(self.default, |(_, quota)| *quota);
+        (quota != StorageQuota::default()).then_some(quota)
+    }
+}
+
+/// "pro=2000:10737418240": plan label, then file and byte limits (0 = unlimited)
+fn parse_plan(entry: &str) -> Result<(String, StorageQuota)> {
+    let (plan, limits) = entry
+        .split_once('=')
+        .with_context(|| format!("UPLOAD_QUOTA_PLANS entry '{}' must look like plan=files:bytes", entry))?;
+    let (files, bytes) = limits
+        .split_once(':')
+        .with_context(|| format!("UPLOAD_QUOTA_PLANS entry '{}' must look like plan=files:bytes", entry))?;
+    let limit = |value: &str| {
+        value
+            .trim()
+            .parse::<u64>()
+            .with_context(|| format!("UPLOAD_QUOTA_PLANS entry '{}' has an invalid limit '{}'", entry, value))
+    };
+    Ok((plan.trim().to_string(), StorageQuota { max_files: limit(files)?, max_bytes: limit(bytes)? }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(labels: &[&str], is_service: bool) -> AppwriteClaims {
+        AppwriteClaims {
+            user_id: "u1".to_string(),
+            email: None,
+            name: None,
+            is_service,
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_policy_picks_plan_by_label() {
+        let policy = QuotaPolicy::from_lookup(&|name| match name {
+            "UPLOAD_QUOTA_FILES" => Some("100".to_string()),
+            "UPLOAD_QUOTA_BYTES" => Some("1000".to_string()),
+            "UPLOAD_QUOTA_PLANS" => Some("pro=500:0, staff=0:0".to_string()),
+            _ => None,
+        })
+        .unwrap();
+
+        let default = StorageQuota { max_files: 100, max_bytes: 1000 };
+        assert_eq!(policy.for_claims(&claims(&[], false)), Some(default));
+        assert_eq!(
+            policy.for_claims(&claims(&["beta", "Pro"], false)),
+            Some(StorageQuota { max_files: 500, max_bytes: 0 })
+        );
+        assert_eq!(policy.for_claims(&claims(&["staff"], false)), None);
+        assert_eq!(policy.for_claims(&claims(&[], true)), None);
+
+        assert!(QuotaPolicy::from_lookup(&|_| None).unwrap().for_claims(&claims(&[], false)).is_none());
+        assert!(QuotaPolicy::from_lookup(&|_| Some("pro=many:0".to_string())).is_err());
+        assert!(QuotaPolicy::from_lookup(&|_| Some("pro".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_check_names_the_exceeded_limit() {
+        let quota = StorageQuota { max_files: 10, max_bytes: 1000 };
+        let usage = StorageUsage { files: 9, bytes: 900 };
+
+        assert!(quota.check(usage, 1, 100).is_ok());
+        assert_eq!(
+            quota.check(usage, 2, 0).unwrap_err(),
+            "Upload quota exceeded: 9 of 10 files used, this upload adds 2."
+        );
+        assert!(quota.check(usage, 1, 101).unwrap_err().contains("900 of 1000 bytes"));
+        assert!(StorageQuota { max_files: 0, max_bytes: 0 }.check(usage, 100, 1 << 40).is_ok());
+    }
+}
@@ -0,0 +1,314 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::AppState;
+use super::upload::PendingFile;
+
+/// Status of files whose processing a shutdown cut short
+pub const INTERRUPTED: &str = "interrupted";
+
+/// Uploads recorded by this process that haven't finished processing, with their
+/// bytes, so a shutdown that can't wait for them can hand them to the next boot.
+///
+/// Interrupted bytes are written to `PROCESSING_SPOOL_DIR` (default: a `quwa-interrupted`
+/// directory under the system temp dir), created readable by this user only since
+/// the files are patient documents. The startup sweep re-queues the files it finds
+/// there, fails the rest and deletes whatever is left over.
+#[derive(Clone)]
+pub struct InFlightFiles {
+    files: Arc<Mutex<HashMap<Uuid, PendingFile>>>,
+    spool_dir: PathBuf,
+}
+
+impl InFlightFiles {
+    pub fn new(spool_dir: PathBuf) -> Self {
+        Self { files: Arc::new(Mutex::new(HashMap::new())), spool_dir }
+    }
+
+    pub fn from_env() -> Self {
+        let dir = std::env::var("PROCESSING_SPOOL_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| std::env::temp_dir().join("quwa-interrupted"));
+
+        Self::new(dir)
+    }
+
+    pub(crate) fn track(&self, file: &PendingFile) {
+        self.files.lock().unwrap().insert(file.file_id, file.clone());
+    }
+
+    pub(crate) fn finish(&self, file_id: Uuid) {
+        self.files.lock().unwrap().remove(&file_id);
+    }
+
+    fn snapshot(&self) -> Vec<PendingFile> {
+        self.files.lock().unwrap().values().cloned().collect()
+    }
+
+    fn spool_path(&self, file_id: Uuid) -> PathBuf {
+        spool_path(&self.spool_dir, file_id)
+    }
+}
+
+fn spool_path(dir: &Path, file_id: Uuid) -> PathBuf {
+    dir.join(format!("{}.bin", file_id))
+}
+
+/// Spool and flag every file still in flight at shutdown. Returns how many were
+/// marked interrupted; files that finish meanwhile keep their final status.
+pub async fn interrupt_in_flight(state: &AppState) -> Result<u64> {
+    let files = state.in_flight.snapshot();
+    if files.is_empty() {
+        return Ok(0);
+    }
+
+    create_private_dir(&state.in_flight.spool_dir).await?;
+
+    let mut ids = Vec::with_capacity(files.len());
+    for file in files {
+        let path = state.in_flight.spool_path(file.file_id);
+        // Unspooled files are still flagged; the sweep fails them instead of re-queueing
+        if let Err(e) = tokio::fs::write(&path, &file.data).await {
+            tracing::warn!("Failed to spool interrupted file {} to {:?}: {}", file.file_id, path, e);
+        }
+        ids.push(file.file_id);
+    }
+
+    // Files that finished while being spooled keep their status and need no copy
+    let interrupted = crate::db::queries::mark_files_interrupted(&state.db_pool, &ids).await?;
+    for id in ids.iter().filter(|id| !interrupted.contains(id)) {
+        let _ = tokio::fs::remove_file(state.in_flight.spool_path(*id)).await;
+    }
+
+    Ok(interrupted.len() as u64)
+}
+
+/// Create `dir` (and parents) with the spooled files kept private to this user
+async fn create_private_dir(dir: &Path) -> Result<()> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create spool dir {:?}", dir))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+            .await
+            .with_context(|| format!("Failed to restrict spool dir {:?}", dir))?;
+    }
+    Ok(())
+}
+
+/// Delete spooled bytes no interrupted file will read again, e.g. after a crash
+/// between spooling and marking
+async fn remove_stale_spool_files(dir: &Path, keep: &std::collections::HashSet<Uuid>) -> Result<usize> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read spool dir {:?}", dir)),
+    };
+
+    let mut removed = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let spooled_id = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".bin"))
+            .and_then(|id| id.parse::<Uuid>().ok());
+        if spooled_id.is_some_and(|id| !keep.contains(&id)) && tokio::fs::remove_file(&path).await.is_ok() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Startup sweep: re-queue interrupted files whose bytes were spooled, discarding
+/// any partial output from the cut-short run, and fail those that can't be
+/// recovered. Stops early (leaving the rest for the next boot) if the queue fills.
+pub async fn resume_interrupted(state: &AppState) -> Result<usize> {
+    let files = crate::db::queries::get_files_by_status(&state.db_pool, INTERRUPTED).await?;
+    let mut resumed = 0;
+
+    // Only files still waiting for the next boot keep their spooled bytes
+    let waiting = files.iter().map(|f| f.id).collect();
+    let removed = remove_stale_spool_files(&state.in_flight.spool_dir, &waiting).await?;
+    if removed > 0 {
+        tracing::info!("Deleted {} leftover spooled files", removed);
+    }
+
+    for file in files {
+        let path = state.in_flight.spool_path(file.id);
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => bytes::Bytes::from(data),
+            Err(e) => {
+                tracing::warn!("Interrupted file {} has no spooled bytes ({}), marking failed", file.id, e);
+                crate::db::queries::update_file_status(
+                    &state.db_pool,
+                    file.id,
+                    "failed",
+                    Some("Processing was interrupted by a server restart; please upload the file again"),
+                )
+                .await?;
+                continue;
+            }
+        };
+
+        let Ok(ticket) = state.processing.reserve() else {
+            tracing::warn!("Processing queue full, leaving remaining interrupted files for the next start");
+            break;
+        };
+
+        state.vector_store.delete_by_source("user_file", &file.id.to_string()).await?;
+        crate::db::queries::delete_embedding_metadata(&state.db_pool, file.id).await?;
+        crate::db::queries::update_file_status(&state.db_pool, file.id, "pending", None).await?;
+
+        let pending = PendingFile { file_id: file.id, file_type: file.file_type, data };
+        state.in_flight.track(&pending);
+        ticket.spawn(pending.process(state.clone()));
+        let _ = tokio::fs::remove_file(&path).await;
+        resumed += 1;
+    }
+
+    Ok(resumed)
+}
+
+/// Keep the heartbeat of every file in flight here fresh, every `threshold / 4`,
+/// so another instance's `fail_stale` leaves them alone however long they take
+pub async fn heartbeat_in_flight(state: AppState, threshold: Duration) {
+    let mut interval = tokio::time::interval(threshold / 4);
+    loop {
+        interval.tick().await;
+        let ids: Vec<Uuid> = state.in_flight.snapshot().iter().map(|f| f.file_id).collect();
+        if ids.is_empty() {
+            continue;
+        }
+        if let Err(e) = crate::db::queries::touch_file_heartbeats(&state.db_pool, &ids).await {
+            tracing::warn!("Failed to refresh heartbeat of {} in-flight files: {:#}", ids.len(), e);
+        }
+    }
+}
+
+/// Startup sweep for files a crash left `pending` or `processing`: their bytes
+/// were only in memory, so they are failed rather than retried. Only files whose
+/// last heartbeat (or status change) is older than `threshold` count, which
+/// spares files another live instance is still working on.
+pub async fn fail_stale(state: &AppState, threshold: Duration) -> Result<u64> {
+    crate::db::queries::fail_stale_files(
+        &state.db_pool,
+        threshold,
+        "Processing stopped unexpectedly (server restart); please upload the file again",
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracked_files_until_finished() {
+        let in_flight = InFlightFiles::new(std::env::temp_dir());
+        let file = PendingFile {
+            file_id: Uuid::new_v4(),
+            file_type: "pdf".to_string(),
+            data: bytes::Bytes::from_static(b"%PDF"),
+        };
+
+        in_flight.track(&file);
+        let snapshot = in_flight.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].data, file.data);
+        assert_eq!(in_flight.spool_path(file.file_id).file_name().unwrap().to_str().unwrap(), format!("{}.bin", file.file_id));
+
+        in_flight.finish(file.file_id);
+        assert!(in_flight.snapshot().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_spool_dir_is_private_and_swept() {
+        let dir = std::env::temp_dir().join(format!("quwa-spool-test-{}", Uuid::new_v4()));
+        create_private_dir(&dir).await.unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        let (waiting, done) = (Uuid::new_v4(), Uuid::new_v4());
+        for id in [waiting, done] {
+            std::fs::write(spool_path(&dir, id), b"%PDF").unwrap();
+        }
+        std::fs::write(dir.join("notes.txt"), b"kept").unwrap();
+
+        let removed = remove_stale_spool_files(&dir, &[waiting].into_iter().collect()).await.unwrap();
+        assert_eq!(removed, 1);
+        assert!(spool_path(&dir, waiting).exists());
+        assert!(!spool_path(&dir, done).exists());
+        assert!(dir.join("notes.txt").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(remove_stale_spool_files(&dir, &Default::default()).await.unwrap(), 0);
+    }
+
+    /// Needs DATABASE_URL with migrations applied:
+    /// `cargo test test_fail_stale_files_spares_recent -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_fail_stale_files_spares_recent() {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL not set");
+        let pool = crate::db::create_pool(&database_url).await.unwrap();
+
+        let appwrite_id = format!("stale-test-{}", Uuid::new_v4());
+        let user = crate::db::queries::get_or_create_user(&pool, &appwrite_id, None, None).await.unwrap();
+
+        let mut ids = Vec::new();
+        for (status, age_secs) in [("processing", 7200), ("pending", 7200), ("processing", 60), ("completed", 7200)] {
+            let file = crate::db::models::UploadedFile {
+                id: Uuid::new_v4(),
+                user_id: user.id,
+                file_name: format!("{}.pdf", status),
+                file_type: "pdf".to_string(),
+                mime_type: None,
+                file_size_bytes: None,
+                appwrite_file_id: "test".to_string(),
+                appwrite_bucket_id: "test".to_string(),
+                processing_status: status.to_string(),
+                upload_date: chrono::Utc::now(),
+                processed_at: None,
+                error_message: None,
+                content_hash: None,
+                text_preview: None,
+                document_set_id: None,
+                text_extractor: None,
+                storage_backend: None,
+            };
+            crate::db::queries::create_uploaded_file(&pool, &file, None, false).await.unwrap();
+            sqlx::query("UPDATE uploaded_files SET processing_status = $1, upload_date = NOW() - make_interval(secs => $2) WHERE id = $3")
+                .bind(status)
+                .bind(age_secs as f64)
+                .bind(file.id)
+                .execute(&pool)
+                .await
+                .unwrap();
+            ids.push(file.id);
+        }
+
+        let failed = crate::db::queries::fail_stale_files(&pool, Duration::from_secs(3600), "stale").await.unwrap();
+        assert!(failed >= 2);
+
+        let mut statuses = Vec::new();
+        for id in &ids {
+            let file = crate::db::queries::get_file_by_id(&pool, *id).await.unwrap().unwrap();
+            statuses.push(file.processing_status);
+        }
+        assert_eq!(statuses, vec!["failed", "failed", "processing", "completed"]);
+
+        sqlx::query("DELETE FROM users WHERE id = $1").bind(user.id).execute(&pool).await.unwrap();
+    }
+}
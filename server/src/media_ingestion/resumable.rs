@@ -0,0 +1,516 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::{AppState, auth::AppwriteClaims};
+use super::upload::{UploadResponse, check_quota, ingest_file, reserve_processing};
+use super::validation::{FileTypePolicy, MAX_FILE_SIZE};
+
+/// Largest accepted part; also the body limit of the part route
+pub const MAX_PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Part numbers run from 1 to this (inclusive)
+const MAX_PARTS: u32 = 10_000;
+
+/// Default `UPLOAD_MAX_SESSIONS_PER_USER`
+const DEFAULT_MAX_SESSIONS_PER_OWNER: usize = 5;
+
+/// Default `UPLOAD_MAX_SPOOLED_BYTES_PER_USER`: two maximum-size files
+const DEFAULT_MAX_BYTES_PER_OWNER: usize = 2 * MAX_FILE_SIZE;
+
+type UploadError = (StatusCode, String);
+
+/// An upload in progress. Part bytes live on disk; only their sizes are kept here.
+struct UploadSession {
+    owner: String,
+    file_name: String,
+    content_type: String,
+    parts: BTreeMap<u32, usize>,
+    /// Bytes of parts still being written, counted against the caps so concurrent
+    /// parts can't each pass the check and overshoot together
+    reserved: usize,
+    created_at: Instant,
+}
+
+impl UploadSession {
+    fn total_bytes(&self) -> usize {
+        self.parts.values().sum()
+    }
+
+    /// Disk the session holds or is about to hold
+    fn spooled_bytes(&self) -> usize {
+        self.total_bytes() + self.reserved
+    }
+}
+
+/// Resumable upload sessions, with parts stored under `UPLOAD_TMP_DIR/<upload id>/`.
+///
+/// Sessions are in-memory, so an upload can resume across dropped connections but
+/// not across a server restart. Each user may hold a limited number of sessions
+/// and spooled bytes, so one account can't fill the disk.
+#[derive(Clone)]
+pub struct UploadSessions {
+    sessions: Arc<Mutex<HashMap<Uuid, UploadSession>>>,
+    dir: PathBuf,
+    ttl: Duration,
+    max_sessions_per_owner: usize,
+    max_bytes_per_owner: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InitUploadRequest {
+    pub file_name: String,
+    pub content_type: Option<String>,
+    /// Expected total size, checked up front when given
+    pub total_size: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InitUploadResponse {
+    pub upload_id: Uuid,
+    pub max_part_bytes: usize,
+    pub max_total_bytes: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadStatusResponse {
+    pub upload_id: Uuid,
+    pub file_name: String,
+    /// Part numbers received so far (clients re-send the rest after a failure)
+    pub parts: Vec<u32>,
+    pub total_bytes: usize,
+}
+
+impl UploadSessions {
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        Self {
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            dir,
+            ttl,
+            max_sessions_per_owner: DEFAULT_MAX_SESSIONS_PER_OWNER,
+            max_bytes_per_owner: DEFAULT_MAX_BYTES_PER_OWNER,
+        }
+    }
+
+    /// Cap open sessions and spooled part bytes per user
+    pub fn with_owner_limits(mut self, max_sessions: usize, max_bytes: usize) -> Self {
+        self.max_sessions_per_owner = max_sessions;
+        self.max_bytes_per_owner = max_bytes;
+        self
+    }
+
+    /// `UPLOAD_TMP_DIR` (default: system temp dir), `UPLOAD_SESSION_TTL_SECS` (default 24h),
+    /// `UPLOAD_MAX_SESSIONS_PER_USER` and `UPLOAD_MAX_SPOOLED_BYTES_PER_USER`
+    pub fn from_env() -> Self {
+        let dir = std::env::var("UPLOAD_TMP_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| std::env::temp_dir().join("quwa-uploads"));
+        let ttl_secs = std::env::var("UPLOAD_SESSION_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(24 * 60 * 60);
+        let max_sessions = std::env::var("UPLOAD_MAX_SESSIONS_PER_USER")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_MAX_SESSIONS_PER_OWNER);
+        let max_bytes = std::env::var("UPLOAD_MAX_SPOOLED_BYTES_PER_USER")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_MAX_BYTES_PER_OWNER);
+
+        Self::new(dir, Duration::from_secs(ttl_secs)).with_owner_limits(max_sessions, max_bytes)
+    }
+
+    fn session_dir(&self, upload_id: Uuid) -> PathBuf {
+        self.dir.join(upload_id.to_string())
+    }
+
+    fn part_path(&self, upload_id: Uuid, part_number: u32) -> PathBuf {
+        self.session_dir(upload_id).join(format!("{}.part", part_number))
+    }
+
+    /// Drop sessions older than the TTL along with their parts
+    async fn expire(&self) {
+        let expired: Vec<Uuid> = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let expired = sessions
+                .iter()
+                .filter(|(_, s)| s.created_at.elapsed() > self.ttl)
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+            for id in &expired {
+                sessions.remove(id);
+            }
+            expired
+        };
+
+        for id in expired {
+            tracing::info!("Expiring abandoned upload {}", id);
+            let _ = tokio::fs::remove_dir_all(self.session_dir(id)).await;
+        }
+    }
+
+    pub async fn create(
+        &self,
+        owner: &str,
+        request: InitUploadRequest,
+        file_types: &FileTypePolicy,
+    ) -> Result<InitUploadResponse, UploadError> {
+        // Content is only checked once assembled; the name can be rejected up front
+        file_types.check_extension(&request.file_name)
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+        if request.total_size.is_some_and(|size| size > MAX_FILE_SIZE) {
+            return Err(too_large());
+        }
+
+        self.expire().await;
+
+        // Registered before the directory exists so concurrent inits count against the cap
+        let upload_id = Uuid::new_v4();
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            let open = sessions.values().filter(|s| s.owner == owner).count();
+            if open >= self.max_sessions_per_owner {
+                return Err((
+                    StatusCode::TOO_MANY_REQUESTS,
+                    format!(
+                        "Too many uploads in progress ({} of {}); complete or let one expire first",
+                        open, self.max_sessions_per_owner
+                    ),
+                ));
+            }
+            sessions.insert(upload_id, UploadSession {
+                owner: owner.to_string(),
+                file_name: request.file_name,
+                content_type: request
+                    .content_type
+                    .unwrap_or_else(|| "application/octet-stream".to_string()),
+                parts: BTreeMap::new(),
+                reserved: 0,
+                created_at: Instant::now(),
+            });
+        }
+
+        if let Err(e) = tokio::fs::create_dir_all(self.session_dir(upload_id)).await {
+            self.sessions.lock().unwrap().remove(&upload_id);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+        }
+
+        Ok(InitUploadResponse {
+            upload_id,
+            max_part_bytes: MAX_PART_SIZE,
+            max_total_bytes: MAX_FILE_SIZE,
+        })
+    }
+
+    /// Store (or replace) one part. Re-sending a part is safe, which is what makes resuming work.
+    pub async fn store_part(
+        &self,
+        owner: &str,
+        upload_id: Uuid,
+        part_number: u32,
+        data: Bytes,
+    ) -> Result<UploadStatusResponse, UploadError> {
+        if part_number == 0 || part_number > MAX_PARTS {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Part number must be between 1 and {}", MAX_PARTS),
+            ));
+        }
+        if data.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "Part is empty".to_string()));
+        }
+        if data.len() > MAX_PART_SIZE {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Part exceeds maximum size of {} bytes", MAX_PART_SIZE),
+            ));
+        }
+
+        // Reserve the bytes under the lock before writing anything, so parts
+        // written concurrently can't together exceed either cap
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            let owner_bytes: usize = sessions
+                .values()
+                .filter(|s| s.owner == owner)
+                .map(UploadSession::spooled_bytes)
+                .sum();
+            let session = sessions
+                .get_mut(&upload_id)
+                .filter(|s| s.owner == owner)
+                .ok_or_else(not_found)?;
+            let replaced = session.parts.get(&part_number).copied().unwrap_or(0);
+            if session.spooled_bytes() - replaced + data.len() > MAX_FILE_SIZE {
+                return Err(too_large());
+            }
+            if owner_bytes - replaced + data.len() > self.max_bytes_per_owner {
+                return Err((
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!(
+                        "Uploads in progress would exceed {} bytes; complete or let one expire first",
+                        self.max_bytes_per_owner
+                    ),
+                ));
+            }
+            session.reserved += data.len();
+        }
+
+        let written = tokio::fs::write(self.part_path(upload_id, part_number), &data).await;
+
+        // The session may have expired while writing; its reservation went with it
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(&upload_id)
+            .filter(|s| s.owner == owner)
+            .ok_or_else(not_found)?;
+        session.reserved -= data.len();
+        written.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        session.parts.insert(part_number, data.len());
+
+        Ok(status_of(upload_id, session))
+    }
+
+    pub fn status(&self, owner: &str, upload_id: Uuid) -> Result<UploadStatusResponse, UploadError> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = owned_session(&sessions, owner, upload_id)?;
+        Ok(status_of(upload_id, session))
+    }
+
+    /// End the session and return `(file_name, content_type, bytes)` with the parts joined in order
+    pub async fn assemble(
+        &self,
+        owner: &str,
+        upload_id: Uuid,
+    ) -> Result<(String, String, Bytes), UploadError> {
+        let session = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let session = owned_session(&sessions, owner, upload_id)?;
+
+            let missing = missing_parts(&session.parts);
+            if session.parts.is_empty() || !missing.is_empty() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Upload is incomplete, missing parts: {:?}", missing),
+                ));
+            }
+            sessions.remove(&upload_id).expect("session checked above")
+        };
+
+        let mut data = Vec::with_capacity(session.total_bytes());
+        for part_number in session.parts.keys() {
+            let part = tokio::fs::read(self.part_path(upload_id, *part_number))
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            data.extend_from_slice(&part);
+        }
+        let _ = tokio::fs::remove_dir_all(self.session_dir(upload_id)).await;
+
+        Ok((session.file_name, session.content_type, Bytes::from(data)))
+    }
+}
+
+fn owned_session<'a>(
+    sessions: &'a HashMap<Uuid, UploadSession>,
+    owner: &str,
+    upload_id: Uuid,
+) -> Result<&'a UploadSession, UploadError> {
+    // Other users' uploads are indistinguishable from missing ones
+    sessions
+        .get(&upload_id)
+        .filter(|s| s.owner == owner)
+        .ok_or_else(not_found)
+}
+
+fn status_of(upload_id: Uuid, session: &UploadSession) -> UploadStatusResponse {
+    UploadStatusResponse {
+        upload_id,
+        file_name: session.file_name.clone(),
+        parts: session.parts.keys().copied().collect(),
+        total_bytes: session.total_bytes(),
+    }
+}
+
+/// Gaps in 1..=highest received part
+fn missing_parts(parts: &BTreeMap<u32, usize>) -> Vec<u32> {
+    let highest = parts.keys().next_back().copied().unwrap_or(0);
+    (1..=highest).filter(|n| !parts.contains_key(n)).collect()
+}
+
+fn not_found() -> UploadError {
+    (StatusCode::NOT_FOUND, "Upload not found or expired".to_string())
+}
+
+fn too_large() -> UploadError {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        "File size exceeds maximum allowed size of 50MB".to_string(),
+    )
+}
+
+// ── Handlers ─────────────────────────────────────────────────────────────────
+
+pub async fn init_upload(
+    State(state): State<AppState>,
+    claims: AppwriteClaims,
+    Json(payload): Json<InitUploadRequest>,
+) -> Result<Json<InitUploadResponse>, UploadError> {
+    // Refuse up front what the quota can't hold, rather than after every part
+    // is sent; an archive's members are at least as large as the zip
+    let quota = state.config.upload_quota.for_claims(&claims);
+    if let (Some(_), Some(total_size)) = (quota, payload.total_size) {
+        let user = crate::db::queries::get_or_create_user(
+            &state.db_pool,
+            &claims.user_id,
+            claims.email.as_deref(),
+            claims.name.as_deref(),
+        )
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        check_quota(&state, quota, user.id, 1, total_size).await?;
+    }
+
+    state.uploads.create(&claims.user_id, payload, &state.config.file_types).await.map(Json)
+}
+
+pub async fn upload_part(
+    State(state): State<AppState>,
+    claims: AppwriteClaims,
+    Path((upload_id, part_number)): Path<(Uuid, u32)>,
+    body: Bytes,
+) -> Result<Json<UploadStatusResponse>, UploadError> {
+    state
+        .uploads
+        .store_part(&claims.user_id, upload_id, part_number, body)
+        .await
+        .map(Json)
+}
+
+pub async fn upload_status(
+    State(state): State<AppState>,
+    claims: AppwriteClaims,
+    Path(upload_id): Path<Uuid>,
+) -> Result<Json<UploadStatusResponse>, UploadError> {
+    state.uploads.status(&claims.user_id, upload_id).map(Json)
+}
+
+pub async fn complete_upload(
+    State(state): State<AppState>,
+    claims: AppwriteClaims,
+    Path(upload_id): Path<Uuid>,
+) -> Result<Json<UploadResponse>, UploadError> {
+    let ticket = reserve_processing(&state)?;
+    let (file_name, content_type, data) = state.uploads.assemble(&claims.user_id, upload_id).await?;
+    ingest_file(state, ticket, claims, file_name, content_type, data).await.map(Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sessions() -> UploadSessions {
+        let dir = std::env::temp_dir().join(format!("quwa-upload-test-{}", Uuid::new_v4()));
+        UploadSessions::new(dir, Duration::from_secs(60))
+    }
+
+    fn init(name: &str) -> InitUploadRequest {
+        InitUploadRequest {
+            file_name: name.to_string(),
+            content_type: Some("application/pdf".to_string()),
+            total_size: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parts_are_assembled_in_order() {
+        let uploads = sessions();
+        let id = uploads.create("user-1", init("scan.pdf"), &FileTypePolicy::default()).await.unwrap().upload_id;
+
+        uploads.store_part("user-1", id, 2, Bytes::from_static(b"world")).await.unwrap();
+        uploads.store_part("user-1", id, 1, Bytes::from_static(b"hello ")).await.unwrap();
+        // Re-sending a part replaces it
+        let status = uploads.store_part("user-1", id, 2, Bytes::from_static(b"there")).await.unwrap();
+        assert_eq!(status.parts, vec![1, 2]);
+        assert_eq!(status.total_bytes, 11);
+
+        let (name, content_type, data) = uploads.assemble("user-1", id).await.unwrap();
+        assert_eq!(name, "scan.pdf");
+        assert_eq!(content_type, "application/pdf");
+        assert_eq!(&data[..], b"hello there");
+
+        // The session is gone once assembled
+        assert_eq!(uploads.status("user-1", id).unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_incomplete_upload_is_rejected() {
+        let uploads = sessions();
+        let id = uploads.create("user-1", init("scan.pdf"), &FileTypePolicy::default()).await.unwrap().upload_id;
+        uploads.store_part("user-1", id, 1, Bytes::from_static(b"a")).await.unwrap();
+        uploads.store_part("user-1", id, 3, Bytes::from_static(b"c")).await.unwrap();
+
+        let (status, message) = uploads.assemble("user-1", id).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(message.contains("[2]"));
+        // Still resumable after a failed completion
+        assert!(uploads.status("user-1", id).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_uploads_are_private_and_size_capped() {
+        let uploads = sessions();
+        let id = uploads.create("user-1", init("scan.pdf"), &FileTypePolicy::default()).await.unwrap().upload_id;
+
+        let err = uploads.store_part("user-2", id, 1, Bytes::from_static(b"x")).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+
+        let oversized = InitUploadRequest { total_size: Some(MAX_FILE_SIZE + 1), ..init("scan.pdf") };
+        assert_eq!(uploads.create("user-1", oversized, &FileTypePolicy::default()).await.unwrap_err().0, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(uploads.create("user-1", init("virus.exe"), &FileTypePolicy::default()).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_owner_limits() {
+        let uploads = sessions().with_owner_limits(2, 10);
+        let first = uploads.create("user-1", init("a.pdf"), &FileTypePolicy::default()).await.unwrap().upload_id;
+        let second = uploads.create("user-1", init("b.pdf"), &FileTypePolicy::default()).await.unwrap().upload_id;
+        let err = uploads.create("user-1", init("c.pdf"), &FileTypePolicy::default()).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::TOO_MANY_REQUESTS);
+        assert!(uploads.create("user-2", init("c.pdf"), &FileTypePolicy::default()).await.is_ok());
+
+        // Spooled bytes count across all of a user's sessions
+        uploads.store_part("user-1", first, 1, Bytes::from_static(b"123456")).await.unwrap();
+        let err = uploads.store_part("user-1", second, 1, Bytes::from_static(b"12345")).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::PAYLOAD_TOO_LARGE);
+        uploads.store_part("user-1", second, 1, Bytes::from_static(b"1234")).await.unwrap();
+        // Replacing a part only counts the difference
+        uploads.store_part("user-1", first, 1, Bytes::from_static(b"654321")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_releases_reservation() {
+        let uploads = sessions();
+        let id = uploads.create("user-1", init("scan.pdf"), &FileTypePolicy::default()).await.unwrap().upload_id;
+        tokio::fs::remove_dir_all(uploads.session_dir(id)).await.unwrap();
+
+        let err = uploads.store_part("user-1", id, 1, Bytes::from_static(b"abc")).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(uploads.sessions.lock().unwrap()[&id].spooled_bytes(), 0);
+    }
+
+    #[test]
+    fn test_missing_parts() {
+        let parts: BTreeMap<u32, usize> = [(1, 1), (2, 1), (5, 1)].into_iter().collect();
+        assert_eq!(missing_parts(&parts), vec![3, 4]);
+        assert!(missing_parts(&BTreeMap::new()).is_empty());
+    }
+}
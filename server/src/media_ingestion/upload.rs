@@ -1,27 +1,171 @@
 use axum::{
-    extract::{Multipart, State},
-    http::StatusCode,
-    response::Json,
+    body::Body,
+    extract::{Multipart, Path, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
 };
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
 use uuid::Uuid;
 
-use crate::{AppState, auth::AppwriteClaims};
-use super::validation::{validate_file, determine_file_type};
+use crate::{AppState, auth::AppwriteClaims, db::queries::RecordedUpload, redact::pii, storage::StoredFile};
+use super::archive::{ArchiveMember, SkippedMember, extract_archive};
+use super::queue::QueueTicket;
+use super::quota::{QuotaExceeded, StorageQuota, StorageUsage};
+use super::validation::{ARCHIVE, validate_file};
 
 #[derive(Debug, Serialize)]
 pub struct UploadResponse {
+    /// For zip uploads this is the document set id; poll its members with
+    /// `GET /api/files?document_set_id=` or by their own `file_id`s
     pub file_id: String,
     pub file_name: String,
     pub status: String,
+    /// Same bytes were already uploaded by this user; `file_id` refers to that upload
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub duplicate: bool,
+    /// What was extracted, once known (duplicates of processed files; see `file_status`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_preview: Option<String>,
+    /// Set for zip uploads (where it equals `file_id`) and their members
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document_set_id: Option<String>,
+    /// Files extracted from a zip upload, each with its own `file_id`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<UploadResponse>,
+    /// Zip members left out under `ZIP_INVALID_MEMBERS=skip`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<SkippedMember>,
 }
 
-pub async fn handle_file_upload(
+/// Characters of extracted text kept as the file's preview
+const TEXT_PREVIEW_CHARS: usize = 500;
+
+#[derive(Debug, Serialize)]
+pub struct FileStatusResponse {
+    pub file_id: String,
+    pub file_name: String,
+    pub file_type: String,
+    pub status: String,
+    pub upload_date: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Start of the extracted text, available as soon as extraction finishes, so a
+    /// failed or garbled extraction (e.g. a scanned PDF) is visible before chatting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_preview: Option<String>,
+    /// Zip upload this file was extracted from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document_set_id: Option<String>,
+    /// PDF extractor that produced the text (see `PDF_EXTRACTORS`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_extractor: Option<String>,
+}
+
+/// `GET /api/files/{id}`: processing status and extracted-text preview of an upload
+pub async fn file_status(
     State(state): State<AppState>,
-    claims: AppwriteClaims, // Extracted from JWT middleware
-    mut multipart: Multipart,
-) -> Result<Json<UploadResponse>, (StatusCode, String)> {
-    // Get or create user (now with email and name from Appwrite API)
+    claims: AppwriteClaims,
+    Path(file_id): Path<Uuid>,
+) -> Result<Json<FileStatusResponse>, (StatusCode, String)> {
+    let file = crate::db::queries::get_file_for_user(&state.db_pool, file_id, &claims.user_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        // Other users' files are indistinguishable from missing ones
+        .ok_or((StatusCode::NOT_FOUND, format!("No file with id {}", file_id)))?;
+
+    Ok(Json(file.into()))
+}
+
+impl From<crate::db::models::UploadedFile> for FileStatusResponse {
+    fn from(file: crate::db::models::UploadedFile) -> Self {
+        FileStatusResponse {
+            file_id: file.id.to_string(),
+            file_name: file.file_name,
+            file_type: file.file_type,
+            status: file.processing_status,
+            upload_date: file.upload_date,
+            error: file.error_message,
+            text_preview: file.text_preview,
+            document_set_id: file.document_set_id.map(|id| id.to_string()),
+            text_extractor: file.text_extractor,
+        }
+    }
+}
+
+/// `GET /api/files/{id}/download`: the original bytes of one of the caller's uploads
+pub async fn download_file(
+    State(state): State<AppState>,
+    claims: AppwriteClaims,
+    Path(file_id): Path<Uuid>,
+) -> Result<Response, (StatusCode, String)> {
+    let file = crate::db::queries::get_file_for_user(&state.db_pool, file_id, &claims.user_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, format!("No file with id {}", file_id)))?;
+
+    if file.storage_backend.is_none() {
+        return Err((StatusCode::NOT_FOUND, format!("The original of file {} was not kept", file_id)));
+    }
+
+    let stored = StoredFile {
+        backend: file.storage_backend,
+        file_id: file.appwrite_file_id,
+        bucket_id: file.appwrite_bucket_id,
+    };
+    let body = state.file_storage.open(&stored).await.map_err(|e| {
+        tracing::error!("Failed to open stored file {}: {:#}", file_id, e);
+        (StatusCode::SERVICE_UNAVAILABLE, "File storage unavailable, please retry".to_string())
+    })?;
+
+    let content_type = file.mime_type.unwrap_or_else(|| "application/octet-stream".to_string());
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_DISPOSITION, content_disposition(&file.file_name)),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+/// `attachment` disposition keeping the upload's name, minus characters that
+/// would break out of the quoted header value
+fn content_disposition(file_name: &str) -> String {
+    let safe: String = file_name
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"' && *c != '\\')
+        .collect();
+    format!("attachment; filename=\"{}\"", safe)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListFilesQuery {
+    /// Only files uploaded at or after this instant (RFC 3339), for incremental sync
+    pub since: Option<DateTime<Utc>>,
+    /// Only the files extracted from one zip upload
+    pub document_set_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileListResponse {
+    pub files: Vec<FileStatusResponse>,
+    /// All of the caller's uploads, whatever `since` selected
+    pub usage: StorageUsage,
+    /// The caller's limits, when any apply (`UPLOAD_QUOTA_*`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<StorageQuota>,
+}
+
+/// `GET /api/files?since=&document_set_id=`: the caller's uploads, newest first,
+/// with their storage usage
+pub async fn list_files(
+    State(state): State<AppState>,
+    claims: AppwriteClaims,
+    Query(params): Query<ListFilesQuery>,
+) -> Result<Json<FileListResponse>, (StatusCode, String)> {
     let user = crate::db::queries::get_or_create_user(
         &state.db_pool,
         &claims.user_id,
@@ -30,14 +174,57 @@ pub async fn handle_file_upload(
     )
     .await
     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    
+
+    let files = crate::db::queries::get_user_files(&state.db_pool, user.id, params.since, params.document_set_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(FileListResponse {
+        files: files.into_iter().map(FileStatusResponse::from).collect(),
+        usage: storage_usage(&state, user.id).await?,
+        quota: state.config.upload_quota.for_claims(&claims),
+    }))
+}
+
+async fn storage_usage(state: &AppState, user_id: i32) -> Result<StorageUsage, (StatusCode, String)> {
+    crate::db::queries::get_user_storage_usage(&state.db_pool, user_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// 413 when `files` more files of `bytes` in total would take the user past `quota`.
+/// An early refusal only: `create_uploaded_file` checks again under a lock.
+pub(crate) async fn check_quota(
+    state: &AppState,
+    quota: Option<StorageQuota>,
+    user_id: i32,
+    files: usize,
+    bytes: usize,
+) -> Result<(), (StatusCode, String)> {
+    let Some(quota) = quota else {
+        return Ok(());
+    };
+    let usage = storage_usage(state, user_id).await?;
+    quota.check(usage, files as u64, bytes as u64).map_err(|message| {
+        tracing::info!("Rejecting upload for user {}: {}", user_id, message);
+        (StatusCode::PAYLOAD_TOO_LARGE, message)
+    })
+}
+
+pub async fn handle_file_upload(
+    State(state): State<AppState>,
+    claims: AppwriteClaims, // Extracted from JWT middleware
+    mut multipart: Multipart,
+) -> Result<Json<UploadResponse>, (StatusCode, String)> {
+    let ticket = reserve_processing(&state)?;
     let mut file_data = None;
     let mut file_name = String::new();
     let mut content_type = String::new();
     
-    // Parse multipart data
+    // Parse multipart data. The body is streamed under the route's body limit,
+    // so oversized uploads fail here with 413 instead of being buffered first.
     while let Some(field) = multipart.next_field().await.map_err(|e| {
-        (StatusCode::BAD_REQUEST, format!("Failed to read multipart: {}", e))
+        (e.status(), format!("Failed to read multipart: {}", e))
     })? {
         let name = field.name().unwrap_or("").to_string();
         
@@ -46,7 +233,7 @@ pub async fn handle_file_upload(
             content_type = field.content_type().unwrap_or("application/octet-stream").to_string();
             
             let data = field.bytes().await.map_err(|e| {
-                (StatusCode::BAD_REQUEST, format!("Failed to read file: {}", e))
+                (e.status(), format!("Failed to read file: {}", e))
             })?;
             
             file_data = Some(data);
@@ -58,63 +245,333 @@ pub async fn handle_file_upload(
         "No file provided".to_string(),
     ))?;
     
-    // Validate file
-    validate_file(&file_name, &file_bytes).map_err(|e| {
-        (StatusCode::BAD_REQUEST, e.to_string())
+    ingest_file(state, ticket, claims, file_name, content_type, file_bytes).await.map(Json)
+}
+
+/// Claim a processing slot before receiving the file, so a full queue answers
+/// 429 without buffering the body (and, for resumable uploads, keeps the parts)
+pub(crate) fn reserve_processing(state: &AppState) -> Result<QueueTicket, (StatusCode, String)> {
+    state.processing.reserve().map_err(|e| {
+        tracing::warn!("Rejecting upload: {}", e);
+        (StatusCode::TOO_MANY_REQUESTS, e.to_string())
+    })
+}
+
+/// Validate, dedup, record and start processing a fully received file.
+/// Shared by single-request and resumable uploads.
+pub(crate) async fn ingest_file(
+    state: AppState,
+    ticket: QueueTicket,
+    claims: AppwriteClaims,
+    file_name: String,
+    content_type: String,
+    file_bytes: bytes::Bytes,
+) -> Result<UploadResponse, (StatusCode, String)> {
+    // Get or create user (now with email and name from Appwrite API)
+    let user = crate::db::queries::get_or_create_user(
+        &state.db_pool,
+        &claims.user_id,
+        claims.email.as_deref(),
+        claims.name.as_deref(),
+    )
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    
+    // Validate file and pick its processing path
+    let file_type = validate_file(&state.config.file_types, &file_name, &content_type, &file_bytes).map_err(|e| {
+        (StatusCode::BAD_REQUEST, format!("{}: {}", file_name, e))
     })?;
     
+    let quota = state.config.upload_quota.for_claims(&claims);
+    if file_type == ARCHIVE {
+        return ingest_document_set(state, ticket, user.id, quota, file_name, file_bytes).await;
+    }
+    
+    let (mut response, pending) =
+        record_file(&state, user.id, quota, file_name, content_type, file_type, file_bytes, None).await?;
+    
+    // Process in the background once a worker is free
+    if let Some(pending) = pending {
+        response.status = if ticket.will_wait() { "queued" } else { "processing" }.to_string();
+        ticket.spawn(pending.process(state));
+    }
+    
+    Ok(response)
+}
+
+/// Unpack a zip and record every member under one document set id. The members
+/// are processed one after another on the upload's single queue slot.
+async fn ingest_document_set(
+    state: AppState,
+    ticket: QueueTicket,
+    user_id: i32,
+    quota: Option<StorageQuota>,
+    file_name: String,
+    file_bytes: bytes::Bytes,
+) -> Result<UploadResponse, (StatusCode, String)> {
+    // Inflating up to MAX_ARCHIVE_TOTAL_SIZE is CPU-bound, so keep it off the runtime
+    let config = state.config.clone();
+    let archive = file_bytes.clone();
+    let extracted = tokio::task::spawn_blocking(move || {
+        extract_archive(&archive, config.archive_member_policy, &config.file_types)
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| (StatusCode::BAD_REQUEST, format!("{}: {}", file_name, e)))?;
+    // Members count against the quota, not the zip itself, and only those
+    // that won't dedup to a file already stored. Checked for the whole set up
+    // front so an over-quota zip isn't half recorded.
+    if quota.is_some() {
+        let (files, bytes) = new_member_usage(&state, user_id, &extracted.members).await?;
+        check_quota(&state, quota, user_id, files, bytes).await?;
+    }
+    
+    let document_set_id = Uuid::new_v4();
+    let mut members = Vec::with_capacity(extracted.members.len());
+    let mut pending = Vec::new();
+    for member in extracted.members {
+        let recorded = record_file(
+            &state,
+            user_id,
+            quota,
+            member.file_name,
+            member.content_type,
+            member.file_type,
+            member.data,
+            Some(document_set_id),
+        )
+        .await;
+        let (response, job) = match recorded {
+            Ok(recorded) => recorded,
+            Err(e) => {
+                fail_recorded_members(&state, &pending, &e.1).await;
+                return Err(e);
+            }
+        };
+        members.push(response);
+        pending.extend(job);
+    }
+    
+    tracing::info!(
+        "Zip {} unpacked into document set {}: {} files ({} new), {} skipped",
+        pii(&file_name),
+        document_set_id,
+        members.len(),
+        pending.len(),
+        extracted.skipped.len()
+    );
+    
+    let status = if pending.is_empty() {
+        "completed"
+    } else if ticket.will_wait() {
+        "queued"
+    } else {
+        "processing"
+    };
+    ticket.spawn(async move {
+        for file in pending {
+            file.process(state.clone()).await;
+        }
+    });
+    
+    Ok(UploadResponse {
+        file_id: document_set_id.to_string(),
+        file_name,
+        status: status.to_string(),
+        duplicate: false,
+        text_preview: None,
+        document_set_id: Some(document_set_id.to_string()),
+        members,
+        skipped: extracted.skipped,
+    })
+}
+
+/// The upload failed part way through recording a zip's members: fail the ones
+/// already recorded, which would otherwise stay pending with nothing to process them
+async fn fail_recorded_members(state: &AppState, recorded: &[PendingFile], reason: &str) {
+    let message = format!("Zip upload failed before this file was processed: {}", reason);
+    for file in recorded {
+        state.in_flight.finish(file.file_id);
+        if let Err(e) = crate::db::queries::update_file_status(&state.db_pool, file.file_id, "failed", Some(&message)).await {
+            tracing::error!("Failed to mark zip member {} as failed: {}", file.file_id, e);
+        }
+    }
+}
+
+/// A recorded file waiting to be processed
+#[derive(Clone)]
+pub(crate) struct PendingFile {
+    pub(crate) file_id: Uuid,
+    pub(crate) file_type: String,
+    pub(crate) data: bytes::Bytes,
+}
+
+impl PendingFile {
+    /// Process the file, recording any failure against it
+    pub(crate) async fn process(self, state: AppState) {
+        let file_id = self.file_id;
+        let db_pool = state.db_pool.clone();
+        let in_flight = state.in_flight.clone();
+        let result = process_uploaded_file(state, file_id, self.file_type, self.data).await;
+        in_flight.finish(file_id);
+        if let Err(e) = result {
+            tracing::error!("File processing failed for {}: {}", file_id, e);
+            
+            // Update status to failed
+            let _ = crate::db::queries::update_file_status(
+                &db_pool,
+                file_id,
+                "failed",
+                Some(&e.to_string()),
+            ).await;
+        }
+    }
+}
+
+/// Number and total size of the archive `members` that `record_file` would
+/// store rather than match to an existing upload (or an earlier member)
+async fn new_member_usage(
+    state: &AppState,
+    user_id: i32,
+    members: &[ArchiveMember],
+) -> Result<(usize, usize), (StatusCode, String)> {
+    if !state.config.upload_dedup {
+        return Ok((members.len(), members.iter().map(|m| m.data.len()).sum()));
+    }
+    
+    let hashes: Vec<String> = members.iter().map(|m| format!("{:x}", Sha256::digest(&m.data))).collect();
+    let mut seen: HashSet<String> = crate::db::queries::find_existing_hashes(&state.db_pool, user_id, &hashes)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .into_iter()
+        .collect();
+    let new: Vec<_> = members.iter().zip(hashes).filter(|(_, hash)| seen.insert(hash.clone())).collect();
+    Ok((new.len(), new.iter().map(|(m, _)| m.data.len()).sum()))
+}
+
+/// Dedup and save a validated file's metadata, within the owner's `quota`.
+/// Returns the file to process, or `None` when the bytes match an existing upload.
+#[allow(clippy::too_many_arguments)]
+async fn record_file(
+    state: &AppState,
+    user_id: i32,
+    quota: Option<StorageQuota>,
+    file_name: String,
+    content_type: String,
+    file_type: String,
+    file_bytes: bytes::Bytes,
+    document_set_id: Option<Uuid>,
+) -> Result<(UploadResponse, Option<PendingFile>), (StatusCode, String)> {
+    let content_hash = format!("{:x}", Sha256::digest(&file_bytes));
+    
+    // Re-uploading the same bytes (even under another name) would duplicate every embedding
+    if state.config.upload_dedup {
+        let existing = crate::db::queries::find_file_by_hash(&state.db_pool, user_id, &content_hash)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        
+        if let Some(existing) = existing {
+            return Ok((duplicate_response(&file_name, existing), None));
+        }
+    }
+    
+    // Duplicates never count against the quota; refuse before storing the bytes
+    check_quota(state, quota, user_id, 1, file_bytes.len()).await?;
+    
     let file_id = Uuid::new_v4();
-    let file_type = determine_file_type(&content_type);
     
-    // For now, skip Appwrite upload and store file temporarily
-    // TODO: Integrate actual Appwrite storage
-    let appwrite_file_id = file_id.to_string();
-    let appwrite_bucket_id = "medical_files".to_string();
+    // Keep the original bytes (FILE_STORAGE) for download and reprocessing
+    let stored = state.file_storage.put(file_id, &file_name, &file_bytes).await.map_err(|e| {
+        tracing::error!("Failed to store {}: {:#}", pii(&file_name), e);
+        (StatusCode::SERVICE_UNAVAILABLE, "File storage unavailable, please retry".to_string())
+    })?;
     
     // Save metadata to database
     let uploaded_file = crate::db::models::UploadedFile {
         id: file_id,
-        user_id: user.id,
+        user_id,
         file_name: file_name.clone(),
         file_type: file_type.clone(),
         mime_type: Some(content_type),
         file_size_bytes: Some(file_bytes.len() as i64),
-        appwrite_file_id,
-        appwrite_bucket_id,
+        appwrite_file_id: stored.file_id.clone(),
+        appwrite_bucket_id: stored.bucket_id.clone(),
         processing_status: "pending".to_string(),
         upload_date: chrono::Utc::now(),
         processed_at: None,
         error_message: None,
+        content_hash: Some(content_hash),
+        text_preview: None,
+        document_set_id,
+        text_extractor: None,
+        storage_backend: stored.backend.clone(),
     };
     
-    crate::db::queries::create_uploaded_file(&state.db_pool, &uploaded_file)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    
-    // Trigger async processing
-    let state_clone = state.clone();
-    let db_pool_clone = state.db_pool.clone();
-    tokio::spawn(async move {
-        if let Err(e) = process_uploaded_file(state_clone, file_id, file_type, file_bytes).await {
-            tracing::error!("File processing failed for {}: {}", file_id, e);
-            
-            // Update status to failed
-            let _ = crate::db::queries::update_file_status(
-                &db_pool_clone,
-                file_id,
-                "failed",
-                Some(&e.to_string()),
-            ).await;
+    // Dedup again under the insert's lock: a concurrent upload of the same bytes
+    // may have been recorded since the check above
+    let recorded = crate::db::queries::create_uploaded_file(
+        &state.db_pool,
+        &uploaded_file,
+        quota,
+        state.config.upload_dedup,
+    )
+    .await;
+    if !matches!(recorded, Ok(RecordedUpload::Created(_))) {
+        // Without the row nothing points at the stored bytes any more
+        if let Err(delete_err) = state.file_storage.delete(&stored).await {
+            tracing::warn!("Failed to remove stored bytes of unrecorded file {}: {:#}", file_id, delete_err);
         }
-    });
+    }
+    match recorded {
+        Ok(RecordedUpload::Created(_)) => {}
+        Ok(RecordedUpload::Duplicate(existing)) => return Ok((duplicate_response(&file_name, existing), None)),
+        // Another upload took the last of the quota since `check_quota`
+        Err(e) => match e.downcast_ref::<QuotaExceeded>() {
+            Some(exceeded) => {
+                tracing::info!("Rejecting upload for user {}: {}", user_id, exceeded);
+                return Err((StatusCode::PAYLOAD_TOO_LARGE, exceeded.to_string()));
+            }
+            None => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        },
+    }
     
-    Ok(Json(UploadResponse {
-        file_id: file_id.to_string(),
-        file_name,
-        status: "processing".to_string(),
-    }))
+    let pending = PendingFile { file_id, file_type, data: file_bytes };
+    state.in_flight.track(&pending);
+    
+    Ok((
+        UploadResponse {
+            file_id: file_id.to_string(),
+            file_name,
+            status: "pending".to_string(),
+            duplicate: false,
+            text_preview: None,
+            document_set_id: document_set_id.map(|id| id.to_string()),
+            members: vec![],
+            skipped: vec![],
+        },
+        Some(pending),
+    ))
+}
+
+/// Response for an upload whose bytes match the owner's `existing` file
+fn duplicate_response(file_name: &str, existing: crate::db::models::UploadedFile) -> UploadResponse {
+    tracing::info!("Upload of {} matches existing file {}, skipping processing", pii(file_name), existing.id);
+    UploadResponse {
+        file_id: existing.id.to_string(),
+        file_name: existing.file_name,
+        status: existing.processing_status,
+        duplicate: true,
+        text_preview: existing.text_preview,
+        document_set_id: existing.document_set_id.map(|id| id.to_string()),
+        members: vec![],
+        skipped: vec![],
+    }
 }
 
+/// Internal file types `process_uploaded_file` handles; other accepted types
+/// (e.g. DICOM) are stored but fail processing
+pub const PROCESSED_FILE_TYPES: &[&str] = &["pdf", "image"];
+
 async fn process_uploaded_file(
     state: AppState,
     file_id: Uuid,
@@ -128,22 +585,35 @@ async fn process_uploaded_file(
     
     match file_type.as_str() {
         "pdf" => {
-            // Process PDF
-            let embeddings = state.pdf_processor.process_pdf(file_data, Some(&state.request_counter)).await?;
+            // Process PDF, publishing the preview before the slow embedding step.
+            // Extraction is CPU-bound and fans out over PDF_PAGE_THREADS, so keep it off the runtime
+            let pdf_processor = state.pdf_processor.clone();
+            let extracted = tokio::task::spawn_blocking(move || pdf_processor.extract_text(file_data)).await??;
+            tracing::info!("Extracted PDF text for {} with {}", file_id, extracted.extractor);
+            crate::db::queries::set_file_text_preview(&state.db_pool, file_id, &text_preview(&extracted.text)).await?;
+            crate::db::queries::set_file_text_extractor(&state.db_pool, file_id, extracted.extractor.as_str()).await?;
+            let chunks = state.pdf_processor.process_text(&extracted.text).await?;
+            let chunk_count = chunks.len();
             
             // Store in vector store
-            for (idx, (text, embedding)) in embeddings.iter().enumerate() {
+            for (idx, chunk) in chunks.into_iter().enumerate() {
                 let embedding_id = format!("{}_{}", file_id, idx);
+                let offsets = (chunk.start_offset as i32, chunk.end_offset as i32);
                 
                 state.vector_store.add_document(
                     embedding_id.clone(),
-                    text.clone(),
-                    embedding.clone(),
+                    chunk.text.clone(),
+                    chunk.embedding,
                     crate::rag::vector_store::DocumentMetadata {
                         source_type: "user_file".to_string(),
                         source_id: file_id.to_string(),
                         file_name: None,
                         orpha_code: None,
+                        hpo_associations: vec![],
+                        start_offset: Some(offsets.0),
+                        end_offset: Some(offsets.1),
+                        modality: None,
+                        region: None,
                     },
                 ).await?;
                 
@@ -152,16 +622,19 @@ async fn process_uploaded_file(
                     &state.db_pool,
                     file_id,
                     idx as i32,
-                    text,
+                    &chunk.text,
                     &embedding_id,
+                    Some(offsets),
                 ).await?;
             }
             
-            tracing::info!("PDF processing completed: {} chunks", embeddings.len());
+            tracing::info!("PDF processing completed: {} chunks", chunk_count);
         }
         "image" => {
             // Process image
-            let (description, embedding) = state.image_processor.process_image(file_data).await?;
+            let crate::processing::image::ProcessedImage { description, analysis, embedding } =
+                state.image_processor.process_image(file_data).await?;
+            crate::db::queries::set_file_text_preview(&state.db_pool, file_id, &text_preview(&description)).await?;
             
             let embedding_id = format!("{}_0", file_id);
             
@@ -175,6 +648,11 @@ async fn process_uploaded_file(
                     source_id: file_id.to_string(),
                     file_name: None,
                     orpha_code: None,
+                    hpo_associations: vec![],
+                    start_offset: None,
+                    end_offset: None,
+                    modality: analysis.modality,
+                    region: analysis.region,
                 },
             ).await?;
             
@@ -185,6 +663,7 @@ async fn process_uploaded_file(
                 0,
                 &description,
                 &embedding_id,
+                None,
             ).await?;
             
             tracing::info!("Image processing completed");
@@ -199,3 +678,176 @@ async fn process_uploaded_file(
     
     Ok(())
 }
+
+/// First `TEXT_PREVIEW_CHARS` characters of `text` with whitespace runs collapsed,
+/// cut at a word boundary and marked with an ellipsis when shortened
+fn text_preview(text: &str) -> String {
+    let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.chars().count() <= TEXT_PREVIEW_CHARS {
+        return collapsed;
+    }
+
+    let mut preview: String = collapsed.chars().take(TEXT_PREVIEW_CHARS).collect();
+    if let Some(space) = preview.rfind(' ') {
+        preview.truncate(space);
+    }
+    preview.push('…');
+    preview
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_files_since_parses_rfc3339() {
+        let uri: axum::http::Uri = "/api/files?since=2026-10-01T12:00:00Z".parse().unwrap();
+        let Query(query) = Query::<ListFilesQuery>::try_from_uri(&uri).unwrap();
+        assert_eq!(query.since, Some("2026-10-01T12:00:00Z".parse().unwrap()));
+
+        let uri: axum::http::Uri = "/api/files".parse().unwrap();
+        assert!(Query::<ListFilesQuery>::try_from_uri(&uri).unwrap().0.since.is_none());
+
+        let uri: axum::http::Uri = "/api/files?document_set_id=9f1c2d3e-0000-4000-8000-000000000001".parse().unwrap();
+        let Query(query) = Query::<ListFilesQuery>::try_from_uri(&uri).unwrap();
+        assert_eq!(query.document_set_id, Some("9f1c2d3e-0000-4000-8000-000000000001".parse().unwrap()));
+
+        let uri: axum::http::Uri = "/api/files?since=yesterday".parse().unwrap();
+        assert!(Query::<ListFilesQuery>::try_from_uri(&uri).is_err());
+    }
+
+    /// The `since` bound is inclusive. Needs DATABASE_URL with migrations applied:
+    /// `cargo test test_get_user_files_since_boundary -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_get_user_files_since_boundary() {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL not set");
+        let pool = crate::db::create_pool(&database_url).await.unwrap();
+
+        let appwrite_id = format!("since-test-{}", Uuid::new_v4());
+        let user = crate::db::queries::get_or_create_user(&pool, &appwrite_id, None, None).await.unwrap();
+        let boundary: DateTime<Utc> = "2026-10-01T12:00:00Z".parse().unwrap();
+
+        let mut ids = Vec::new();
+        for offset_secs in [-1, 0, 1] {
+            let file = crate::db::models::UploadedFile {
+                id: Uuid::new_v4(),
+                user_id: user.id,
+                file_name: format!("{}.pdf", offset_secs),
+                file_type: "pdf".to_string(),
+                mime_type: None,
+                file_size_bytes: None,
+                appwrite_file_id: "test".to_string(),
+                appwrite_bucket_id: "test".to_string(),
+                processing_status: "completed".to_string(),
+                upload_date: Utc::now(),
+                processed_at: None,
+                error_message: None,
+                content_hash: None,
+                text_preview: None,
+                document_set_id: None,
+                text_extractor: None,
+                storage_backend: None,
+            };
+            crate::db::queries::create_uploaded_file(&pool, &file, None, false).await.unwrap();
+            sqlx::query("UPDATE uploaded_files SET upload_date = $1 WHERE id = $2")
+                .bind(boundary + chrono::Duration::seconds(offset_secs))
+                .bind(file.id)
+                .execute(&pool)
+                .await
+                .unwrap();
+            ids.push(file.id);
+        }
+
+        let since = crate::db::queries::get_user_files(&pool, user.id, Some(boundary), None).await.unwrap();
+        let since: Vec<Uuid> = since.into_iter().map(|f| f.id).collect();
+        assert_eq!(since, vec![ids[2], ids[1]]);
+
+        let all = crate::db::queries::get_user_files(&pool, user.id, None, None).await.unwrap();
+        assert_eq!(all.len(), 3);
+
+        sqlx::query("DELETE FROM users WHERE id = $1").bind(user.id).execute(&pool).await.unwrap();
+    }
+
+    /// Concurrent inserts can't both pass a one-file quota, nor both miss the
+    /// dedup check for the same bytes. Needs DATABASE_URL with migrations applied:
+    /// `cargo test test_create_uploaded_file_checks_are_atomic -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_create_uploaded_file_checks_are_atomic() {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL not set");
+        let pool = crate::db::create_pool(&database_url).await.unwrap();
+
+        let appwrite_id = format!("atomic-test-{}", Uuid::new_v4());
+        let user = crate::db::queries::get_or_create_user(&pool, &appwrite_id, None, None).await.unwrap();
+        let file = |content_hash: Option<&str>| crate::db::models::UploadedFile {
+            id: Uuid::new_v4(),
+            user_id: user.id,
+            file_name: "scan.pdf".to_string(),
+            file_type: "pdf".to_string(),
+            mime_type: None,
+            file_size_bytes: Some(10),
+            appwrite_file_id: "test".to_string(),
+            appwrite_bucket_id: "test".to_string(),
+            processing_status: "pending".to_string(),
+            upload_date: Utc::now(),
+            processed_at: None,
+            error_message: None,
+            content_hash: content_hash.map(str::to_string),
+            text_preview: None,
+            document_set_id: None,
+            text_extractor: None,
+            storage_backend: None,
+        };
+        let race = |files: Vec<crate::db::models::UploadedFile>, quota: Option<StorageQuota>, dedup: bool| {
+            let handles: Vec<_> = files
+                .into_iter()
+                .map(|file| {
+                    let pool = pool.clone();
+                    tokio::spawn(async move { crate::db::queries::create_uploaded_file(&pool, &file, quota, dedup).await })
+                })
+                .collect();
+            async move {
+                let mut created = 0;
+                for handle in handles {
+                    match handle.await.unwrap() {
+                        Ok(RecordedUpload::Created(_)) => created += 1,
+                        Ok(RecordedUpload::Duplicate(_)) => {}
+                        Err(e) => assert!(e.is::<QuotaExceeded>(), "{}", e),
+                    }
+                }
+                created
+            }
+        };
+
+        let same_bytes = (0..4).map(|_| file(Some("same-hash"))).collect();
+        assert_eq!(race(same_bytes, None, true).await, 1);
+
+        let quota = StorageQuota { max_files: 2, max_bytes: 0 };
+        let distinct = (0..4).map(|_| file(None)).collect();
+        assert_eq!(race(distinct, Some(quota), false).await, 1);
+
+        sqlx::query("DELETE FROM users WHERE id = $1").bind(user.id).execute(&pool).await.unwrap();
+    }
+
+    #[test]
+    fn test_content_disposition_strips_quotes_and_controls() {
+        assert_eq!(content_disposition("scan.png"), "attachment; filename=\"scan.png\"");
+        assert_eq!(content_disposition("a\"b\\c\r\nd.pdf"), "attachment; filename=\"abcd.pdf\"");
+    }
+
+    #[test]
+    fn test_text_preview_collapses_whitespace_and_cuts_at_word() {
+        assert_eq!(text_preview("  Patient   report\n\npage 1 "), "Patient report page 1");
+
+        let long = "word ".repeat(200);
+        let preview = text_preview(&long);
+        assert!(preview.ends_with("word…"));
+        assert!(preview.chars().count() <= TEXT_PREVIEW_CHARS + 1);
+
+        // A single unbroken run is still cut to the limit
+        assert_eq!(text_preview(&"x".repeat(600)).chars().count(), TEXT_PREVIEW_CHARS + 1);
+    }
+}
@@ -1,68 +1,245 @@
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
 use bytes::Bytes;
+use serde::Deserialize;
+use std::path::Path;
 
-const MAX_FILE_SIZE: usize = 50 * 1024 * 1024; // 50MB
-const ALLOWED_EXTENSIONS: &[&str] = &["pdf", "jpg", "jpeg", "png", "dcm"];
+use crate::config::Lookup;
 
-pub fn validate_file(file_name: &str, file_data: &Bytes) -> Result<()> {
+pub const MAX_FILE_SIZE: usize = 50 * 1024 * 1024; // 50MB
+
+/// Internal type of zip uploads, which are unpacked rather than processed
+pub const ARCHIVE: &str = "archive";
+
+/// Declared types that say nothing about the content; they never veto a match
+const GENERIC_MIME_TYPES: &[&str] = &["", "application/octet-stream", "binary/octet-stream"];
+
+/// One accepted upload format: the extensions it may carry, the MIME types a
+/// client may declare for it, the leading bytes its content must start with,
+/// and the internal type that picks its processing path
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FileTypeRule {
+    /// Internal type: "pdf" and "image" are processed, "archive" is unpacked
+    pub file_type: String,
+    pub extensions: Vec<String>,
+    pub mime_types: Vec<String>,
+    /// Hex signature the content must contain at `magic_offset`; unset skips the check
+    #[serde(default)]
+    pub magic: Option<String>,
+    #[serde(default)]
+    pub magic_offset: usize,
+}
+
+impl FileTypeRule {
+    fn new(file_type: &str, extensions: &[&str], mime_types: &[&str], magic: &str, magic_offset: usize) -> Self {
+        Self {
+            file_type: file_type.to_string(),
+            extensions: extensions.iter().map(|e| e.to_string()).collect(),
+            mime_types: mime_types.iter().map(|m| m.to_string()).collect(),
+            magic: Some(magic.to_string()),
+            magic_offset,
+        }
+    }
+
+    fn matches_magic(&self, data: &[u8]) -> bool {
+        let Some(magic) = self.magic.as_deref().and_then(decode_hex) else {
+            return true;
+        };
+        data.get(self.magic_offset..self.magic_offset + magic.len()) == Some(magic.as_slice())
+    }
+}
+
+/// Which uploads are accepted and what they are, shared by validation and
+/// routing. Loaded from `FILE_TYPE_POLICY_PATH` (a JSON array of `FileTypeRule`)
+/// or built in: PDF, JPEG, PNG, DICOM and zip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileTypePolicy {
+    rules: Vec<FileTypeRule>,
+}
+
+impl Default for FileTypePolicy {
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                FileTypeRule::new("pdf", &["pdf"], &["application/pdf"], "25504446", 0),
+                FileTypeRule::new("image", &["jpg", "jpeg"], &["image/jpeg", "image/jpg", "image/pjpeg"], "ffd8ff", 0),
+                FileTypeRule::new("image", &["png"], &["image/png"], "89504e470d0a1a0a", 0),
+                FileTypeRule::new("dicom", &["dcm"], &["application/dicom"], "4449434d", 128),
+                FileTypeRule::new(ARCHIVE, &["zip"], &["application/zip", "application/x-zip-compressed"], "504b", 0),
+            ],
+        }
+    }
+}
+
+impl FileTypePolicy {
+    pub fn new(rules: Vec<FileTypeRule>) -> Result<Self> {
+        if rules.is_empty() {
+            bail!("File type policy must allow at least one type");
+        }
+        for rule in &rules {
+            if rule.extensions.is_empty() {
+                bail!("File type rule for '{}' lists no extensions", rule.file_type);
+            }
+            if let Some(magic) = &rule.magic
+                && decode_hex(magic).is_none()
+            {
+                bail!("File type rule for '{}' has invalid hex magic '{}'", rule.file_type, magic);
+            }
+        }
+
+        let rules = rules
+            .into_iter()
+            .map(|rule| FileTypeRule {
+                extensions: rule.extensions.iter().map(|e| e.trim_start_matches('.').to_lowercase()).collect(),
+                mime_types: rule.mime_types.iter().map(|m| m.to_lowercase()).collect(),
+                ..rule
+            })
+            .collect();
+        Ok(Self { rules })
+    }
+
+    pub fn from_lookup(lookup: Lookup<'_>) -> Result<Self> {
+        let Some(path) = lookup("FILE_TYPE_POLICY_PATH") else {
+            return Ok(Self::default());
+        };
+        Self::load(Path::new(&path))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read file type policy {:?}", path))?;
+        let rules: Vec<FileTypeRule> = serde_json::from_str(&content)
+            .with_context(|| format!("File type policy {:?} must be a JSON array of rules", path))?;
+        Self::new(rules)
+    }
+
+    pub fn rules(&self) -> &[FileTypeRule] {
+        &self.rules
+    }
+
+    /// Comma-separated allowed extensions, for error messages
+    fn allowed_extensions(&self) -> String {
+        self.rules.iter().flat_map(|r| &r.extensions).map(String::as_str).collect::<Vec<_>>().join(", ")
+    }
+
+    /// Reject names whose extension no rule allows, before any bytes arrive
+    pub fn check_extension(&self, file_name: &str) -> Result<()> {
+        let extension = extension(file_name);
+        if !self.rules.iter().any(|r| r.extensions.contains(&extension)) {
+            bail!("File type .{} not supported. Allowed: {}", extension, self.allowed_extensions());
+        }
+        Ok(())
+    }
+
+    /// Internal type of an upload whose extension, declared MIME type and content
+    /// all match one rule. A generic declared type (octet-stream) matches any rule.
+    pub fn classify(&self, file_name: &str, content_type: &str, data: &[u8]) -> Result<&str> {
+        self.check_extension(file_name)?;
+        let extension = extension(file_name);
+        let mime = content_type.split(';').next().unwrap_or("").trim().to_lowercase();
+
+        let candidates: Vec<&FileTypeRule> = self.rules.iter().filter(|r| r.extensions.contains(&extension)).collect();
+        let declared: Vec<&FileTypeRule> = candidates
+            .iter()
+            .copied()
+            .filter(|r| GENERIC_MIME_TYPES.contains(&mime.as_str()) || r.mime_types.contains(&mime))
+            .collect();
+        if declared.is_empty() {
+            let expected: Vec<&str> = candidates.iter().flat_map(|r| &r.mime_types).map(String::as_str).collect();
+            bail!("Declared type {} does not match .{} (expected {})", mime, extension, expected.join(" or "));
+        }
+
+        declared
+            .iter()
+            .find(|r| r.matches_magic(data))
+            .map(|r| r.file_type.as_str())
+            .with_context(|| format!("Content does not look like a .{} file", extension))
+    }
+}
+
+/// Lowercased text after the last dot (the whole name when there is none)
+fn extension(file_name: &str) -> String {
+    file_name.rsplit('.').next().unwrap_or("").to_lowercase()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Check size limits and classify the file under `policy`, returning its internal type
+pub fn validate_file(policy: &FileTypePolicy, file_name: &str, content_type: &str, file_data: &Bytes) -> Result<String> {
     // Check file size
     if file_data.len() > MAX_FILE_SIZE {
         bail!("File size exceeds maximum allowed size of 50MB");
     }
-    
+
     if file_data.is_empty() {
         bail!("File is empty");
     }
-    
-    // Check file extension
-    let extension = file_name
-        .split('.')
-        .last()
-        .unwrap_or("")
-        .to_lowercase();
-    
-    if !ALLOWED_EXTENSIONS.contains(&extension.as_str()) {
-        bail!("File type not supported. Allowed: PDF, JPG, PNG, DICOM");
-    }
-    
-    Ok(())
-}
 
-pub fn determine_file_type(mime_type: &str) -> String {
-    if mime_type.contains("pdf") {
-        "pdf".to_string()
-    } else if mime_type.contains("image") {
-        "image".to_string()
-    } else {
-        "unknown".to_string()
-    }
+    policy.classify(file_name, content_type, file_data).map(str::to_string)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const PDF: &[u8] = b"%PDF-1.7\n";
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
     #[test]
     fn test_validate_file_size() {
-        let data = Bytes::from(vec![0u8; 100]);
-        assert!(validate_file("test.pdf", &data).is_ok());
-        
-        let large_data = Bytes::from(vec![0u8; MAX_FILE_SIZE + 1]);
-        assert!(validate_file("test.pdf", &large_data).is_err());
+        let policy = FileTypePolicy::default();
+        let data = Bytes::from_static(PDF);
+        assert_eq!(validate_file(&policy, "test.pdf", "application/pdf", &data).unwrap(), "pdf");
+
+        let mut large = PDF.to_vec();
+        large.resize(MAX_FILE_SIZE + 1, 0);
+        assert!(validate_file(&policy, "test.pdf", "application/pdf", &Bytes::from(large)).is_err());
+        assert!(validate_file(&policy, "test.pdf", "application/pdf", &Bytes::new()).is_err());
     }
 
     #[test]
-    fn test_validate_extension() {
-        let data = Bytes::from(vec![0u8; 100]);
-        assert!(validate_file("test.pdf", &data).is_ok());
-        assert!(validate_file("test.jpg", &data).is_ok());
-        assert!(validate_file("test.exe", &data).is_err());
+    fn test_classify_requires_extension_mime_and_magic_to_agree() {
+        let policy = FileTypePolicy::default();
+
+        assert_eq!(policy.classify("scan.PNG", "image/png", PNG).unwrap(), "image");
+        assert_eq!(policy.classify("scan.png", "application/octet-stream", PNG).unwrap(), "image");
+        assert_eq!(policy.classify("photo.jpg", "image/jpeg; charset=binary", b"\xff\xd8\xff\xe0").unwrap(), "image");
+        assert_eq!(policy.classify("set.zip", "application/zip", b"PK\x03\x04").unwrap(), ARCHIVE);
+
+        let mut dicom = vec![0u8; 128];
+        dicom.extend_from_slice(b"DICM");
+        assert_eq!(policy.classify("ct.dcm", "application/dicom", &dicom).unwrap(), "dicom");
+
+        let error = policy.classify("setup.exe", "application/octet-stream", b"MZ").unwrap_err();
+        assert_eq!(error.to_string(), "File type .exe not supported. Allowed: pdf, jpg, jpeg, png, dcm, zip");
+
+        let error = policy.classify("scan.png", "application/pdf", PNG).unwrap_err();
+        assert_eq!(error.to_string(), "Declared type application/pdf does not match .png (expected image/png)");
+
+        let error = policy.classify("report.pdf", "application/pdf", PNG).unwrap_err();
+        assert_eq!(error.to_string(), "Content does not look like a .pdf file");
     }
 
     #[test]
-    fn test_determine_file_type() {
-        assert_eq!(determine_file_type("application/pdf"), "pdf");
-        assert_eq!(determine_file_type("image/jpeg"), "image");
-        assert_eq!(determine_file_type("image/png"), "image");
+    fn test_policy_rules_from_json() {
+        let rules: Vec<FileTypeRule> = serde_json::from_str(
+            r#"[{"file_type": "text", "extensions": [".TXT"], "mime_types": ["Text/Plain"]}]"#,
+        )
+        .unwrap();
+        let policy = FileTypePolicy::new(rules).unwrap();
+
+        assert_eq!(policy.classify("notes.txt", "text/plain", b"anything").unwrap(), "text");
+        assert!(policy.classify("report.pdf", "application/pdf", PDF).is_err());
+
+        assert!(FileTypePolicy::new(vec![]).is_err());
+        let bad_magic = FileTypeRule { magic: Some("zz".to_string()), ..FileTypeRule::new("pdf", &["pdf"], &[], "00", 0) };
+        assert!(FileTypePolicy::new(vec![bad_magic]).is_err());
     }
 }
@@ -1,63 +1,110 @@
 use anyhow::{Result, Context};
-use std::path::Path;
+use std::path::PathBuf;
 
-use crate::processing::{OrphanetProcessor};
-use crate::embeddings::LocalEmbeddingService;
+use crate::config::{Lookup, flag, parse_or};
+
+use crate::processing::{HpoWeighting, OrphanetDisorder, OrphanetProcessor};
+use crate::embeddings::EmbeddingProvider;
+use crate::health::{KnowledgeBaseState, Readiness};
 use crate::rag::vector_store::{RagVectorStore, DocumentMetadata};
 
-/// Load Orphanet dataset into the vector store
+/// Disorders embedded per `embed_batch` call unless `ORPHANET_BATCH_SIZE` is set
+const DEFAULT_BATCH_SIZE: usize = 50;
+
+/// Load the configured Orphanet dataset into the vector store, reporting progress
+/// through `readiness`. Failures are left for the caller to record.
+///
+/// With `dry_run` the dataset is parsed and summarized (see `DryRunSummary`) but
+/// nothing is embedded or written, even if Orphanet is already loaded; the count
+/// returned is what a real run would load.
 pub async fn load_orphanet_data(
     vector_store: &RagVectorStore,
-    embedding_service: &LocalEmbeddingService,
-    dataset_path: &Path,
-    limit: Option<usize>,
+    embedding_service: &dyn EmbeddingProvider,
+    readiness: &Readiness,
+    config: &OrphanetConfig,
+    dry_run: bool,
 ) -> Result<usize> {
-    tracing::info!("Starting Orphanet dataset loading from {:?}", dataset_path);
+    let OrphanetConfig { dataset_paths, limit, min_hpo_associations, batch_size, weighting, .. } = config;
+    let (batch_size, weighting) = (*batch_size, *weighting);
+    anyhow::ensure!(batch_size > 0, "Orphanet batch size must be positive");
+
+    tracing::info!(
+        "Starting Orphanet dataset loading from {:?} (batch size {}, HPO weighting {:?})",
+        dataset_paths,
+        batch_size,
+        weighting
+    );
     
-    // Check if Orphanet data already exists in MongoDB
+    if dry_run {
+        let processor = OrphanetProcessor::new(*limit).with_min_hpo_associations(*min_hpo_associations);
+        let disorders = processor.parse_products(dataset_paths)
+            .context("Failed to parse Orphanet XML")?;
+        DryRunSummary::new(&disorders, embedding_service.max_input_chars(), weighting).log();
+        return Ok(disorders.len());
+    }
+
+    // Check if Orphanet data already exists (indexed lookup on source_type)
     let existing_count = vector_store.count_by_source("orphadata").await?;
     if existing_count > 0 {
         tracing::info!(
             "✓ Orphanet data already loaded ({} disorders), skipping re-processing",
             existing_count
         );
+        readiness.set_knowledge_base(KnowledgeBaseState::Complete);
         return Ok(existing_count);
     }
     
     tracing::info!("No existing Orphanet data found, loading fresh...");
+    readiness.set_knowledge_base(KnowledgeBaseState::Loading { done: 0, total: 0 });
     
-    // Parse XML
-    let processor = OrphanetProcessor::new(limit);
-    let disorders = processor.parse_xml(dataset_path)
+    // Parse XML, merging products so each disorder gets one vector
+    let processor = OrphanetProcessor::new(*limit).with_min_hpo_associations(*min_hpo_associations);
+    let mut disorders = processor.parse_products(dataset_paths)
         .context("Failed to parse Orphanet XML")?;
+
+    // Excluded conditions could never be returned, so don't pay to embed them
+    let exclusions = vector_store.exclusions();
+    let parsed = disorders.len();
+    disorders.retain(|d| !exclusions.is_excluded(Some(&d.orpha_code)));
+    if disorders.len() < parsed {
+        tracing::info!("Skipped {} excluded Orphanet disorders", parsed - disorders.len());
+    }
     
     tracing::info!("Parsed {} disorders, generating embeddings...", disorders.len());
+    readiness.set_knowledge_base(KnowledgeBaseState::Loading { done: 0, total: disorders.len() });
     
-    // Convert to embedable texts
-    let _texts: Vec<String> = disorders
-        .iter()
-        .map(|d| d.to_embedable_text())
-        .collect();
+    // Long HPO lists don't fit the embedding window; their tail terms won't influence search
+    if let Some(max_chars) = embedding_service.max_input_chars() {
+        let over_limit = over_input_limit(&disorders, max_chars, weighting);
+        if !over_limit.is_empty() {
+            tracing::warn!(
+                "{} of {} disorders exceed the {}-char embedding input limit; trailing HPO terms will be truncated (e.g. Orpha {})",
+                over_limit.len(),
+                disorders.len(),
+                max_chars,
+                over_limit.iter().take(5).copied().collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
     
-    // Generate embeddings in batches to avoid memory issues
-    const BATCH_SIZE: usize = 50;
+    // Generate embeddings in batches to bound peak memory (and mutex hold time for the local model)
     let mut total_added = 0;
     
-    for (batch_idx, chunk) in disorders.chunks(BATCH_SIZE).enumerate() {
+    for (batch_idx, chunk) in disorders.chunks(batch_size).enumerate() {
         let batch_texts: Vec<String> = chunk
             .iter()
-            .map(|d| d.to_embedable_text())
+            .map(|d| d.to_weighted_text(weighting))
             .collect();
         
         tracing::info!(
             "Processing batch {}/{} ({} disorders)...",
             batch_idx + 1,
-            (disorders.len() + BATCH_SIZE - 1) / BATCH_SIZE,
+            disorders.len().div_ceil(batch_size),
             batch_texts.len()
         );
         
         // Generate embeddings for this batch
-        let embeddings = embedding_service.embed_batch(batch_texts.clone())
+        let embeddings = embedding_service.embed_for_storage(batch_texts.clone())
             .await
             .context("Failed to generate batch embeddings")?;
         
@@ -69,6 +116,11 @@ pub async fn load_orphanet_data(
                 source_id: disorder.orpha_code.clone(),
                 file_name: None,
                 orpha_code: Some(disorder.orpha_code.clone()),
+                hpo_associations: disorder.hpo_associations.clone(),
+                start_offset: None,
+                end_offset: None,
+                modality: None,
+                region: None,
             };
             
             vector_store.add_document(
@@ -80,6 +132,7 @@ pub async fn load_orphanet_data(
             
             total_added += 1;
         }
+        readiness.set_knowledge_base(KnowledgeBaseState::Loading { done: total_added, total: disorders.len() });
         
         tracing::info!(
             "Batch {} complete. Total processed: {}/{}",
@@ -93,26 +146,171 @@ pub async fn load_orphanet_data(
         "✓ Successfully loaded {} Orphanet disorders into vector store",
         total_added
     );
+    readiness.set_knowledge_base(KnowledgeBaseState::Complete);
     
     Ok(total_added)
 }
 
-/// Load Orphanet data with configuration from environment variables
-pub async fn load_orphanet_from_env(
-    vector_store: &RagVectorStore,
-    embedding_service: &LocalEmbeddingService,
-) -> Result<usize> {
-    let dataset_path = std::env::var("ORPHANET_DATASET_PATH")
-        .unwrap_or_else(|_| "dataset/en_product4.xml".to_string());
-    
-    let limit = std::env::var("ORPHANET_LIMIT")
-        .ok()
-        .and_then(|s| s.parse::<usize>().ok());
-    
-    load_orphanet_data(
-        vector_store,
-        embedding_service,
-        Path::new(&dataset_path),
-        limit,
-    ).await
+/// Orpha codes of disorders whose embedded text exceeds `max_chars`
+fn over_input_limit(disorders: &[OrphanetDisorder], max_chars: usize, weighting: HpoWeighting) -> Vec<&str> {
+    disorders
+        .iter()
+        .filter(|d| d.to_weighted_text(weighting).chars().count() > max_chars)
+        .map(|d| d.orpha_code.as_str())
+        .collect()
+}
+
+/// What a load would embed, and data problems worth fixing first (`ORPHANET_DRY_RUN`)
+#[derive(Debug, Default, PartialEq)]
+pub struct DryRunSummary {
+    pub disorders: usize,
+    pub hpo_associations: usize,
+    pub min_hpo: usize,
+    pub max_hpo: usize,
+    /// Disorders parsed without a name
+    pub unnamed: usize,
+    /// Associations missing an HPO id, term or frequency
+    pub incomplete_associations: usize,
+    /// Disorders whose text would be truncated by the embedding input limit
+    pub over_input_limit: usize,
+}
+
+impl DryRunSummary {
+    pub fn new(disorders: &[OrphanetDisorder], max_input_chars: Option<usize>, weighting: HpoWeighting) -> Self {
+        let counts = disorders.iter().map(|d| d.hpo_associations.len());
+
+        Self {
+            disorders: disorders.len(),
+            hpo_associations: counts.clone().sum(),
+            min_hpo: counts.clone().min().unwrap_or(0),
+            max_hpo: counts.max().unwrap_or(0),
+            unnamed: disorders.iter().filter(|d| d.name.is_empty()).count(),
+            incomplete_associations: disorders
+                .iter()
+                .flat_map(|d| &d.hpo_associations)
+                .filter(|a| a.hpo_id.is_empty() || a.hpo_term.is_empty() || a.frequency.is_empty())
+                .count(),
+            over_input_limit: max_input_chars.map_or(0, |max| over_input_limit(disorders, max, weighting).len()),
+        }
+    }
+
+    pub fn log(&self) {
+        let mean = self.hpo_associations as f64 / self.disorders.max(1) as f64;
+        tracing::info!(
+            "Orphanet dry run (nothing embedded or stored):\n\
+             \x20 disorders              {:>8}\n\
+             \x20 HPO associations       {:>8}\n\
+             \x20 HPO per disorder       {:>8} min / {:.1} mean / {} max\n\
+             \x20 unnamed disorders      {:>8}\n\
+             \x20 incomplete HPO entries {:>8}\n\
+             \x20 over embedding limit   {:>8}",
+            self.disorders,
+            self.hpo_associations,
+            self.min_hpo,
+            mean,
+            self.max_hpo,
+            self.unnamed,
+            self.incomplete_associations,
+            self.over_input_limit,
+        );
+        if self.unnamed > 0 || self.incomplete_associations > 0 {
+            tracing::warn!(
+                "Orphanet dry run found {} unnamed disorders and {} incomplete HPO associations",
+                self.unnamed,
+                self.incomplete_associations
+            );
+        }
+    }
+}
+
+/// Orphanet dataset settings, loaded once into `Config`
+#[derive(Debug, Clone)]
+pub struct OrphanetConfig {
+    /// Load the dataset at startup (`LOAD_ORPHANET`)
+    pub load: bool,
+    /// Product files to load (`ORPHANET_DATASET_PATH`, comma-separated); disorders
+    /// appearing in several are merged by Orpha code
+    pub dataset_paths: Vec<PathBuf>,
+    pub limit: Option<usize>,
+    /// Skip disorders with fewer HPO associations (`ORPHANET_MIN_HPO`)
+    pub min_hpo_associations: usize,
+    pub batch_size: usize,
+    /// Hold `/health/ready` at 503 until the load completes (`READY_REQUIRES_KNOWLEDGE_BASE`)
+    pub gate_readiness: bool,
+    /// Emphasis given to frequent HPO signs in the embedded text (`HPO_WEIGHTING`)
+    pub weighting: HpoWeighting,
+    /// Parse and summarize the dataset without embedding or storing it (`ORPHANET_DRY_RUN`)
+    pub dry_run: bool,
+    /// Leave Orphanet out entirely (`SKIP_ORPHANET`): nothing is loaded, whatever
+    /// `LOAD_ORPHANET` says, and chat searches uploaded files only
+    pub skip: bool,
+}
+
+impl OrphanetConfig {
+    pub fn from_lookup(lookup: Lookup<'_>) -> Result<Self> {
+        let batch_size = match lookup("ORPHANET_BATCH_SIZE") {
+            Some(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n > 0)
+                .with_context(|| format!("ORPHANET_BATCH_SIZE must be a positive integer, got '{}'", value))?,
+            None => DEFAULT_BATCH_SIZE,
+        };
+
+        let skip = flag(lookup, "SKIP_ORPHANET", false);
+        Ok(Self {
+            load: !skip && flag(lookup, "LOAD_ORPHANET", false),
+            dataset_paths: lookup("ORPHANET_DATASET_PATH")
+                .unwrap_or_else(|| "dataset/en_product4.xml".to_string())
+                .split(',')
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                .collect(),
+            limit: lookup("ORPHANET_LIMIT").and_then(|s| s.parse::<usize>().ok()),
+            min_hpo_associations: parse_or(lookup, "ORPHANET_MIN_HPO", 1),
+            batch_size,
+            gate_readiness: flag(lookup, "READY_REQUIRES_KNOWLEDGE_BASE", false),
+            weighting: HpoWeighting::parse(lookup("HPO_WEIGHTING")),
+            dry_run: !skip && flag(lookup, "ORPHANET_DRY_RUN", false),
+            skip,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skip_overrides_load_and_dry_run() {
+        let config = OrphanetConfig::from_lookup(&|name| match name {
+            "SKIP_ORPHANET" | "LOAD_ORPHANET" | "ORPHANET_DRY_RUN" => Some("true".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert!(config.skip);
+        assert!(!config.load);
+        assert!(!config.dry_run);
+    }
+
+    #[test]
+    fn test_dry_run_summary_counts_problems() {
+        let xml = include_str!("../fixtures/orphanet_product4_sample.xml");
+        let mut disorders = OrphanetProcessor::new(None).parse_str(xml);
+        assert!(!disorders.is_empty());
+        disorders[0].name.clear();
+        disorders[0].hpo_associations[0].frequency.clear();
+
+        let summary = DryRunSummary::new(&disorders, Some(10), HpoWeighting::Off);
+        assert_eq!(summary.disorders, disorders.len());
+        assert_eq!(summary.hpo_associations, disorders.iter().map(|d| d.hpo_associations.len()).sum::<usize>());
+        assert!(summary.min_hpo >= 1 && summary.min_hpo <= summary.max_hpo);
+        assert_eq!(summary.unnamed, 1);
+        assert_eq!(summary.incomplete_associations, 1);
+        assert_eq!(summary.over_input_limit, disorders.len());
+
+        assert_eq!(DryRunSummary::new(&disorders, None, HpoWeighting::Off).over_input_limit, 0);
+        assert_eq!(DryRunSummary::new(&[], None, HpoWeighting::Off), DryRunSummary::default());
+    }
 }
@@ -0,0 +1,213 @@
+/// Window size and overlap (in characters) for splitting extracted text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkConfig {
+    pub size: usize,
+    pub overlap: usize,
+}
+
+impl ChunkConfig {
+    pub const DEFAULT: ChunkConfig = ChunkConfig { size: 1500, overlap: 200 };
+
+    /// Keeps windows advancing: size at least 1, overlap below size
+    pub fn new(size: usize, overlap: usize) -> Self {
+        let size = size.max(1);
+        Self { size, overlap: overlap.min(size - 1) }
+    }
+
+    /// Characters the window moves forward each step
+    pub fn step(&self) -> usize {
+        self.size - self.overlap
+    }
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Document subtype detected from extracted text, each with its own chunking
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentKind {
+    /// Tabular results: short, dense lines that retrieve best in small chunks
+    LabReport,
+    /// Discharge summaries, clinical letters: prose that needs context in each chunk
+    Narrative,
+    Other,
+}
+
+impl DocumentKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::LabReport => "lab_report",
+            Self::Narrative => "narrative",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Keyword hits a subtype needs before it is picked
+const MIN_KEYWORD_HITS: usize = 2;
+
+/// Leading characters searched for keywords; headers and the first sections decide
+const CLASSIFY_SCAN_CHARS: usize = 5000;
+
+const LAB_REPORT_KEYWORDS: &[&str] = &[
+    "reference range", "reference interval", "result", "units", "specimen",
+    "mg/dl", "mmol/l", "g/dl", "μmol/l", "hemoglobin", "haemoglobin", "platelets", "creatinine",
+];
+
+const NARRATIVE_KEYWORDS: &[&str] = &[
+    "discharge summary", "history of present illness", "hospital course", "impression",
+    "assessment and plan", "chief complaint", "past medical history", "dear dr",
+];
+
+/// Keyword rule for one subtype
+#[derive(Debug, Clone)]
+struct ChunkRule {
+    kind: DocumentKind,
+    keywords: Vec<String>,
+    config: ChunkConfig,
+}
+
+/// Picks a `ChunkConfig` per document by keyword heuristics. The subtype with the
+/// most distinct keyword hits (at least `MIN_KEYWORD_HITS`) wins; documents
+/// matching none use the default chunking.
+///
+/// Env: `CHUNK_SIZE`/`CHUNK_OVERLAP` (default), `LAB_REPORT_CHUNK_SIZE`/`_OVERLAP`/
+/// `_KEYWORDS` and `NARRATIVE_CHUNK_SIZE`/`_OVERLAP`/`_KEYWORDS` (comma-separated
+/// keywords; an empty list disables that subtype).
+#[derive(Debug, Clone)]
+pub struct ChunkRules {
+    rules: Vec<ChunkRule>,
+    default: ChunkConfig,
+}
+
+impl Default for ChunkRules {
+    fn default() -> Self {
+        Self::from_lookup(&|_| None)
+    }
+}
+
+impl ChunkRules {
+    pub fn from_env() -> Self {
+        Self::from_lookup(&|name| std::env::var(name).ok())
+    }
+
+    pub fn from_lookup(lookup: crate::config::Lookup<'_>) -> Self {
+        use crate::config::parse_or;
+
+        let config = |prefix: &str, fallback: ChunkConfig| {
+            ChunkConfig::new(
+                parse_or(lookup, &format!("{}CHUNK_SIZE", prefix), fallback.size),
+                parse_or(lookup, &format!("{}CHUNK_OVERLAP", prefix), fallback.overlap),
+            )
+        };
+        let keywords = |name: &str, fallback: &[&str]| -> Vec<String> {
+            match lookup(name) {
+                Some(list) => list
+                    .split(',')
+                    .map(|k| k.trim().to_lowercase())
+                    .filter(|k| !k.is_empty())
+                    .collect(),
+                None => fallback.iter().map(|k| k.to_string()).collect(),
+            }
+        };
+
+        let rules = vec![
+            ChunkRule {
+                kind: DocumentKind::LabReport,
+                keywords: keywords("LAB_REPORT_KEYWORDS", LAB_REPORT_KEYWORDS),
+                config: config("LAB_REPORT_", ChunkConfig { size: 500, overlap: 50 }),
+            },
+            ChunkRule {
+                kind: DocumentKind::Narrative,
+                keywords: keywords("NARRATIVE_KEYWORDS", NARRATIVE_KEYWORDS),
+                config: config("NARRATIVE_", ChunkConfig { size: 2000, overlap: 300 }),
+            },
+        ];
+
+        Self { rules, default: config("", ChunkConfig::DEFAULT) }
+    }
+
+    /// Subtype of `text`; ties go to the earlier rule
+    pub fn classify(&self, text: &str) -> DocumentKind {
+        let head: String = text.chars().take(CLASSIFY_SCAN_CHARS).collect::<String>().to_lowercase();
+
+        let mut best = (DocumentKind::Other, MIN_KEYWORD_HITS - 1);
+        for rule in &self.rules {
+            let hits = rule.keywords.iter().filter(|k| head.contains(k.as_str())).count();
+            if hits > best.1 {
+                best = (rule.kind, hits);
+            }
+        }
+        best.0
+    }
+
+    pub fn config_for(&self, kind: DocumentKind) -> ChunkConfig {
+        self.rules
+            .iter()
+            .find(|rule| rule.kind == kind)
+            .map_or(self.default, |rule| rule.config)
+    }
+
+    /// Classification and chunking for `text`
+    pub fn select(&self, text: &str) -> (DocumentKind, ChunkConfig) {
+        let kind = self.classify(text);
+        (kind, self.config_for(kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn rules(vars: &[(&str, &str)]) -> ChunkRules {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        ChunkRules::from_lookup(&|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_classify_by_keyword_hits() {
+        let rules = ChunkRules::default();
+
+        let lab = "CBC panel\nHemoglobin 10.1 g/dL  Reference range 12-16\nPlatelets 140";
+        assert_eq!(rules.classify(lab), DocumentKind::LabReport);
+
+        let letter = "DISCHARGE SUMMARY\nHistory of present illness: progressive weakness...\nHospital course: ...";
+        assert_eq!(rules.classify(letter), DocumentKind::Narrative);
+
+        // A single stray keyword isn't enough
+        assert_eq!(rules.classify("The result was reassuring."), DocumentKind::Other);
+        assert_eq!(rules.classify(""), DocumentKind::Other);
+    }
+
+    #[test]
+    fn test_select_applies_the_subtype_config() {
+        let rules = rules(&[
+            ("CHUNK_SIZE", "1000"),
+            ("LAB_REPORT_CHUNK_SIZE", "300"),
+            ("LAB_REPORT_CHUNK_OVERLAP", "500"),
+            ("NARRATIVE_KEYWORDS", "clinic letter, dear colleague"),
+        ]);
+
+        let (kind, config) = rules.select("Specimen: serum. Creatinine 1.2 mg/dL");
+        assert_eq!(kind, DocumentKind::LabReport);
+        // Overlap is clamped below the window size
+        assert_eq!(config, ChunkConfig { size: 300, overlap: 299 });
+
+        let (kind, config) = rules.select("Clinic letter\nDear colleague, ...");
+        assert_eq!(kind, DocumentKind::Narrative);
+        assert_eq!(config, ChunkConfig { size: 2000, overlap: 300 });
+
+        // The built-in narrative keywords were replaced
+        assert_eq!(rules.select("Discharge summary. Hospital course").1, ChunkConfig { size: 1000, overlap: 200 });
+    }
+
+    #[test]
+    fn test_empty_keyword_list_disables_subtype() {
+        let rules = rules(&[("LAB_REPORT_KEYWORDS", "")]);
+        assert_eq!(rules.classify("Hemoglobin 10 g/dL, reference range 12-16"), DocumentKind::Other);
+    }
+}
@@ -1,41 +1,113 @@
 use anyhow::{Result, Context};
 use bytes::Bytes;
+use serde::Deserialize;
 use std::sync::Arc;
-use crate::embeddings::LocalEmbeddingService;
+use crate::embeddings::EmbeddingProvider;
 
 pub struct ImageProcessor {
-    embedding_service: Arc<LocalEmbeddingService>,
+    embedding_service: Arc<dyn EmbeddingProvider>,
+    /// Embed only the findings, leaving the modality/region boilerplate (kept as
+    /// metadata) out of the vector (`IMAGE_EMBED_FINDINGS_ONLY`)
+    embed_findings_only: bool,
+}
+
+/// Structured Vision output. The Vision prompt must ask for exactly this JSON:
+/// `{"modality": "MRI", "region": "brain", "findings": "..."}`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ImageAnalysis {
+    #[serde(default)]
+    pub modality: Option<String>,
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default)]
+    pub findings: String,
+}
+
+impl ImageAnalysis {
+    /// Parse a Vision reply: the first JSON object in it, fenced or not. A reply
+    /// without a usable object is kept whole as the findings, so a model that
+    /// answers in prose still yields a searchable document.
+    pub fn parse(response: &str) -> Self {
+        let object = response
+            .find('{')
+            .zip(response.rfind('}'))
+            .filter(|(start, end)| start < end)
+            .and_then(|(start, end)| serde_json::from_str::<ImageAnalysis>(&response[start..=end]).ok());
+
+        let mut analysis = object.unwrap_or_else(|| {
+            tracing::warn!("Vision reply is not the expected JSON, storing it as findings");
+            ImageAnalysis { findings: response.to_string(), ..Default::default() }
+        });
+        analysis.modality = known(analysis.modality);
+        analysis.region = known(analysis.region);
+        analysis.findings = analysis.findings.trim().to_string();
+        analysis
+    }
+
+    /// Stored document text: every field, one per line
+    pub fn describe(&self) -> String {
+        format!(
+            "Medical Image Analysis:\nModality: {}\nRegion: {}\nFindings: {}",
+            self.modality.as_deref().unwrap_or("Unknown"),
+            self.region.as_deref().unwrap_or("Unknown"),
+            self.findings,
+        )
+    }
+}
+
+/// Blank and "unknown" fields carry nothing worth filtering on
+fn known(field: Option<String>) -> Option<String> {
+    field
+        .map(|f| f.trim().to_string())
+        .filter(|f| !f.is_empty() && !f.eq_ignore_ascii_case("unknown"))
+}
+
+/// An analyzed image ready to store
+#[derive(Debug, Clone)]
+pub struct ProcessedImage {
+    pub description: String,
+    pub analysis: ImageAnalysis,
+    pub embedding: Vec<f32>,
 }
 
 impl ImageProcessor {
-    pub fn new(embedding_service: Arc<LocalEmbeddingService>) -> Result<Self> {
-        Ok(Self { embedding_service })
+    pub fn new(embedding_service: Arc<dyn EmbeddingProvider>) -> Result<Self> {
+        let embed_findings_only = std::env::var("IMAGE_EMBED_FINDINGS_ONLY")
+            .map(|v| v.to_lowercase() == "true")
+            .unwrap_or(false);
+
+        Ok(Self { embedding_service, embed_findings_only })
     }
     
-    pub async fn process_image(&self, file_data: Bytes) -> Result<(String, Vec<f32>)> {
+    pub async fn process_image(&self, file_data: Bytes) -> Result<ProcessedImage> {
         // Generate clinical description (placeholder for now - needs Gemini Vision API)
-        let description = self.generate_clinical_description(&file_data).await?;
+        let analysis = ImageAnalysis::parse(&self.generate_clinical_description(&file_data).await?);
+        let description = analysis.describe();
         
-        // Generate embedding from description using local FastEmbed
-        let embedding = self.embedding_service.embed_text(&description).await?;
+        // Generate embedding from description using the shared provider
+        let embedded_text = if self.embed_findings_only && !analysis.findings.is_empty() {
+            &analysis.findings
+        } else {
+            &description
+        };
+        let embedding = self.embedding_service
+            .embed_for_storage(vec![embedded_text.clone()])
+            .await?
+            .pop()
+            .context("Embedding provider returned no vector")?;
         
-        Ok((description, embedding))
+        Ok(ProcessedImage { description, analysis, embedding })
     }
     
     async fn generate_clinical_description(&self, _image_data: &Bytes) -> Result<String> {
-        // TODO: Implement actual Gemini Vision API call for image analysis
-        // For now, return a placeholder description
+        // TODO: Implement actual Gemini Vision API call for image analysis, asking
+        // for the `ImageAnalysis` JSON. For now, return a placeholder reply
         tracing::warn!("Using mock image description - implement actual Gemini Vision API");
         
-        let mock_description = format!(
-            "Medical Image Analysis:\n\
-             Modality: Unknown (requires Vision API)\n\
-             Region: Unknown\n\
-             Findings: Image processing pending\n\
-             Note: This is a placeholder. Implement Gemini Vision API for actual image analysis."
-        );
+        let mock_response = r#"{"modality": null, "region": null, "findings": "Image processing pending. This is a placeholder; implement Gemini Vision API for actual image analysis."}"#
+            .to_string();
         
-        Ok(mock_description)
+        Ok(mock_response)
     }
     
     /// Validate that the image can be loaded
@@ -69,4 +141,37 @@ mod tests {
         // This will fail with minimal data, but tests the validation flow
         let _ = ImageProcessor::validate_image(&bytes);
     }
+
+    #[test]
+    fn test_parse_structured_reply() {
+        let reply = "```json\n{\"modality\": \" MRI \", \"region\": \"Brain\", \"findings\": \"T2 hyperintense lesions \"}\n```";
+        let analysis = ImageAnalysis::parse(reply);
+        assert_eq!(
+            analysis,
+            ImageAnalysis {
+                modality: Some("MRI".to_string()),
+                region: Some("Brain".to_string()),
+                findings: "T2 hyperintense lesions".to_string(),
+            }
+        );
+        assert_eq!(
+            analysis.describe(),
+            "Medical Image Analysis:\nModality: MRI\nRegion: Brain\nFindings: T2 hyperintense lesions"
+        );
+    }
+
+    #[test]
+    fn test_parse_tolerates_missing_fields_and_prose() {
+        let partial = ImageAnalysis::parse(r#"{"modality": "unknown", "findings": "Normal"}"#);
+        assert_eq!(partial.modality, None);
+        assert_eq!(partial.region, None);
+        assert!(partial.describe().contains("Modality: Unknown"));
+
+        let prose = ImageAnalysis::parse("  Chest X-ray without acute findings. ");
+        assert_eq!(prose.findings, "Chest X-ray without acute findings.");
+        assert_eq!(prose.modality, None);
+
+        let broken = ImageAnalysis::parse("{\"modality\": ");
+        assert_eq!(broken.findings, "{\"modality\":");
+    }
 }
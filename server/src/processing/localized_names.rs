@@ -0,0 +1,176 @@
+use anyhow::{Context, Result};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Language of the names in the Orphanet products the knowledge base is built from
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// A disorder's name and synonyms in one language
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalizedName {
+    pub name: String,
+    pub synonyms: Vec<String>,
+}
+
+/// Names for one language, keyed by `orpha_code`
+pub type LanguageNames = HashMap<String, LocalizedName>;
+
+/// Disorder names in other languages, read from Orphanet product files for those
+/// languages (`ORPHANET_NAME_FILES`, e.g. `fr_product1.xml`; any product with
+/// `Disorder/Name` works). Each file's language comes from its `lang` attributes.
+#[derive(Debug, Default)]
+pub struct LocalizedNames {
+    languages: HashMap<String, LanguageNames>,
+}
+
+impl LocalizedNames {
+    pub fn from_env() -> Result<Self> {
+        let paths: Vec<PathBuf> = std::env::var("ORPHANET_NAME_FILES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .collect();
+        Self::load(&paths)
+    }
+
+    pub fn load<P: AsRef<Path>>(paths: &[P]) -> Result<Self> {
+        let mut names = Self::default();
+        for path in paths {
+            let path = path.as_ref();
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read Orphanet names {:?}", path))?;
+            let (language, parsed) = parse_names(&content);
+            let language = language.with_context(|| format!("No lang attribute on disorder names in {:?}", path))?;
+            tracing::info!("Loaded {} Orphanet disorder names in '{}' from {:?}", parsed.len(), language, path);
+            names.languages.entry(language).or_default().extend(parsed);
+        }
+        Ok(names)
+    }
+
+    /// Names in `language`, or `None` when it wasn't loaded (callers fall back to English)
+    pub fn language(&self, language: &str) -> Option<&LanguageNames> {
+        self.languages.get(&language.to_lowercase())
+    }
+
+    pub fn get(&self, language: &str, orpha_code: &str) -> Option<&LocalizedName> {
+        self.language(language)?.get(orpha_code)
+    }
+}
+
+/// Disorder names and synonyms by code, with the language they are written in
+pub fn parse_names(content: &str) -> (Option<String>, LanguageNames) {
+    let mut reader = Reader::from_str(content);
+    let mut names = LanguageNames::new();
+    let mut language = None;
+
+    let mut code = String::new();
+    let mut current = LocalizedName { name: String::new(), synonyms: vec![] };
+    let mut stack: Vec<String> = Vec::new();
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) => {
+                let element = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                if element == "Name" && stack.last().is_some_and(|parent| parent == "Disorder") && language.is_none() {
+                    language = e
+                        .try_get_attribute("lang")
+                        .ok()
+                        .flatten()
+                        .and_then(|a| a.unescape_value().ok())
+                        .map(|lang| lang.to_lowercase());
+                }
+                if element == "Disorder" {
+                    code.clear();
+                    current = LocalizedName { name: String::new(), synonyms: vec![] };
+                }
+                stack.push(element);
+            }
+            Ok(Event::Text(e)) => {
+                let text = e.unescape().map(|t| t.trim().to_string()).unwrap_or_default();
+                if text.is_empty() {
+                    continue;
+                }
+                match stack.as_slice() {
+                    [.., parent, element] if parent == "Disorder" && element == "OrphaCode" => code = text,
+                    [.., parent, element] if parent == "Disorder" && element == "Name" => current.name = text,
+                    [.., disorder, list, element]
+                        if disorder == "Disorder" && list == "SynonymList" && element == "Synonym" =>
+                    {
+                        current.synonyms.push(text)
+                    }
+                    _ => {}
+                }
+            }
+            Ok(Event::End(_)) => {
+                let closed = stack.pop();
+                if closed.as_deref() == Some("Disorder")
+                    && !code.is_empty()
+                    && !current.name.is_empty()
+                {
+                    names.insert(std::mem::take(&mut code), current.clone());
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => {
+                tracing::warn!("Stopped reading Orphanet names at position {}: {}", reader.buffer_position(), e);
+                break;
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    (language, names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FR_PRODUCT: &str = r#"<JDBOR>
+      <DisorderList count="2">
+        <Disorder id="1">
+          <OrphaCode>58</OrphaCode>
+          <Name lang="fr">Maladie d'Alexander</Name>
+          <SynonymList count="1"><Synonym lang="fr">Leucodystrophie fibrinoïde</Synonym></SynonymList>
+          <DisorderType id="21"><Name lang="fr">Maladie</Name></DisorderType>
+        </Disorder>
+        <Disorder id="2">
+          <OrphaCode>324</OrphaCode>
+          <Name lang="fr">Maladie de Fabry</Name>
+        </Disorder>
+      </DisorderList>
+    </JDBOR>"#;
+
+    #[test]
+    fn test_parse_names_keeps_disorder_level_names() {
+        let (language, names) = parse_names(FR_PRODUCT);
+        assert_eq!(language.as_deref(), Some("fr"));
+        assert_eq!(names.len(), 2);
+        assert_eq!(
+            names["58"],
+            LocalizedName {
+                name: "Maladie d'Alexander".to_string(),
+                synonyms: vec!["Leucodystrophie fibrinoïde".to_string()],
+            }
+        );
+        assert_eq!(names["324"].name, "Maladie de Fabry");
+    }
+
+    #[test]
+    fn test_lookup_by_language() {
+        let path = std::env::temp_dir().join(format!("quwa-names-{}.xml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, FR_PRODUCT).unwrap();
+        let names = LocalizedNames::load(&[&path]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(names.get("FR", "324").map(|n| n.name.as_str()), Some("Maladie de Fabry"));
+        assert!(names.get("de", "324").is_none());
+        assert!(names.get("fr", "999").is_none());
+    }
+}
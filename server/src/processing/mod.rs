@@ -1,7 +1,9 @@
+pub mod chunking;
 pub mod pdf;
 pub mod image;
 pub mod orphanet;
+pub mod localized_names;
 
 pub use pdf::PdfProcessor;
 pub use image::ImageProcessor;
-pub use orphanet::{OrphanetProcessor, OrphanetDisorder};
+pub use orphanet::{OrphanetProcessor, OrphanetDisorder, HpoWeighting};
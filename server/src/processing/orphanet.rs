@@ -1,6 +1,8 @@
 use anyhow::{Result, Context};
 use quick_xml::events::Event;
 use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
 
 #[derive(Debug, Clone)]
@@ -10,25 +12,121 @@ pub struct OrphanetDisorder {
     pub hpo_associations: Vec<HPOAssociation>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HPOAssociation {
     pub hpo_id: String,
     pub hpo_term: String,
     pub frequency: String,
 }
 
+impl HPOAssociation {
+    /// Rank of the Orphanet frequency band (0 = obligate, higher = rarer).
+    /// Excluded signs rank last since the disorder explicitly lacks them.
+    pub fn frequency_rank(&self) -> u8 {
+        let freq = self.frequency.to_lowercase();
+        if freq.starts_with("obligate") {
+            0
+        } else if freq.starts_with("very frequent") {
+            1
+        } else if freq.starts_with("frequent") {
+            2
+        } else if freq.starts_with("occasional") {
+            3
+        } else if freq.starts_with("very rare") {
+            4
+        } else if freq.starts_with("excluded") {
+            6
+        } else {
+            5
+        }
+    }
+
+    /// Share of patients showing the sign, taken as the midpoint of its
+    /// Orphanet band (excluded and unknown bands weigh nothing)
+    pub fn frequency_weight(&self) -> f32 {
+        match self.frequency_rank() {
+            0 => 1.0,
+            1 => 0.9,
+            2 => 0.545,
+            3 => 0.17,
+            4 => 0.025,
+            _ => 0.0,
+        }
+    }
+}
+
+/// How HPO frequency shapes the embedded disorder text (`HPO_WEIGHTING`).
+/// Every association is always listed; weighting only adds emphasis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HpoWeighting {
+    /// Associations in dataset order, all equal
+    Off,
+    /// Prepend a "Key features" line naming the frequent-or-more signs
+    Summary,
+    /// Most frequent signs first, with the term repeated in proportion to its weight
+    Repeat,
+}
+
+impl HpoWeighting {
+    pub fn parse(value: Option<String>) -> Self {
+        match value
+            .unwrap_or_else(|| "off".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "summary" => HpoWeighting::Summary,
+            "repeat" => HpoWeighting::Repeat,
+            _ => HpoWeighting::Off,
+        }
+    }
+}
+
+/// Signs weighing at least this (the "Frequent" band and up) count as key features
+const KEY_FEATURE_MIN_WEIGHT: f32 = 0.5;
+
+/// Times the heaviest sign is written under `HpoWeighting::Repeat`
+const MAX_TERM_REPEATS: usize = 3;
+
 impl OrphanetDisorder {
     /// Convert disorder to embedable text format
     pub fn to_embedable_text(&self) -> String {
-        let mut text = format!(
-            "Disease: {} (Orpha: {})\n\nClinical Signs and Symptoms:\n",
-            self.name, self.orpha_code
-        );
+        self.to_weighted_text(HpoWeighting::Off)
+    }
+
+    /// Embedable text with frequent signs emphasised according to `weighting`
+    pub fn to_weighted_text(&self, weighting: HpoWeighting) -> String {
+        let mut text = format!("Disease: {} (Orpha: {})\n", self.name, self.orpha_code);
+
+        // Stable sort keeps dataset order within a band
+        let mut associations: Vec<&HPOAssociation> = self.hpo_associations.iter().collect();
+        if weighting != HpoWeighting::Off {
+            associations.sort_by_key(|a| a.frequency_rank());
+        }
+
+        if weighting == HpoWeighting::Summary {
+            let key_features: Vec<&str> = associations
+                .iter()
+                .filter(|a| a.frequency_weight() >= KEY_FEATURE_MIN_WEIGHT)
+                .map(|a| a.hpo_term.as_str())
+                .collect();
+            if !key_features.is_empty() {
+                text.push_str(&format!("Key features: {}\n", key_features.join(", ")));
+            }
+        }
+
+        text.push_str("\nClinical Signs and Symptoms:\n");
         
-        for assoc in &self.hpo_associations {
+        for assoc in associations {
+            let term = match weighting {
+                HpoWeighting::Repeat => {
+                    let repeats = 1 + (assoc.frequency_weight() * (MAX_TERM_REPEATS - 1) as f32).round() as usize;
+                    vec![assoc.hpo_term.as_str(); repeats].join(", ")
+                }
+                _ => assoc.hpo_term.clone(),
+            };
             text.push_str(&format!(
                 "- {} ({}) [{}]\n",
-                assoc.hpo_term, assoc.frequency, assoc.hpo_id
+                term, assoc.frequency, assoc.hpo_id
             ));
         }
         
@@ -36,35 +134,147 @@ impl OrphanetDisorder {
     }
 }
 
+impl OrphanetDisorder {
+    /// Fold another product's record of the same disorder into this one: a missing
+    /// name is filled in and HPO terms not yet listed are appended. For a term both
+    /// list, this record's frequency wins unless it has none.
+    pub fn merge(&mut self, other: OrphanetDisorder) {
+        if self.name.is_empty() {
+            self.name = other.name;
+        }
+        for assoc in other.hpo_associations {
+            match self.hpo_associations.iter_mut().find(|a| a.hpo_id == assoc.hpo_id) {
+                Some(existing) if existing.frequency.is_empty() => existing.frequency = assoc.frequency,
+                Some(_) => {}
+                None => self.hpo_associations.push(assoc),
+            }
+        }
+    }
+}
+
+/// Keyed accumulator that merges the disorders of several products into one
+/// record per `orpha_code`, so each disorder becomes a single vector document.
+/// Disorders keep the order in which their code was first seen.
+#[derive(Default)]
+pub struct DisorderMerger {
+    disorders: Vec<OrphanetDisorder>,
+    index: HashMap<String, usize>,
+    merged: usize,
+}
+
+impl DisorderMerger {
+    pub fn add(&mut self, product: Vec<OrphanetDisorder>) {
+        for disorder in product {
+            match self.index.get(&disorder.orpha_code) {
+                Some(&i) => {
+                    self.disorders[i].merge(disorder);
+                    self.merged += 1;
+                }
+                None => {
+                    self.index.insert(disorder.orpha_code.clone(), self.disorders.len());
+                    self.disorders.push(disorder);
+                }
+            }
+        }
+    }
+
+    /// Records folded into an earlier one with the same code
+    pub fn merged(&self) -> usize {
+        self.merged
+    }
+
+    pub fn finish(self) -> Vec<OrphanetDisorder> {
+        self.disorders
+    }
+}
+
 pub struct OrphanetProcessor {
     limit: Option<usize>,
+    /// Disorders with fewer HPO associations are skipped (never below 1)
+    min_hpo_associations: usize,
 }
 
 impl OrphanetProcessor {
     pub fn new(limit: Option<usize>) -> Self {
-        Self { limit }
+        Self { limit, min_hpo_associations: 1 }
+    }
+
+    /// Skip disorders with fewer than `min` HPO associations, whose few terms
+    /// embed too vaguely to rank well
+    pub fn with_min_hpo_associations(mut self, min: usize) -> Self {
+        self.min_hpo_associations = min.max(1);
+        self
     }
     
     /// Parse the Orphanet XML file and extract disorders
     pub fn parse_xml<P: AsRef<Path>>(&self, path: P) -> Result<Vec<OrphanetDisorder>> {
         let content = std::fs::read_to_string(path)
             .context("Failed to read Orphanet XML file")?;
-        
-        let mut reader = Reader::from_str(&content);
+
+        Ok(self.parse_str(&content))
+    }
+
+    /// Parse several product files and merge them into one disorder per code.
+    /// `limit` and the HPO minimum apply to the merged disorders, since a code
+    /// can gain associations from each product.
+    pub fn parse_products<P: AsRef<Path>>(&self, paths: &[P]) -> Result<Vec<OrphanetDisorder>> {
+        if let [path] = paths {
+            return self.parse_xml(path);
+        }
+
+        let each = OrphanetProcessor::new(None);
+        let mut products = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = path.as_ref();
+            products.push(each.parse_xml(path).with_context(|| format!("Failed to parse {:?}", path))?);
+        }
+        Ok(self.merge_products(products))
+    }
+
+    /// Merge already-parsed products, then apply `limit` and the HPO minimum
+    pub fn merge_products(&self, products: Vec<Vec<OrphanetDisorder>>) -> Vec<OrphanetDisorder> {
+        let mut merger = DisorderMerger::default();
+        for product in products {
+            merger.add(product);
+        }
+        if merger.merged() > 0 {
+            tracing::info!("Merged {} duplicate disorder records across products", merger.merged());
+        }
+
+        let mut disorders = merger.finish();
+        let before = disorders.len();
+        disorders.retain(|d| d.hpo_associations.len() >= self.min_hpo_associations);
+        log_below_min(before - disorders.len(), self.min_hpo_associations);
+        if let Some(limit) = self.limit {
+            disorders.truncate(limit);
+        }
+        disorders
+    }
+
+    /// Parse Orphanet product4 XML already in memory.
+    ///
+    /// Several elements carry a `Name` child (`Disorder`, `DisorderType`,
+    /// `DisorderGroup`, `HPOFrequency`, ...), so text is attributed by its
+    /// immediate parent on an explicit element stack rather than by what was
+    /// seen first.
+    pub fn parse_str(&self, content: &str) -> Vec<OrphanetDisorder> {
+        let mut reader = Reader::from_str(content);
         
         let mut disorders = Vec::new();
         let mut current_disorder: Option<OrphanetDisorder> = None;
         let mut current_hpo: Option<HPOAssociation> = None;
+        let mut below_min = 0;
         
-        let mut current_element = String::new();
+        // Open elements from the root down to the one being read
+        let mut stack: Vec<String> = Vec::new();
         let mut buf = Vec::new();
         
         loop {
             match reader.read_event_into(&mut buf) {
                 Ok(Event::Start(e)) => {
-                    current_element = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                    let element = String::from_utf8_lossy(e.name().as_ref()).to_string();
                     
-                    match current_element.as_str() {
+                    match element.as_str() {
                         "Disorder" => {
                             current_disorder = Some(OrphanetDisorder {
                                 orpha_code: String::new(),
@@ -81,30 +291,42 @@ impl OrphanetProcessor {
                         }
                         _ => {}
                     }
+                    stack.push(element);
                 }
                 Ok(Event::Text(e)) => {
-                    let text = e.unescape().unwrap().to_string();
+                    let text = e.unescape().map(|t| t.trim().to_string()).unwrap_or_else(|err| {
+                        tracing::warn!("Skipping malformed XML text at position {}: {}", reader.buffer_position(), err);
+                        String::new()
+                    });
+
+                    // Whitespace between elements is reported as text too
+                    let (parent, element) = match stack.as_slice() {
+                        [.., parent, element] if !text.is_empty() => (parent.as_str(), element.as_str()),
+                        _ => ("", ""),
+                    };
                     
-                    match current_element.as_str() {
-                        "OrphaCode" => {
+                    match (parent, element) {
+                        ("Disorder", "OrphaCode") => {
                             if let Some(ref mut disorder) = current_disorder {
                                 disorder.orpha_code = text;
                             }
                         }
-                        "Name" => {
-                            // Only capture disorder name (not other Name elements)
+                        ("Disorder", "Name") => {
                             if let Some(ref mut disorder) = current_disorder {
-                                if disorder.name.is_empty() {
-                                    disorder.name = text;
-                                }
+                                disorder.name = text;
+                            }
+                        }
+                        ("HPOFrequency", "Name") => {
+                            if let Some(ref mut hpo) = current_hpo {
+                                hpo.frequency = text;
                             }
                         }
-                        "HPOId" => {
+                        ("HPO", "HPOId") => {
                             if let Some(ref mut hpo) = current_hpo {
                                 hpo.hpo_id = text;
                             }
                         }
-                        "HPOTerm" => {
+                        ("HPO", "HPOTerm") => {
                             if let Some(ref mut hpo) = current_hpo {
                                 hpo.hpo_term = text;
                             }
@@ -114,6 +336,7 @@ impl OrphanetProcessor {
                 }
                 Ok(Event::End(e)) => {
                     let element = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                    stack.pop();
                     
                     match element.as_str() {
                         "HPODisorderAssociation" => {
@@ -121,33 +344,22 @@ impl OrphanetProcessor {
                                 disorder.hpo_associations.push(hpo);
                             }
                         }
-                        "HPOFrequency" => {
-                            // Capture the frequency from the Name element that follows
-                            if let Some(ref mut hpo) = current_hpo {
-                                // Frequency will be captured in the Name text event
-                                if hpo.frequency.is_empty() {
-                                    // Read ahead for the Name element
-                                    let mut freq_buf = Vec::new();
-                                    if let Ok(Event::Start(_)) = reader.read_event_into(&mut freq_buf) {
-                                        if let Ok(Event::Text(freq_text)) = reader.read_event_into(&mut freq_buf) {
-                                            hpo.frequency = freq_text.unescape().unwrap().to_string();
-                                        }
-                                    }
-                                }
-                            }
-                        }
                         "Disorder" => {
                             if let Some(disorder) = current_disorder.take() {
-                                // Only add disorders with HPO associations
-                                if !disorder.hpo_associations.is_empty() {
+                                // Disorders without HPO associations are always dropped
+                                let associations = disorder.hpo_associations.len();
+                                if associations > 0 && associations < self.min_hpo_associations {
+                                    below_min += 1;
+                                } else if associations > 0 {
                                     disorders.push(disorder);
                                     
                                     // Check limit
-                                    if let Some(limit) = self.limit {
-                                        if disorders.len() >= limit {
-                                            tracing::info!("Reached limit of {} disorders", limit);
-                                            return Ok(disorders);
-                                        }
+                                    if let Some(limit) = self.limit
+                                        && disorders.len() >= limit
+                                    {
+                                        tracing::info!("Reached limit of {} disorders", limit);
+                                        log_below_min(below_min, self.min_hpo_associations);
+                                        return disorders;
                                     }
                                 }
                             }
@@ -165,8 +377,15 @@ impl OrphanetProcessor {
             buf.clear();
         }
         
+        log_below_min(below_min, self.min_hpo_associations);
         tracing::info!("Parsed {} disorders from Orphanet XML", disorders.len());
-        Ok(disorders)
+        disorders
+    }
+}
+
+fn log_below_min(skipped: usize, min: usize) {
+    if skipped > 0 {
+        tracing::info!("Skipped {} disorders with fewer than {} HPO associations", skipped, min);
     }
 }
 
@@ -199,4 +418,203 @@ mod tests {
         assert!(text.contains("Macrocephaly"));
         assert!(text.contains("Very frequent"));
     }
+
+    #[test]
+    fn test_frequency_rank_orders_bands() {
+        let rank = |frequency: &str| HPOAssociation {
+            hpo_id: String::new(),
+            hpo_term: String::new(),
+            frequency: frequency.to_string(),
+        }
+        .frequency_rank();
+
+        assert!(rank("Obligate (100%)") < rank("Very frequent (99-80%)"));
+        assert!(rank("Very frequent (99-80%)") < rank("Frequent (79-30%)"));
+        assert!(rank("Frequent (79-30%)") < rank("Occasional (29-5%)"));
+        assert!(rank("Occasional (29-5%)") < rank("Very rare (<4-1%)"));
+        assert!(rank("Very rare (<4-1%)") < rank(""));
+        assert!(rank("") < rank("Excluded (0%)"));
+    }
+
+    fn weighted_disorder() -> OrphanetDisorder {
+        let assoc = |hpo_id: &str, hpo_term: &str, frequency: &str| HPOAssociation {
+            hpo_id: hpo_id.to_string(),
+            hpo_term: hpo_term.to_string(),
+            frequency: frequency.to_string(),
+        };
+        OrphanetDisorder {
+            orpha_code: "324".to_string(),
+            name: "Fabry disease".to_string(),
+            hpo_associations: vec![
+                assoc("HP:0001250", "Seizure", "Occasional (29-5%)"),
+                assoc("HP:0001014", "Angiokeratoma", "Very frequent (99-80%)"),
+                assoc("HP:0000083", "Renal insufficiency", "Frequent (79-30%)"),
+                assoc("HP:0000613", "Photophobia", "Very rare (<4-1%)"),
+            ],
+        }
+    }
+
+    fn mentions(text: &str, term: &str) -> usize {
+        text.matches(term).count()
+    }
+
+    #[test]
+    fn test_frequency_weight_follows_band() {
+        let disorder = weighted_disorder();
+        let weights: Vec<f32> = disorder.hpo_associations.iter().map(|a| a.frequency_weight()).collect();
+        assert_eq!(weights, vec![0.17, 0.9, 0.545, 0.025]);
+
+        let unknown = HPOAssociation { hpo_id: String::new(), hpo_term: String::new(), frequency: "Excluded (0%)".to_string() };
+        assert_eq!(unknown.frequency_weight(), 0.0);
+    }
+
+    #[test]
+    fn test_weighting_off_matches_plain_text() {
+        let disorder = weighted_disorder();
+        let text = disorder.to_weighted_text(HpoWeighting::Off);
+
+        assert_eq!(text, disorder.to_embedable_text());
+        assert!(text.starts_with("Disease: Fabry disease (Orpha: 324)\n\nClinical Signs and Symptoms:\n- Seizure"));
+        assert!(!text.contains("Key features"));
+        assert_eq!(mentions(&text, "Angiokeratoma"), mentions(&text, "Photophobia"));
+    }
+
+    #[test]
+    fn test_summary_weighting_leads_with_frequent_terms() {
+        let text = weighted_disorder().to_weighted_text(HpoWeighting::Summary);
+
+        assert!(text.contains("Key features: Angiokeratoma, Renal insufficiency\n"));
+        assert_eq!(mentions(&text, "Angiokeratoma"), 2);
+        assert_eq!(mentions(&text, "Seizure"), 1);
+        assert_eq!(mentions(&text, "Photophobia"), 1);
+        // The full list goes most frequent first so rare signs are the ones truncated
+        assert!(text.find("- Angiokeratoma").unwrap() < text.find("- Seizure").unwrap());
+        assert!(text.find("- Seizure").unwrap() < text.find("- Photophobia").unwrap());
+    }
+
+    #[test]
+    fn test_repeat_weighting_repeats_in_proportion_to_frequency() {
+        let text = weighted_disorder().to_weighted_text(HpoWeighting::Repeat);
+
+        assert!(text.contains("- Angiokeratoma, Angiokeratoma, Angiokeratoma (Very frequent (99-80%)) [HP:0001014]\n"));
+        assert_eq!(mentions(&text, "Angiokeratoma"), 3);
+        assert_eq!(mentions(&text, "Renal insufficiency"), 2);
+        assert_eq!(mentions(&text, "Seizure"), 1);
+        assert_eq!(mentions(&text, "Photophobia"), 1);
+    }
+
+    #[test]
+    fn test_weighting_parse() {
+        assert_eq!(HpoWeighting::parse(None), HpoWeighting::Off);
+        assert_eq!(HpoWeighting::parse(Some("Summary".to_string())), HpoWeighting::Summary);
+        assert_eq!(HpoWeighting::parse(Some("repeat".to_string())), HpoWeighting::Repeat);
+        assert_eq!(HpoWeighting::parse(Some("bogus".to_string())), HpoWeighting::Off);
+    }
+
+    #[test]
+    fn test_parse_product4_nested_names() {
+        let xml = include_str!("../../fixtures/orphanet_product4_sample.xml");
+        let disorders = OrphanetProcessor::new(None).parse_str(xml);
+
+        // The disorder without HPO associations is dropped
+        assert_eq!(disorders.len(), 2);
+
+        // DisorderType/DisorderGroup names precede the disorder name here
+        let alexander = &disorders[0];
+        assert_eq!(alexander.orpha_code, "58");
+        assert_eq!(alexander.name, "Alexander disease");
+        assert_eq!(alexander.hpo_associations.len(), 2);
+        assert_eq!(alexander.hpo_associations[0].hpo_id, "HP:0000256");
+        assert_eq!(alexander.hpo_associations[0].hpo_term, "Macrocephaly");
+        assert_eq!(alexander.hpo_associations[0].frequency, "Very frequent (99-80%)");
+        assert_eq!(alexander.hpo_associations[1].frequency, "Occasional (29-5%)");
+
+        let fabry = &disorders[1];
+        assert_eq!(fabry.orpha_code, "324");
+        assert_eq!(fabry.name, "Fabry disease");
+        assert_eq!(fabry.hpo_associations[0].hpo_term, "Angiokeratoma");
+        assert_eq!(fabry.hpo_associations[0].frequency, "Very frequent (99-80%)");
+    }
+
+    #[test]
+    fn test_min_hpo_associations_filter() {
+        // Alexander disease has 2 associations, Fabry disease 1, the third disorder none
+        let xml = include_str!("../../fixtures/orphanet_product4_sample.xml");
+
+        let names = |processor: OrphanetProcessor| -> Vec<String> {
+            processor.parse_str(xml).into_iter().map(|d| d.name).collect()
+        };
+        assert_eq!(names(OrphanetProcessor::new(None)), vec!["Alexander disease", "Fabry disease"]);
+        assert_eq!(names(OrphanetProcessor::new(None).with_min_hpo_associations(0)), vec!["Alexander disease", "Fabry disease"]);
+        assert_eq!(names(OrphanetProcessor::new(None).with_min_hpo_associations(2)), vec!["Alexander disease"]);
+        assert!(names(OrphanetProcessor::new(None).with_min_hpo_associations(3)).is_empty());
+    }
+
+    #[test]
+    fn test_parse_respects_limit() {
+        let xml = include_str!("../../fixtures/orphanet_product4_sample.xml");
+        let disorders = OrphanetProcessor::new(Some(1)).parse_str(xml);
+        assert_eq!(disorders.len(), 1);
+        assert_eq!(disorders[0].name, "Alexander disease");
+    }
+
+    #[test]
+    fn test_merge_products_combines_overlapping_codes() {
+        let first = OrphanetProcessor::new(None).parse_str(include_str!("../../fixtures/orphanet_product4_sample.xml"));
+        let second = OrphanetProcessor::new(None).parse_str(include_str!("../../fixtures/orphanet_product4_overlap_sample.xml"));
+
+        let merged = OrphanetProcessor::new(None).merge_products(vec![first, second]);
+        let codes: Vec<&str> = merged.iter().map(|d| d.orpha_code.as_str()).collect();
+        assert_eq!(codes, vec!["58", "324", "100"]);
+
+        // The shared Angiokeratoma term is listed once; Renal insufficiency is added
+        let fabry = &merged[1];
+        let terms: Vec<&str> = fabry.hpo_associations.iter().map(|a| a.hpo_term.as_str()).collect();
+        assert_eq!(terms, vec!["Angiokeratoma", "Renal insufficiency"]);
+        assert_eq!(fabry.to_embedable_text().matches("Disease: Fabry disease").count(), 1);
+
+        // Disjoint codes pass through untouched
+        assert_eq!(merged[0].hpo_associations.len(), 2);
+        assert_eq!(merged[2].name, "Ataxia-telangiectasia");
+    }
+
+    #[test]
+    fn test_merge_products_applies_min_and_limit_after_merging() {
+        let products = || {
+            vec![
+                OrphanetProcessor::new(None).parse_str(include_str!("../../fixtures/orphanet_product4_sample.xml")),
+                OrphanetProcessor::new(None).parse_str(include_str!("../../fixtures/orphanet_product4_overlap_sample.xml")),
+            ]
+        };
+
+        // Fabry has one term per product but two once merged
+        let names: Vec<String> = OrphanetProcessor::new(None)
+            .with_min_hpo_associations(2)
+            .merge_products(products())
+            .into_iter()
+            .map(|d| d.name)
+            .collect();
+        assert_eq!(names, vec!["Alexander disease", "Fabry disease"]);
+
+        assert_eq!(OrphanetProcessor::new(Some(1)).merge_products(products()).len(), 1);
+    }
+
+    #[test]
+    fn test_merge_fills_missing_name_and_frequency() {
+        let assoc = |frequency: &str| HPOAssociation {
+            hpo_id: "HP:0001250".to_string(),
+            hpo_term: "Seizure".to_string(),
+            frequency: frequency.to_string(),
+        };
+        let mut disorder = OrphanetDisorder { orpha_code: "58".to_string(), name: String::new(), hpo_associations: vec![assoc("")] };
+        disorder.merge(OrphanetDisorder {
+            orpha_code: "58".to_string(),
+            name: "Alexander disease".to_string(),
+            hpo_associations: vec![assoc("Occasional (29-5%)")],
+        });
+
+        assert_eq!(disorder.name, "Alexander disease");
+        assert_eq!(disorder.hpo_associations.len(), 1);
+        assert_eq!(disorder.hpo_associations[0].frequency, "Occasional (29-5%)");
+    }
 }
@@ -1,77 +1,242 @@
 use anyhow::{Result, Context};
 use bytes::Bytes;
 use std::sync::Arc;
-use crate::embeddings::LocalEmbeddingService;
+use crate::embeddings::EmbeddingProvider;
+use crate::rag::vector_store::cosine_similarity;
+use super::chunking::{ChunkConfig, ChunkRules};
+
+/// A chunk of extracted PDF text with its embedding. Offsets are character
+/// positions in the extracted document text (end exclusive), for source highlighting.
+#[derive(Debug, Clone)]
+pub struct PdfChunk {
+    pub text: String,
+    pub embedding: Vec<f32>,
+    pub start_offset: usize,
+    pub end_offset: usize,
+}
 
 pub struct PdfProcessor {
-    embedding_service: Arc<LocalEmbeddingService>,
+    embedding_service: Arc<dyn EmbeddingProvider>,
+    /// Drop a chunk when its cosine to the previous kept chunk exceeds this (`PDF_DEDUP_THRESHOLD`, off when unset)
+    dedup_threshold: Option<f32>,
+    /// Drop chunks shorter than this after trimming (`MIN_CHUNK_CHARS`), e.g. stray page numbers
+    min_chunk_chars: usize,
+    /// Extractors tried in order (`PDF_EXTRACTORS`)
+    extractors: Vec<PdfExtractor>,
+    /// Trimmed characters an extractor must produce to end the chain (`PDF_MIN_TEXT_CHARS`)
+    min_text_chars: usize,
+    /// Window size/overlap per detected document subtype
+    chunk_rules: ChunkRules,
+    /// Threads extracting lopdf pages in parallel (`PDF_PAGE_THREADS`)
+    page_threads: usize,
+}
+
+/// Default `MIN_CHUNK_CHARS`
+const DEFAULT_MIN_CHUNK_CHARS: usize = 50;
+
+/// Default `PDF_MIN_TEXT_CHARS`
+const DEFAULT_MIN_TEXT_CHARS: usize = 100;
+
+/// Upper bound on the default `PDF_PAGE_THREADS`, leaving cores for the runtime
+const DEFAULT_MAX_PAGE_THREADS: usize = 4;
+
+/// Fewest pages worth a thread of their own; shorter documents stay on one thread
+const MIN_PAGES_PER_THREAD: usize = 8;
+
+/// A PDF text extraction backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PdfExtractor {
+    Lopdf,
+    /// The `pdf-extract` crate; handles some font encodings lopdf mangles (needs the `pdf-extract` feature)
+    PdfExtract,
+}
+
+impl PdfExtractor {
+    /// Name as written in `PDF_EXTRACTORS` and stored in `uploaded_files.text_extractor`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lopdf => "lopdf",
+            Self::PdfExtract => "pdf-extract",
+        }
+    }
+
+    /// Comma-separated chain, in order. Unknown names and extractors not compiled
+    /// into this build (including `ocr`, which has no backend yet) are skipped with
+    /// a warning; an empty result falls back to `lopdf` alone.
+    pub fn parse_chain(value: Option<&str>) -> Vec<Self> {
+        let mut chain = Vec::new();
+
+        for name in value.unwrap_or_default().split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let extractor = match name.to_lowercase().as_str() {
+                "lopdf" => Self::Lopdf,
+                "pdf-extract" | "pdf_extract" if cfg!(feature = "pdf-extract") => Self::PdfExtract,
+                "pdf-extract" | "pdf_extract" => {
+                    tracing::warn!("PDF_EXTRACTORS: pdf-extract needs the `pdf-extract` feature, skipping");
+                    continue;
+                }
+                "ocr" => {
+                    tracing::warn!("PDF_EXTRACTORS: no OCR backend is available, skipping");
+                    continue;
+                }
+                _ => {
+                    tracing::warn!("PDF_EXTRACTORS: unknown extractor '{}', skipping", name);
+                    continue;
+                }
+            };
+            if !chain.contains(&extractor) {
+                chain.push(extractor);
+            }
+        }
+
+        if chain.is_empty() {
+            chain.push(Self::Lopdf);
+        }
+        chain
+    }
+
+    fn extract(self, file_data: &[u8], page_threads: usize) -> Result<String> {
+        match self {
+            Self::Lopdf => extract_with_lopdf(file_data, page_threads),
+            Self::PdfExtract => extract_with_pdf_extract(file_data),
+        }
+    }
+}
+
+impl std::fmt::Display for PdfExtractor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Document text and the extractor that produced it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractedText {
+    pub text: String,
+    pub extractor: PdfExtractor,
 }
 
 impl PdfProcessor {
-    pub fn new(embedding_service: Arc<LocalEmbeddingService>) -> Result<Self> {
-        Ok(Self { embedding_service })
+    /// Extractors tried in order (`PDF_EXTRACTORS`)
+    pub fn extractors(&self) -> &[PdfExtractor] {
+        &self.extractors
+    }
+
+    pub fn new(embedding_service: Arc<dyn EmbeddingProvider>) -> Result<Self> {
+        let dedup_threshold = std::env::var("PDF_DEDUP_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse::<f32>().ok())
+            .filter(|t| *t > 0.0 && *t < 1.0);
+        let min_chunk_chars = std::env::var("MIN_CHUNK_CHARS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_MIN_CHUNK_CHARS);
+        let extractors = PdfExtractor::parse_chain(std::env::var("PDF_EXTRACTORS").ok().as_deref());
+        let min_text_chars = std::env::var("PDF_MIN_TEXT_CHARS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_MIN_TEXT_CHARS);
+        let page_threads = std::env::var("PDF_PAGE_THREADS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, |n| n.get()).min(DEFAULT_MAX_PAGE_THREADS)
+            })
+            .max(1);
+
+        Ok(Self {
+            embedding_service,
+            dedup_threshold,
+            min_chunk_chars,
+            extractors,
+            min_text_chars,
+            chunk_rules: ChunkRules::from_env(),
+            page_threads,
+        })
     }
     
-    pub async fn process_pdf(&self, file_data: Bytes, _counter: Option<&crate::request_counter::RequestCounter>) -> Result<Vec<(String, Vec<f32>)>> {
+    pub async fn process_pdf(&self, file_data: Bytes, _counter: Option<&crate::request_counter::RequestCounter>) -> Result<Vec<PdfChunk>> {
         // Extract text from PDF
-        let text = self.extract_text(file_data)?;
+        let extracted = self.extract_text(file_data)?;
         
-        // Chunk text
-        let chunks = self.chunk_text(&text)?;
-        
-        // Generate embeddings using local FastEmbed
-        let embeddings = self.generate_embeddings(chunks).await?;
-        
-        Ok(embeddings)
+        self.process_text(&extracted.text).await
     }
     
-    fn extract_text(&self, file_data: Bytes) -> Result<String> {
-        use lopdf::Document;
+    /// Chunk and embed already extracted text (the second half of `process_pdf`)
+    pub async fn process_text(&self, text: &str) -> Result<Vec<PdfChunk>> {
+        // Chunk text at the granularity its subtype retrieves best with
+        let (kind, config) = self.chunk_rules.select(text);
+        tracing::info!("Chunking PDF as {} ({} chars, {} overlap)", kind.as_str(), config.size, config.overlap);
+        let chunks = self.chunk_text(text, config)?;
         
-        let doc = Document::load_mem(&file_data)
-            .context("Failed to load PDF document")?;
-        
-        let mut text = String::new();
-        let pages = doc.get_pages();
+        // Generate embeddings using the shared provider
+        let embeddings = self.generate_embeddings(chunks).await?;
         
-        for page_num in 1..=pages.len() {
-            if let Ok(page_text) = doc.extract_text(&[page_num as u32]) {
-                text.push_str(&page_text);
-                text.push('\n');
+        // Overlapping windows can yield near-identical chunks on short documents
+        let embeddings = match self.dedup_threshold {
+            Some(threshold) => {
+                let before = embeddings.len();
+                let kept = drop_near_duplicates(embeddings, threshold);
+                if kept.len() < before {
+                    tracing::info!(
+                        "Dropped {} near-duplicate PDF chunks (cosine > {})",
+                        before - kept.len(),
+                        threshold
+                    );
+                }
+                kept
             }
-        }
-        
-        if text.trim().is_empty() {
-            anyhow::bail!("No text extracted from PDF");
-        }
+            None => embeddings,
+        };
         
-        Ok(text)
+        Ok(embeddings)
     }
     
-    fn chunk_text(&self, text: &str) -> Result<Vec<String>> {
+    /// Run the extractor chain, stopping at the first that yields `min_text_chars`.
+    /// If none does, the longest non-empty result wins; fails when every extractor
+    /// comes back empty or errors (e.g. a scanned PDF).
+    pub fn extract_text(&self, file_data: Bytes) -> Result<ExtractedText> {
+        run_chain(&self.extractors, self.min_text_chars, |extractor| extractor.extract(&file_data, self.page_threads))
+    }
+    
+    /// Split into overlapping windows, returning each trimmed chunk with its char range.
+    /// Chunks under `min_chunk_chars` are dropped unless the whole document fits in one window.
+    fn chunk_text(&self, text: &str, config: ChunkConfig) -> Result<Vec<(String, usize, usize)>> {
         // Simple chunking by size with overlap
-        const CHUNK_SIZE: usize = 1500; // characters
-        const OVERLAP: usize = 200;
         
         let mut chunks = Vec::new();
         let chars: Vec<char> = text.chars().collect();
         let mut start = 0;
+        let mut skipped = 0;
         
         while start < chars.len() {
-            let end = (start + CHUNK_SIZE).min(chars.len());
-            let chunk: String = chars[start..end].iter().collect();
+            let end = (start + config.size).min(chars.len());
+            let window = &chars[start..end];
             
-            if !chunk.trim().is_empty() {
-                chunks.push(chunk.trim().to_string());
+            // Offsets refer to the trimmed text, not the raw window
+            let leading = window.iter().take_while(|c| c.is_whitespace()).count();
+            let trailing = window.iter().rev().take_while(|c| c.is_whitespace()).count();
+            if leading < window.len() {
+                let len = window.len() - leading - trailing;
+                let whole_document = start == 0 && end == chars.len();
+                if len >= self.min_chunk_chars || whole_document {
+                    let chunk: String = window[leading..window.len() - trailing].iter().collect();
+                    chunks.push((chunk, start + leading, end - trailing));
+                } else {
+                    skipped += 1;
+                }
             }
             
             if end >= chars.len() {
                 break;
             }
             
-            start += CHUNK_SIZE - OVERLAP;
+            start += config.step();
         }
         
+        if skipped > 0 {
+            tracing::info!("Skipped {} PDF chunks shorter than {} chars", skipped, self.min_chunk_chars);
+        }
+
         if chunks.is_empty() {
             anyhow::bail!("No chunks created from text");
         }
@@ -79,17 +244,374 @@ impl PdfProcessor {
         Ok(chunks)
     }
     
-    async fn generate_embeddings(&self, chunks: Vec<String>) -> Result<Vec<(String, Vec<f32>)>> {
-        tracing::info!("Generating embeddings for {} PDF chunks using local FastEmbed", chunks.len());
+    async fn generate_embeddings(&self, chunks: Vec<(String, usize, usize)>) -> Result<Vec<PdfChunk>> {
+        tracing::info!(
+            "Generating embeddings for {} PDF chunks using {} embeddings",
+            chunks.len(),
+            self.embedding_service.name()
+        );
         
-        // Use local embedding service (batch processing, fast!)
-        let embeddings = self.embedding_service.embed_batch(chunks.clone()).await?;
+        // Use the shared embedding provider so PDF chunks live in the same space as Orphanet/query vectors
+        let texts: Vec<String> = chunks.iter().map(|(text, _, _)| text.clone()).collect();
+        let embeddings = self.embedding_service.embed_for_storage(texts).await?;
         
         // Combine chunks with their embeddings
-        let results: Vec<(String, Vec<f32>)> = chunks.into_iter()
-            .zip(embeddings.into_iter())
+        let results: Vec<PdfChunk> = chunks.into_iter()
+            .zip(embeddings)
+            .map(|((text, start_offset, end_offset), embedding)| PdfChunk {
+                text,
+                embedding,
+                start_offset,
+                end_offset,
+            })
             .collect();
         
         Ok(results)
     }
 }
+
+fn run_chain(
+    extractors: &[PdfExtractor],
+    min_text_chars: usize,
+    mut extract: impl FnMut(PdfExtractor) -> Result<String>,
+) -> Result<ExtractedText> {
+    let mut best: Option<(usize, ExtractedText)> = None;
+    let mut last_error = None;
+
+    for &extractor in extractors {
+        let text = match extract(extractor) {
+            Ok(text) => text,
+            Err(e) => {
+                tracing::warn!("PDF extractor {} failed: {:#}", extractor, e);
+                last_error = Some(e);
+                continue;
+            }
+        };
+
+        let chars = text.trim().chars().count();
+        if chars >= min_text_chars.max(1) {
+            return Ok(ExtractedText { text, extractor });
+        }
+        tracing::info!("PDF extractor {} found only {} chars, trying the next", extractor, chars);
+        if chars > 0 && best.as_ref().is_none_or(|(best_chars, _)| chars > *best_chars) {
+            best = Some((chars, ExtractedText { text, extractor }));
+        }
+    }
+
+    match (best, last_error) {
+        (Some((_, extracted)), _) => Ok(extracted),
+        // A lone failing extractor keeps its own error (e.g. "Failed to load PDF document")
+        (None, Some(e)) if extractors.len() == 1 => Err(e),
+        (None, _) => anyhow::bail!("No text extracted from PDF"),
+    }
+}
+
+/// Concatenated page text in page order, skipping pages lopdf can't decode.
+/// Long documents are split into contiguous page ranges extracted on up to
+/// `page_threads` threads; the parsed document is only read, so they share it.
+fn extract_with_lopdf(file_data: &[u8], page_threads: usize) -> Result<String> {
+    use lopdf::Document;
+
+    let doc = Document::load_mem(file_data)
+        .context("Failed to load PDF document")?;
+
+    let page_count = doc.get_pages().len() as u32;
+    let extract_range = |pages: std::ops::RangeInclusive<u32>| {
+        let mut text = String::new();
+        for page_num in pages {
+            if let Ok(page_text) = doc.extract_text(&[page_num]) {
+                text.push_str(&page_text);
+                text.push('\n');
+            }
+        }
+        text
+    };
+
+    let threads = page_threads.min(page_count as usize / MIN_PAGES_PER_THREAD).max(1);
+    if threads == 1 {
+        return Ok(extract_range(1..=page_count));
+    }
+
+    let started = std::time::Instant::now();
+    let per_thread = page_count.div_ceil(threads as u32);
+    let text = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads as u32)
+            .map(|i| i * per_thread + 1)
+            .take_while(|first| *first <= page_count)
+            .map(|first| {
+                let last = (first + per_thread - 1).min(page_count);
+                scope.spawn(move || extract_range(first..=last))
+            })
+            .collect();
+        // Joining in spawn order keeps the ranges, and so the pages, in order
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .collect::<String>()
+    });
+    tracing::info!(
+        "Extracted {} PDF pages on {} threads in {}ms",
+        page_count,
+        threads,
+        started.elapsed().as_millis()
+    );
+
+    Ok(text)
+}
+
+#[cfg(feature = "pdf-extract")]
+fn extract_with_pdf_extract(file_data: &[u8]) -> Result<String> {
+    // pdf-extract panics on some malformed documents; treat that like any other failure
+    std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(file_data))
+        .map_err(|_| anyhow::anyhow!("pdf-extract panicked on this document"))?
+        .context("pdf-extract failed to read the document")
+}
+
+#[cfg(not(feature = "pdf-extract"))]
+fn extract_with_pdf_extract(_file_data: &[u8]) -> Result<String> {
+    anyhow::bail!("Built without the `pdf-extract` feature")
+}
+
+/// Drop chunks whose embedding is more similar than `threshold` to the previous kept chunk
+fn drop_near_duplicates(chunks: Vec<PdfChunk>, threshold: f32) -> Vec<PdfChunk> {
+    let mut kept: Vec<PdfChunk> = Vec::with_capacity(chunks.len());
+
+    for chunk in chunks {
+        if let Some(previous) = kept.last()
+            && cosine_similarity(&previous.embedding, &chunk.embedding) > threshold
+        {
+            continue;
+        }
+        kept.push(chunk);
+    }
+
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(text: &str, embedding: Vec<f32>) -> PdfChunk {
+        PdfChunk { text: text.to_string(), embedding, start_offset: 0, end_offset: 0 }
+    }
+
+    #[test]
+    fn test_drop_near_duplicates() {
+        let chunks = vec![
+            chunk("a", vec![1.0, 0.0]),
+            chunk("a'", vec![0.99, 0.01]),
+            chunk("b", vec![0.0, 1.0]),
+            chunk("c", vec![1.0, 0.0]),
+        ];
+
+        let kept: Vec<String> = drop_near_duplicates(chunks, 0.95)
+            .into_iter()
+            .map(|c| c.text)
+            .collect();
+        assert_eq!(kept, vec!["a", "b", "c"]);
+    }
+
+    fn processor(min_chunk_chars: usize) -> PdfProcessor {
+        PdfProcessor {
+            embedding_service: Arc::new(crate::embeddings::LocalEmbeddingService::deferred()),
+            dedup_threshold: None,
+            min_chunk_chars,
+            extractors: vec![PdfExtractor::Lopdf],
+            min_text_chars: DEFAULT_MIN_TEXT_CHARS,
+            chunk_rules: ChunkRules::default(),
+            page_threads: 1,
+        }
+    }
+
+    #[test]
+    fn test_chunk_offsets_point_at_trimmed_text() {
+        let processor = processor(DEFAULT_MIN_CHUNK_CHARS);
+        let text = format!("  {}\n", "x".repeat(2000));
+        let chars: Vec<char> = text.chars().collect();
+
+        let chunks = processor.chunk_text(&text, ChunkConfig::DEFAULT).unwrap();
+        assert_eq!(chunks.len(), 2);
+        for (chunk, start, end) in &chunks {
+            let slice: String = chars[*start..*end].iter().collect();
+            assert_eq!(&slice, chunk);
+        }
+        assert_eq!(chunks[0].1, 2);
+        assert_eq!(chunks[1].1, 1300);
+        assert_eq!(chunks[1].2, 2002);
+    }
+
+    #[test]
+    fn test_short_trailing_fragment_is_dropped() {
+        // Body text, then a page-number fragment alone in the last window
+        let text = format!("{}{}7\n", "x".repeat(1400), " ".repeat(1500));
+        let chars: Vec<char> = text.chars().collect();
+
+        let chunks = processor(50).chunk_text(&text, ChunkConfig::DEFAULT).unwrap();
+        assert_eq!(chunks.len(), 2);
+        // Windows still advance by size - overlap around the skipped one
+        assert_eq!((chunks[0].1, chunks[0].2), (0, 1400));
+        assert_eq!((chunks[1].1, chunks[1].2), (1300, 1400));
+        assert!(chunks.iter().all(|(chunk, _, _)| chunk.chars().count() >= 50));
+
+        // With the filter off the fragment comes back at its real offset
+        let unfiltered = processor(0).chunk_text(&text, ChunkConfig::DEFAULT).unwrap();
+        assert_eq!(unfiltered.len(), 3);
+        assert_eq!(unfiltered[2].0, "7");
+        assert_eq!(chars[unfiltered[2].1], '7');
+    }
+
+    #[test]
+    fn test_short_document_is_kept() {
+        let chunks = processor(50).chunk_text("  Page 1  ", ChunkConfig::DEFAULT).unwrap();
+        assert_eq!(chunks, vec![("Page 1".to_string(), 2, 8)]);
+
+        assert!(processor(50).chunk_text("   \n ", ChunkConfig::DEFAULT).is_err());
+    }
+
+    #[test]
+    fn test_parse_chain() {
+        use PdfExtractor::*;
+
+        assert_eq!(PdfExtractor::parse_chain(None), vec![Lopdf]);
+        assert_eq!(PdfExtractor::parse_chain(Some("ocr, nope")), vec![Lopdf]);
+        assert_eq!(PdfExtractor::parse_chain(Some(" LOPDF ,lopdf")), vec![Lopdf]);
+
+        let chain = PdfExtractor::parse_chain(Some("pdf-extract,lopdf,ocr"));
+        if cfg!(feature = "pdf-extract") {
+            assert_eq!(chain, vec![PdfExtract, Lopdf]);
+        } else {
+            assert_eq!(chain, vec![Lopdf]);
+        }
+    }
+
+    #[test]
+    fn test_chain_stops_at_first_sufficient_text() {
+        let chain = [PdfExtractor::Lopdf, PdfExtractor::PdfExtract];
+        let mut tried = Vec::new();
+
+        let extracted = run_chain(&chain, 10, |extractor| {
+            tried.push(extractor);
+            Ok(match extractor {
+                PdfExtractor::Lopdf => " \n ".to_string(),
+                PdfExtractor::PdfExtract => "Enough text here".to_string(),
+            })
+        })
+        .unwrap();
+        assert_eq!(extracted.extractor, PdfExtractor::PdfExtract);
+        assert_eq!(tried, chain);
+
+        let first = run_chain(&chain, 3, |_| Ok("abcd".to_string())).unwrap();
+        assert_eq!(first.extractor, PdfExtractor::Lopdf);
+    }
+
+    #[test]
+    fn test_chain_falls_back_to_longest_short_result() {
+        let chain = [PdfExtractor::Lopdf, PdfExtractor::PdfExtract];
+
+        let extracted = run_chain(&chain, 100, |extractor| match extractor {
+            PdfExtractor::Lopdf => Ok("ab".to_string()),
+            PdfExtractor::PdfExtract => Ok("abcd".to_string()),
+        })
+        .unwrap();
+        assert_eq!(extracted, ExtractedText { text: "abcd".to_string(), extractor: PdfExtractor::PdfExtract });
+
+        let error = run_chain(&chain, 100, |extractor| match extractor {
+            PdfExtractor::Lopdf => Ok("  ".to_string()),
+            PdfExtractor::PdfExtract => anyhow::bail!("broken"),
+        })
+        .unwrap_err();
+        assert_eq!(error.to_string(), "No text extracted from PDF");
+
+        let error = run_chain(&[PdfExtractor::Lopdf], 100, |_| anyhow::bail!("Failed to load PDF document")).unwrap_err();
+        assert_eq!(error.to_string(), "Failed to load PDF document");
+    }
+
+    #[test]
+    fn test_chunk_size_follows_config() {
+        let text = "x".repeat(1000);
+        let small = processor(0).chunk_text(&text, ChunkConfig::new(400, 100)).unwrap();
+        let spans: Vec<(usize, usize)> = small.iter().map(|(_, start, end)| (*start, *end)).collect();
+        assert_eq!(spans, vec![(0, 400), (300, 700), (600, 1000)]);
+
+        assert_eq!(processor(0).chunk_text(&text, ChunkConfig::DEFAULT).unwrap().len(), 1);
+    }
+
+    /// A PDF whose page `n` reads "Page n"
+    fn numbered_pdf(pages: u32) -> Vec<u8> {
+        use lopdf::content::{Content, Operation};
+        use lopdf::{Document, Object, Stream, dictionary};
+
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Courier",
+        });
+        let resources_id = doc.add_object(dictionary! {
+            "Font" => dictionary! { "F1" => font_id },
+        });
+
+        let kids: Vec<Object> = (1..=pages)
+            .map(|n| {
+                let content = Content {
+                    operations: vec![
+                        Operation::new("BT", vec![]),
+                        Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                        Operation::new("Td", vec![50.into(), 700.into()]),
+                        Operation::new("Tj", vec![Object::string_literal(format!("Page {}", n))]),
+                        Operation::new("ET", vec![]),
+                    ],
+                };
+                let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+                doc.add_object(dictionary! {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "Contents" => content_id,
+                })
+                .into()
+            })
+            .collect();
+
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => pages,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+
+        let mut bytes = Vec::new();
+        doc.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_parallel_extraction_keeps_page_order() {
+        let pdf = numbered_pdf(50);
+        let sequential = extract_with_lopdf(&pdf, 1).unwrap();
+        assert!(sequential.contains("Page 1\n"), "{:?}", sequential);
+
+        for threads in [2, 3, 4, 16] {
+            assert_eq!(extract_with_lopdf(&pdf, threads).unwrap(), sequential);
+        }
+        let positions: Vec<usize> = (1..=50).map(|n| sequential.find(&format!("Page {}\n", n)).unwrap()).collect();
+        assert!(positions.is_sorted());
+    }
+
+    /// Sequential vs parallel extraction time on a long document:
+    /// `cargo test --release pdf_extraction_benchmark -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn pdf_extraction_benchmark() {
+        let pdf = numbered_pdf(400);
+        for threads in [1, 2, 4, 8] {
+            let started = std::time::Instant::now();
+            extract_with_lopdf(&pdf, threads).unwrap();
+            println!("{} threads: {}ms", threads, started.elapsed().as_millis());
+        }
+    }
+}
@@ -1,8 +1,87 @@
 
 use chrono::{DateTime, Utc};
 
+use crate::config::{Lookup, parse_or};
+
 const CRITICAL_FILE_TYPES: &[&str] = &["pdf", "lab_report"];
 
+/// Rough Gemini tokenizer ratio for English text, used to turn `CONTEXT_MAX_TOKENS` into characters
+pub const CHARS_PER_TOKEN: usize = 4;
+
+/// Rough token count of `text`, for checking prompts against Gemini's limits
+/// without a tokenizer round trip
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Default `CONTEXT_MAX_RESULT_CHARS`
+const DEFAULT_MAX_RESULT_CHARS: usize = 400;
+
+/// Caps on retrieved text fed into the answer prompt, so many candidates can't
+/// balloon its cost
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextBudget {
+    /// Characters kept from each retrieved result (`CONTEXT_MAX_RESULT_CHARS`)
+    pub max_result_chars: usize,
+    /// Characters of retrieved text across the whole prompt, the selected match
+    /// included; the smaller of `CONTEXT_MAX_CHARS` and `CONTEXT_MAX_TOKENS` × 4.
+    /// Unset means no total cap.
+    pub max_total_chars: Option<usize>,
+}
+
+impl Default for ContextBudget {
+    fn default() -> Self {
+        Self { max_result_chars: DEFAULT_MAX_RESULT_CHARS, max_total_chars: None }
+    }
+}
+
+/// What `ContextBudget::fit` removed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContextTrim {
+    /// Lowest-ranked entries left out entirely
+    pub dropped: usize,
+    pub chars_removed: usize,
+}
+
+impl ContextBudget {
+    pub fn from_lookup(lookup: Lookup<'_>) -> Self {
+        let max_chars = lookup("CONTEXT_MAX_CHARS").and_then(|v| v.parse::<usize>().ok());
+        let max_tokens = lookup("CONTEXT_MAX_TOKENS")
+            .and_then(|v| v.parse::<usize>().ok())
+            .map(|tokens| tokens.saturating_mul(CHARS_PER_TOKEN));
+
+        Self {
+            max_result_chars: parse_or(lookup, "CONTEXT_MAX_RESULT_CHARS", DEFAULT_MAX_RESULT_CHARS),
+            max_total_chars: match (max_chars, max_tokens) {
+                (Some(chars), Some(tokens)) => Some(chars.min(tokens)),
+                (chars, tokens) => chars.or(tokens),
+            },
+        }
+    }
+
+    /// Keep `entries` (best-ranked first) within `available` characters by
+    /// dropping from the end. The first entry is shortened rather than dropped,
+    /// so some context always survives.
+    pub fn fit(&self, mut entries: Vec<String>, available: usize) -> (Vec<String>, ContextTrim) {
+        let mut trim = ContextTrim::default();
+        let mut total: usize = entries.iter().map(|e| e.chars().count()).sum();
+
+        while total > available && entries.len() > 1 {
+            let removed = entries.pop().map_or(0, |e| e.chars().count());
+            total -= removed;
+            trim.dropped += 1;
+            trim.chars_removed += removed;
+        }
+        if total > available
+            && let Some(first) = entries.first_mut()
+        {
+            *first = first.chars().take(available).collect();
+            trim.chars_removed += total - available;
+        }
+
+        (entries, trim)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ContextStrategy {
@@ -44,3 +123,38 @@ fn is_recent(upload_date: DateTime<Utc>) -> bool {
     let duration = now.signed_duration_since(upload_date);
     duration.num_days() <= 7 // Files from last 7 days
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_from_lookup() {
+        let budget = ContextBudget::from_lookup(&|name| match name {
+            "CONTEXT_MAX_CHARS" => Some("5000".to_string()),
+            "CONTEXT_MAX_TOKENS" => Some("1000".to_string()),
+            "CONTEXT_MAX_RESULT_CHARS" => Some("200".to_string()),
+            _ => None,
+        });
+        assert_eq!(budget, ContextBudget { max_result_chars: 200, max_total_chars: Some(4000) });
+        assert_eq!(ContextBudget::from_lookup(&|_| None), ContextBudget::default());
+    }
+
+    #[test]
+    fn test_fit_drops_lowest_ranked_first() {
+        let budget = ContextBudget::default();
+        let entries = vec!["a".repeat(10), "b".repeat(10), "c".repeat(10)];
+
+        let (kept, trim) = budget.fit(entries.clone(), 25);
+        assert_eq!(kept, entries[..2]);
+        assert_eq!(trim, ContextTrim { dropped: 1, chars_removed: 10 });
+
+        let (kept, trim) = budget.fit(entries.clone(), 4);
+        assert_eq!(kept, vec!["aaaa".to_string()]);
+        assert_eq!(trim, ContextTrim { dropped: 2, chars_removed: 26 });
+
+        let (kept, trim) = budget.fit(entries.clone(), 100);
+        assert_eq!(kept, entries);
+        assert_eq!(trim, ContextTrim::default());
+    }
+}
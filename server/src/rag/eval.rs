@@ -0,0 +1,156 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::embeddings::EmbeddingProvider;
+use crate::rag::vector_store::RagVectorStore;
+
+/// One labeled query: the Orphanet disorder a good retriever should rank near the top
+#[derive(Debug, Clone, Deserialize)]
+pub struct EvalCase {
+    pub query: String,
+    pub expected_orpha_code: String,
+}
+
+/// Aggregate retrieval quality over a fixture set
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalReport {
+    pub cases: usize,
+    pub k: usize,
+    /// Fraction of cases whose expected code appears in the top `k`
+    pub recall_at_k: f32,
+    /// Mean reciprocal rank of the expected code (0 when outside the top `k`)
+    pub mrr: f32,
+    /// Queries whose expected code was not retrieved
+    pub misses: Vec<String>,
+}
+
+/// Read a JSON array of `EvalCase`s
+pub fn load_cases(path: &Path) -> Result<Vec<EvalCase>> {
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read eval fixture {:?}", path))?;
+    serde_json::from_str(&raw).with_context(|| format!("Invalid eval fixture {:?}", path))
+}
+
+/// Embed and search every case, then score the rankings
+pub async fn evaluate(
+    vector_store: &RagVectorStore,
+    embedding_service: &dyn EmbeddingProvider,
+    cases: &[EvalCase],
+    k: usize,
+) -> Result<EvalReport> {
+    let mut rankings = Vec::with_capacity(cases.len());
+
+    for case in cases {
+        let embedding = embedding_service.embed_text(&case.query).await?;
+        let results = vector_store.search(embedding, k).await?;
+        rankings.push(
+            results
+                .into_iter()
+                .map(|(_, _, meta)| meta.orpha_code)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    Ok(score(cases, &rankings, k))
+}
+
+/// Compute recall@k and MRR from the retrieved Orpha codes per case (best first)
+pub fn score(cases: &[EvalCase], rankings: &[Vec<Option<String>>], k: usize) -> EvalReport {
+    let mut hits = 0;
+    let mut reciprocal_ranks = 0.0;
+    let mut misses = Vec::new();
+
+    for (case, ranking) in cases.iter().zip(rankings) {
+        let rank = ranking
+            .iter()
+            .take(k)
+            .position(|code| code.as_deref() == Some(case.expected_orpha_code.as_str()));
+
+        match rank {
+            Some(position) => {
+                hits += 1;
+                reciprocal_ranks += 1.0 / (position + 1) as f32;
+            }
+            None => misses.push(case.query.clone()),
+        }
+    }
+
+    let total = cases.len().max(1) as f32;
+    EvalReport {
+        cases: cases.len(),
+        k,
+        recall_at_k: hits as f32 / total,
+        mrr: reciprocal_ranks / total,
+        misses,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn case(code: &str) -> EvalCase {
+        EvalCase {
+            query: format!("query for {}", code),
+            expected_orpha_code: code.to_string(),
+        }
+    }
+
+    fn codes(codes: &[&str]) -> Vec<Option<String>> {
+        codes.iter().map(|c| Some(c.to_string())).collect()
+    }
+
+    #[test]
+    fn test_score_recall_and_mrr() {
+        let cases = vec![case("58"), case("324"), case("558")];
+        let rankings = vec![
+            codes(&["58", "1", "2"]),  // rank 1
+            codes(&["1", "2", "324"]), // rank 3
+            codes(&["1", "2", "3"]),   // miss
+        ];
+
+        let report = score(&cases, &rankings, 3);
+        assert!((report.recall_at_k - 2.0 / 3.0).abs() < 1e-6);
+        assert!((report.mrr - (1.0 + 1.0 / 3.0) / 3.0).abs() < 1e-6);
+        assert_eq!(report.misses, vec!["query for 558"]);
+
+        // Rank 3 falls outside k = 2
+        let report = score(&cases, &rankings, 2);
+        assert!((report.recall_at_k - 1.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_fixture_parses() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/retrieval_eval.json");
+        let cases = load_cases(&path).unwrap();
+        assert!(!cases.is_empty());
+    }
+
+    /// Runs the fixture against the configured database and embedding provider.
+    /// Needs DATABASE_URL (with Orphanet loaded) and the usual embedding env:
+    /// `cargo test retrieval_eval -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn retrieval_eval() {
+        dotenvy::dotenv().ok();
+        let k = std::env::var("EVAL_TOP_K")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(10);
+
+        let gemini = crate::gemini::GeminiClient::from_env().unwrap();
+        let embedding_service = crate::embeddings::provider_from_env(&gemini).unwrap();
+        embedding_service.initialize().await.unwrap();
+
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL not set");
+        let pool = crate::db::create_pool(&database_url).await.unwrap();
+        let vector_store = RagVectorStore::from_env(&pool, embedding_service.clone()).await.unwrap();
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/retrieval_eval.json");
+        let cases = load_cases(&path).unwrap();
+        let report = evaluate(&vector_store, embedding_service.as_ref(), &cases, k).await.unwrap();
+
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    }
+}
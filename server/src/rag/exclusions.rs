@@ -0,0 +1,199 @@
+use anyhow::{Context, Result};
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crate::AppState;
+use crate::auth::AdminAccess;
+use crate::config::Lookup;
+
+/// Orphanet conditions that must never be returned (`EXCLUDED_ORPHA_CODES` and
+/// `EXCLUDED_ORPHA_CODES_PATH`). Searches drop them and the Orphanet load skips
+/// them. Cloning is cheap and every clone sees `reload`.
+#[derive(Clone, Default)]
+pub struct ConditionExclusions {
+    /// Codes from `EXCLUDED_ORPHA_CODES`, fixed for the process lifetime
+    listed: Arc<HashSet<String>>,
+    /// File of codes, one per line (`#` starts a comment), re-read by `reload`
+    path: Option<PathBuf>,
+    codes: Arc<RwLock<Arc<HashSet<String>>>>,
+}
+
+impl ConditionExclusions {
+    pub fn new(listed: HashSet<String>, path: Option<PathBuf>) -> Result<Self> {
+        let exclusions = Self {
+            listed: Arc::new(listed),
+            path,
+            codes: Arc::default(),
+        };
+        exclusions.reload()?;
+        Ok(exclusions)
+    }
+
+    pub fn from_env() -> Result<Self> {
+        Self::from_lookup(&|name| std::env::var(name).ok())
+    }
+
+    pub fn from_lookup(lookup: Lookup<'_>) -> Result<Self> {
+        let listed = lookup("EXCLUDED_ORPHA_CODES")
+            .map(|list| list.split(',').filter_map(normalize_code).collect())
+            .unwrap_or_default();
+        let path = lookup("EXCLUDED_ORPHA_CODES_PATH")
+            .filter(|p| !p.trim().is_empty())
+            .map(PathBuf::from);
+        Self::new(listed, path)
+    }
+
+    /// Re-read the exclusion file, returning how many codes are now excluded.
+    /// On error the previous set stays in force.
+    pub fn reload(&self) -> Result<usize> {
+        let mut codes = (*self.listed).clone();
+        if let Some(path) = &self.path {
+            codes.extend(read_codes(path)?);
+        }
+
+        let count = codes.len();
+        if count > 0 {
+            tracing::info!("Excluding {} Orphanet conditions from results", count);
+        }
+        *self.codes.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(codes);
+        Ok(count)
+    }
+
+    fn current(&self) -> Arc<HashSet<String>> {
+        self.codes.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.current().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Excluded codes, sorted
+    pub fn codes(&self) -> Vec<String> {
+        let mut codes: Vec<String> = self.current().iter().cloned().collect();
+        codes.sort();
+        codes
+    }
+
+    pub fn is_excluded(&self, orpha_code: Option<&str>) -> bool {
+        orpha_code
+            .and_then(normalize_code)
+            .is_some_and(|code| self.current().contains(&code))
+    }
+
+    /// Drop excluded conditions from ranked results, keeping at most `top_k`
+    pub fn filter<T>(&self, mut results: Vec<T>, top_k: usize, orpha_code: impl Fn(&T) -> Option<&str>) -> Vec<T> {
+        if self.is_empty() {
+            results.truncate(top_k);
+            return results;
+        }
+
+        let mut kept = Vec::with_capacity(results.len().min(top_k));
+        for result in results {
+            if self.is_excluded(orpha_code(&result)) {
+                tracing::debug!("Dropped excluded condition Orpha {} from results", orpha_code(&result).unwrap_or_default());
+                continue;
+            }
+            if kept.len() < top_k {
+                kept.push(result);
+            }
+        }
+        kept
+    }
+}
+
+/// "ORPHA:558", "Orpha 558" and "558" all name the same condition
+fn normalize_code(code: &str) -> Option<String> {
+    let code = code.trim();
+    let code = code
+        .get(..5)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("orpha"))
+        .map_or(code, |_| code[5..].trim_start_matches([':', ' ']));
+    (!code.is_empty()).then(|| code.to_string())
+}
+
+fn read_codes(path: &Path) -> Result<HashSet<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read condition exclusions {:?}", path))?;
+    Ok(content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .filter_map(normalize_code)
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExclusionsResponse {
+    pub count: usize,
+    pub orpha_codes: Vec<String>,
+}
+
+/// `GET /admin/exclusions`: the Orpha codes currently kept out of results
+pub async fn list_exclusions(_admin: AdminAccess, State(state): State<AppState>) -> Json<ExclusionsResponse> {
+    let orpha_codes = state.exclusions.codes();
+    Json(ExclusionsResponse { count: orpha_codes.len(), orpha_codes })
+}
+
+/// `POST /admin/exclusions/reload`: re-read `EXCLUDED_ORPHA_CODES_PATH`
+pub async fn reload_exclusions(
+    _admin: AdminAccess,
+    State(state): State<AppState>,
+) -> Result<Json<ExclusionsResponse>, (StatusCode, String)> {
+    state.exclusions.reload().map_err(|e| {
+        tracing::error!("Condition exclusion reload failed: {:#}", e);
+        (StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e))
+    })?;
+    // Cached answers may name a condition that is now excluded
+    state.answer_cache.invalidate();
+
+    let orpha_codes = state.exclusions.codes();
+    Ok(Json(ExclusionsResponse { count: orpha_codes.len(), orpha_codes }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_from_list_and_file() {
+        let path = std::env::temp_dir().join(format!("quwa-exclusions-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "# out of scope\nORPHA:58\n 324 # comment\n\n").unwrap();
+        let path_value = path.display().to_string();
+
+        let exclusions = ConditionExclusions::from_lookup(&|name| match name {
+            "EXCLUDED_ORPHA_CODES" => Some("Orpha 98896, ,558".to_string()),
+            "EXCLUDED_ORPHA_CODES_PATH" => Some(path_value.clone()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(exclusions.codes(), vec!["324", "558", "58", "98896"]);
+        assert!(exclusions.is_excluded(Some("ORPHA:558")));
+        assert!(!exclusions.is_excluded(Some("5")));
+        assert!(!exclusions.is_excluded(None));
+
+        // Reload picks up edits; a missing file keeps the previous set
+        std::fs::write(&path, "58\n").unwrap();
+        assert_eq!(exclusions.reload().unwrap(), 3);
+        std::fs::remove_file(&path).unwrap();
+        assert!(exclusions.reload().is_err());
+        assert_eq!(exclusions.len(), 3);
+    }
+
+    #[test]
+    fn test_filter_keeps_top_k_after_dropping_excluded() {
+        let exclusions = ConditionExclusions::new(HashSet::from(["58".to_string()]), None).unwrap();
+        let results = vec![Some("58"), None, Some("324"), Some("558")];
+
+        let kept = exclusions.filter(results.clone(), 2, |r| *r);
+        assert_eq!(kept, vec![None, Some("324")]);
+
+        let none = ConditionExclusions::default();
+        assert_eq!(none.filter(results.clone(), 2, |r| *r), results[..2]);
+    }
+}
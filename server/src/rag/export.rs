@@ -0,0 +1,310 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+use crate::auth::AdminAccess;
+use crate::rag::vector_store::{ImportCounts, OnConflict, VectorDocument};
+
+/// Longest accepted import line; a 768-dim vector plus text is well under this
+pub const MAX_IMPORT_LINE_BYTES: usize = 1024 * 1024;
+
+/// Documents written per transaction
+const IMPORT_BATCH_SIZE: usize = 200;
+
+/// Per-line failures reported back in full; the rest are only counted
+const MAX_REPORTED_ERRORS: usize = 10;
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    /// Include the raw vector on each line (`?vectors=false` shrinks the dump)
+    #[serde(default = "default_vectors")]
+    pub vectors: bool,
+}
+
+fn default_vectors() -> bool {
+    true
+}
+
+/// `GET /admin/embeddings/export`: every stored document as NDJSON, one
+/// `VectorDocument` per line, streamed from a database cursor
+pub async fn export_embeddings(
+    _admin: AdminAccess,
+    State(state): State<AppState>,
+    Query(params): Query<ExportQuery>,
+) -> Response {
+    tracing::info!("Exporting embeddings (vectors: {})", params.vectors);
+
+    let lines = state.vector_store.export(params.vectors).map(|document| {
+        let document = document.map_err(|e| {
+            // The status line is already sent, so the only signal left is a truncated body
+            tracing::error!("Embedding export aborted: {}", e);
+            std::io::Error::other(e.to_string())
+        })?;
+        let mut line = serde_json::to_vec(&document).map_err(std::io::Error::other)?;
+        line.push(b'\n');
+        Ok::<_, std::io::Error>(Bytes::from(line))
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    /// `skip` (default) keeps rows already stored for a source; `upsert` overwrites them
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportResponse {
+    pub inserted: usize,
+    pub updated: usize,
+    /// Documents whose source (or id) was already stored
+    pub skipped: usize,
+    pub failed: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+impl ImportResponse {
+    fn fail(&mut self, count: usize, lines: impl std::fmt::Display, reason: impl std::fmt::Display) {
+        self.failed += count;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(format!("{}: {}", lines, reason));
+        }
+    }
+
+    fn add(&mut self, counts: ImportCounts) {
+        self.inserted += counts.inserted;
+        self.updated += counts.updated;
+        self.skipped += counts.skipped;
+    }
+}
+
+/// `POST /admin/embeddings/import`: load an NDJSON dump produced by the export,
+/// keeping original ids, so a new environment can be seeded without re-embedding.
+/// Malformed lines and vectors of the wrong dimension are counted as failures
+/// and the rest of the dump still loads.
+pub async fn import_embeddings(
+    _admin: AdminAccess,
+    State(state): State<AppState>,
+    Query(params): Query<ImportQuery>,
+    body: Body,
+) -> Result<Json<ImportResponse>, (StatusCode, String)> {
+    let dimension = state.embedding_service.dimension();
+    let mut response = ImportResponse::default();
+    let mut lines = LineSplitter::new(MAX_IMPORT_LINE_BYTES);
+    let mut line_number = 0;
+    let mut batch = Batch::default();
+    let mut chunks = body.into_data_stream();
+
+    loop {
+        let chunk = chunks
+            .try_next()
+            .await
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to read body: {}", e)))?;
+        let finished = chunk.is_none();
+
+        let ready = match chunk {
+            Some(chunk) => lines.push(&chunk).map_err(|e| (StatusCode::PAYLOAD_TOO_LARGE, e))?,
+            None => lines.finish().into_iter().collect(),
+        };
+
+        for line in ready {
+            line_number += 1;
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            match parse_line(&line, dimension) {
+                Ok(document) => batch.push(line_number, document),
+                Err(reason) => response.fail(1, format!("line {}", line_number), reason),
+            }
+            if batch.documents.len() >= IMPORT_BATCH_SIZE {
+                batch.flush(&state, params.on_conflict, &mut response).await;
+            }
+        }
+
+        if finished {
+            break;
+        }
+    }
+    batch.flush(&state, params.on_conflict, &mut response).await;
+    // Imported documents can change which condition a query matches
+    state.answer_cache.invalidate();
+
+    tracing::info!(
+        "Embedding import finished: {} inserted, {} updated, {} skipped, {} failed",
+        response.inserted,
+        response.updated,
+        response.skipped,
+        response.failed
+    );
+
+    Ok(Json(response))
+}
+
+/// One NDJSON line as a document ready to store, or why it can't be
+fn parse_line(line: &[u8], dimension: usize) -> Result<VectorDocument, String> {
+    let mut document: VectorDocument =
+        serde_json::from_slice(line).map_err(|e| format!("invalid document: {}", e))?;
+
+    match document.embedding.as_deref() {
+        None => return Err("no embedding (export with vectors=true)".to_string()),
+        Some(embedding) if embedding.len() != dimension => {
+            return Err(format!(
+                "embedding has {} dimensions, this store expects {}",
+                embedding.len(),
+                dimension
+            ));
+        }
+        Some(_) => {}
+    }
+
+    // Hand-written lines may omit the id; give them a fresh one rather than colliding on nil
+    if document.id.is_nil() {
+        document.id = uuid::Uuid::new_v4();
+    }
+    Ok(document)
+}
+
+/// Documents waiting to be written, with the line range they came from for error reports
+#[derive(Default)]
+struct Batch {
+    documents: Vec<VectorDocument>,
+    first_line: usize,
+    last_line: usize,
+}
+
+impl Batch {
+    fn push(&mut self, line: usize, document: VectorDocument) {
+        if self.documents.is_empty() {
+            self.first_line = line;
+        }
+        self.last_line = line;
+        self.documents.push(document);
+    }
+
+    async fn flush(&mut self, state: &AppState, on_conflict: OnConflict, response: &mut ImportResponse) {
+        if self.documents.is_empty() {
+            return;
+        }
+
+        match state.vector_store.add_documents(&self.documents, on_conflict).await {
+            Ok(counts) => response.add(counts),
+            Err(e) => {
+                tracing::error!("Embedding import batch failed: {}", e);
+                response.fail(
+                    self.documents.len(),
+                    format!("lines {}-{}", self.first_line, self.last_line),
+                    e,
+                );
+            }
+        }
+        self.documents.clear();
+    }
+}
+
+/// Splits a chunked byte stream into newline-terminated lines without holding
+/// more than one partial line in memory
+struct LineSplitter {
+    buffer: Vec<u8>,
+    max_line: usize,
+}
+
+impl LineSplitter {
+    fn new(max_line: usize) -> Self {
+        Self { buffer: Vec::new(), max_line }
+    }
+
+    /// Complete lines in `chunk` (plus any carried-over prefix), without the newline
+    fn push(&mut self, chunk: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut lines = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let mut line: Vec<u8> = self.buffer.drain(..=end).collect();
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            lines.push(line);
+        }
+
+        if self.buffer.len() > self.max_line {
+            return Err(format!("Import line exceeds {} bytes", self.max_line));
+        }
+        Ok(lines)
+    }
+
+    /// The trailing line when the body doesn't end with a newline
+    fn finish(&mut self) -> Option<Vec<u8>> {
+        let rest = std::mem::take(&mut self.buffer);
+        (!rest.is_empty()).then_some(rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_splitter_joins_chunks_and_keeps_trailing_line() {
+        let mut lines = LineSplitter::new(64);
+
+        assert_eq!(lines.push(b"{\"a\"").unwrap(), Vec::<Vec<u8>>::new());
+        assert_eq!(lines.push(b":1}\r\n{\"b\":2}\n{\"c\"").unwrap(), vec![b"{\"a\":1}".to_vec(), b"{\"b\":2}".to_vec()]);
+        assert_eq!(lines.push(b":3}").unwrap(), Vec::<Vec<u8>>::new());
+        assert_eq!(lines.finish(), Some(b"{\"c\":3}".to_vec()));
+        assert_eq!(lines.finish(), None);
+    }
+
+    #[test]
+    fn test_line_splitter_rejects_oversized_line() {
+        let mut lines = LineSplitter::new(8);
+
+        assert!(lines.push(b"short\n").is_ok());
+        assert!(lines.push(b"much too long").is_err());
+    }
+
+    #[test]
+    fn test_parse_line_validates_dimension() {
+        let line = br#"{"id":"5a1c7c0e-3f0e-4a55-9d5b-0d7a3f6c2b11","text":"Fabry disease","source_type":"orphadata","source_id":"324","file_name":null,"orpha_code":"324","embedding_length":3,"embedding":[0.1,0.2,0.3],"created_at":"2026-01-02T03:04:05Z"}"#;
+
+        let document = parse_line(line, 3).unwrap();
+        assert_eq!(document.metadata.orpha_code.as_deref(), Some("324"));
+        assert_eq!(document.embedding.as_deref(), Some(&[0.1, 0.2, 0.3][..]));
+
+        assert!(parse_line(line, 384).unwrap_err().contains("expects 384"));
+        assert!(parse_line(b"{not json", 3).unwrap_err().starts_with("invalid document"));
+    }
+
+    #[test]
+    fn test_parse_line_requires_vector_and_fills_missing_id() {
+        // A dump taken with `?vectors=false` can't be restored without re-embedding
+        let without_vector = br#"{"text":"Fabry disease","source_type":"orphadata","source_id":"324","file_name":null,"orpha_code":"324"}"#;
+        assert!(parse_line(without_vector, 3).unwrap_err().contains("no embedding"));
+
+        let without_id = br#"{"text":"Fabry disease","source_type":"orphadata","source_id":"324","file_name":null,"orpha_code":null,"embedding":[1.0]}"#;
+        assert!(!parse_line(without_id, 1).unwrap().id.is_nil());
+    }
+
+    #[test]
+    fn test_import_query_defaults_to_skip() {
+        let query: ImportQuery = serde_json::from_str("{}").unwrap();
+        assert_eq!(query.on_conflict, OnConflict::Skip);
+
+        let query: ImportQuery = serde_json::from_str(r#"{"on_conflict":"upsert"}"#).unwrap();
+        assert_eq!(query.on_conflict, OnConflict::Upsert);
+    }
+}
@@ -0,0 +1,63 @@
+use serde_json::json;
+
+/// Digits in an HPO term ID ("HP:0001249")
+const HPO_ID_DIGITS: usize = 7;
+
+/// HPO term IDs pasted into a query, normalized to "HP:0001249" and deduplicated
+/// in order of appearance. "hp:0001249" and "HP_0001249" (the OBO form) count;
+/// IDs run into other letters or digits don't.
+pub fn extract_hpo_ids(query: &str) -> Vec<String> {
+    let mut ids: Vec<String> = Vec::new();
+    let bytes = query.as_bytes();
+
+    for (start, _) in query.match_indices(['h', 'H']) {
+        let digits = start + 3;
+        let Some(candidate) = bytes.get(start..digits + HPO_ID_DIGITS) else {
+            continue;
+        };
+        let bounded_before = start == 0 || !bytes[start - 1].is_ascii_alphanumeric();
+        let bounded_after = bytes.get(digits + HPO_ID_DIGITS).is_none_or(|b| !b.is_ascii_alphanumeric());
+        if bounded_before
+            && bounded_after
+            && candidate[..2].eq_ignore_ascii_case(b"hp")
+            && matches!(candidate[2], b':' | b'_')
+            && candidate[3..].iter().all(u8::is_ascii_digit)
+        {
+            let id = format!("HP:{}", &query[digits..digits + HPO_ID_DIGITS]);
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+    }
+    ids
+}
+
+/// One `hpo_terms @> pattern` value per ID, matching rows that list that term
+pub fn containment_patterns(hpo_ids: &[String]) -> Vec<serde_json::Value> {
+    hpo_ids.iter().map(|id| json!([{ "hpo_id": id }])).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_hpo_ids() {
+        assert_eq!(
+            extract_hpo_ids("Seizures (HP:0001250), hp:0001249 and HP_0001250; also HP:0001263."),
+            vec!["HP:0001250", "HP:0001249", "HP:0001263"]
+        );
+        assert!(extract_hpo_ids("HP:123 HP:00012490 XHP:0001249 HP:0001249a HP-0001249").is_empty());
+        assert!(extract_hpo_ids("héadache, high fever").is_empty());
+    }
+
+    #[test]
+    fn test_containment_patterns() {
+        let ids = vec!["HP:0001250".to_string(), "HP:0001249".to_string()];
+
+        assert_eq!(
+            containment_patterns(&ids),
+            vec![json!([{ "hpo_id": "HP:0001250" }]), json!([{ "hpo_id": "HP:0001249" }])]
+        );
+    }
+}
@@ -1,23 +1,58 @@
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::AppState;
+use crate::auth::{AdminAccess, InspectAccess};
+use crate::rag::vector_store::{round_score, vector_magnitude, SimilarityStats, VectorDocument, VectorStoreError};
 
 #[derive(Debug, Deserialize)]
 pub struct InspectRequest {
     pub query: String,
     pub top_k: Option<usize>,
     pub min_similarity: Option<f32>,
+    /// Include the raw dot product and vector magnitudes behind each score, and the
+    /// unrounded similarity when `SCORE_DECIMALS` is set
+    #[serde(default)]
+    pub debug: bool,
+    /// Report the score drop after each hit and mark the largest one
+    #[serde(default)]
+    pub gaps: bool,
 }
 
 #[derive(Debug, Serialize)]
 pub struct InspectHit {
     pub text: String,
     pub similarity: f32,
+    /// Full-precision `similarity` (debug requests); serialized as the shortest
+    /// decimal that parses back to the same f32 bits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_similarity: Option<f32>,
     pub source_type: String,
     pub source_id: String,
     pub file_name: Option<String>,
     pub orpha_code: Option<String>,
+    /// Char range of the chunk in its source document, for highlighting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_offset: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_offset: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_dot: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_magnitude: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doc_magnitude: Option<f32>,
+    /// Similarity minus the next hit's (absent on the last hit)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score_gap: Option<f32>,
+    /// The largest drop follows this hit, a natural `top_k`/`min_similarity` cutoff
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub largest_gap: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -26,12 +61,15 @@ pub struct InspectResponse {
     pub hits: Vec<InspectHit>,
 }
 
+/// `POST /api/vector/inspect`: nearest stored chunks for a query. It embeds
+/// arbitrary text and reveals knowledge base contents, so it sits behind `InspectAccess`.
 pub async fn inspect_vectors(
+    _access: InspectAccess,
     State(state): State<AppState>,
     Json(payload): Json<InspectRequest>,
 ) -> Json<InspectResponse> {
     let query = payload.query.trim().to_string();
-    let top_k = payload.top_k.unwrap_or(5).max(1).min(25);
+    let top_k = payload.top_k.unwrap_or(5).clamp(1, 25);
     let min_similarity = payload.min_similarity.unwrap_or(0.0);
 
     if query.is_empty() {
@@ -46,7 +84,16 @@ pub async fn inspect_vectors(
         }
     };
 
-    let results = match state.vector_store.search(embedding, top_k).await {
+    let query_magnitude = payload.debug.then(|| vector_magnitude(&embedding));
+
+    let results = if payload.debug {
+        state.vector_store.search_debug(embedding, top_k).await
+            .map(|hits| hits.into_iter().map(|(t, s, m, d)| (t, s, m, Some(d))).collect())
+    } else {
+        state.vector_store.search(embedding, top_k).await
+            .map(|hits| hits.into_iter().map(|(t, s, m)| (t, s, m, None)).collect())
+    };
+    let results: Vec<_> = match results {
         Ok(results) => results,
         Err(e) => {
             tracing::error!("Vector inspect search error: {}", e);
@@ -54,18 +101,208 @@ pub async fn inspect_vectors(
         }
     };
 
-    let hits = results
+    let mut hits: Vec<InspectHit> = results
         .into_iter()
-        .filter(|(_, similarity, _)| *similarity >= min_similarity)
-        .map(|(text, similarity, metadata)| InspectHit {
+        .filter(|(_, similarity, _, _)| *similarity >= min_similarity)
+        .map(|(text, similarity, metadata, debug)| InspectHit {
             text,
             similarity,
+            raw_similarity: payload.debug.then_some(similarity),
             source_type: metadata.source_type,
             source_id: metadata.source_id,
             file_name: metadata.file_name,
             orpha_code: metadata.orpha_code,
+            start_offset: metadata.start_offset,
+            end_offset: metadata.end_offset,
+            raw_dot: debug.map(|d| d.raw_dot),
+            query_magnitude,
+            doc_magnitude: debug.map(|d| d.doc_magnitude),
+            score_gap: None,
+            largest_gap: false,
         })
         .collect();
 
+    if payload.gaps {
+        annotate_score_gaps(&mut hits);
+    }
+    round_scores(&mut hits, state.config.score_decimals);
+
     Json(InspectResponse { query, hits })
 }
+
+/// Fill `score_gap` on hits already sorted best-first and flag the first hit
+/// followed by the largest drop
+fn annotate_score_gaps(hits: &mut [InspectHit]) {
+    for i in 1..hits.len() {
+        hits[i - 1].score_gap = Some(hits[i - 1].similarity - hits[i].similarity);
+    }
+
+    let largest = hits
+        .iter()
+        .enumerate()
+        .filter_map(|(i, hit)| hit.score_gap.map(|gap| (i, gap)))
+        .fold(None, |best: Option<(usize, f32)>, (i, gap)| match best {
+            Some((_, best_gap)) if best_gap >= gap => best,
+            _ => Some((i, gap)),
+        });
+    if let Some((i, _)) = largest {
+        hits[i].largest_gap = true;
+    }
+}
+
+/// Round displayed scores once filtering and gaps have used the full values
+fn round_scores(hits: &mut [InspectHit], decimals: Option<u32>) {
+    for hit in hits {
+        hit.similarity = round_score(hit.similarity, decimals);
+        hit.score_gap = hit.score_gap.map(|gap| round_score(gap, decimals));
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DocumentQuery {
+    /// Include the stored vector itself (hundreds of floats) in the response
+    #[serde(default)]
+    pub include_vector: bool,
+}
+
+/// `GET /admin/embeddings/{id}`: one stored document in full, for targeted debugging
+pub async fn get_document(
+    _admin: AdminAccess,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<DocumentQuery>,
+) -> Result<Json<VectorDocument>, (StatusCode, String)> {
+    state
+        .vector_store
+        .get_by_id(id, params.include_vector)
+        .await
+        .map(Json)
+        .map_err(|e| match e {
+            VectorStoreError::NotFound(_) => (StatusCode::NOT_FOUND, format!("No document with id {}", id)),
+            e => {
+                tracing::error!("Document lookup failed for {}: {}", id, e);
+                (StatusCode::INTERNAL_SERVER_ERROR, e.code().to_string())
+            }
+        })
+}
+
+/// Default `sample_size` for similarity stats
+const DEFAULT_STATS_SAMPLE: usize = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct SimilarityStatsRequest {
+    /// Text to score the sample against; without one, a random stored document is
+    /// the probe (and may land in its own sample)
+    pub query: Option<String>,
+    /// Documents to sample (defaults to 500, capped at `MAX_STATS_SAMPLE`)
+    pub sample_size: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimilarityStatsResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    /// Stored document used as the probe when no query was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probe_id: Option<Uuid>,
+    /// `null` when the store is empty
+    pub stats: Option<SimilarityStats>,
+}
+
+/// `POST /admin/embeddings/similarity-stats`: how similar a random sample of the
+/// corpus is to a query, to pick `min_similarity`-style thresholds from data
+pub async fn similarity_stats(
+    _admin: AdminAccess,
+    State(state): State<AppState>,
+    Json(payload): Json<SimilarityStatsRequest>,
+) -> Result<Json<SimilarityStatsResponse>, (StatusCode, String)> {
+    let store_error = |e: VectorStoreError| {
+        tracing::error!("Similarity stats failed: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, e.code().to_string())
+    };
+    let query = payload.query.map(|q| q.trim().to_string()).filter(|q| !q.is_empty());
+
+    let (probe_id, embedding) = match &query {
+        Some(query) => {
+            let embedding = state.embedding_service.embed_text(query).await.map_err(|e| {
+                tracing::error!("Similarity stats embedding error: {}", e);
+                (StatusCode::SERVICE_UNAVAILABLE, "embedding_failed".to_string())
+            })?;
+            (None, embedding)
+        }
+        None => match state.vector_store.random_embedding().await.map_err(store_error)? {
+            Some((id, embedding)) => (Some(id), embedding),
+            None => return Ok(Json(SimilarityStatsResponse { query, probe_id: None, stats: None })),
+        },
+    };
+
+    let stats = state
+        .vector_store
+        .sample_similarity_stats(embedding, payload.sample_size.unwrap_or(DEFAULT_STATS_SAMPLE))
+        .await
+        .map_err(store_error)?;
+
+    Ok(Json(SimilarityStatsResponse { query, probe_id, stats }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(similarity: f32) -> InspectHit {
+        InspectHit {
+            text: String::new(),
+            similarity,
+            raw_similarity: None,
+            source_type: "orphadata".to_string(),
+            source_id: String::new(),
+            file_name: None,
+            orpha_code: None,
+            start_offset: None,
+            end_offset: None,
+            raw_dot: None,
+            query_magnitude: None,
+            doc_magnitude: None,
+            score_gap: None,
+            largest_gap: false,
+        }
+    }
+
+    #[test]
+    fn test_score_gaps_mark_largest_drop() {
+        let mut hits: Vec<InspectHit> = [0.9, 0.85, 0.6, 0.55].into_iter().map(hit).collect();
+        annotate_score_gaps(&mut hits);
+
+        let gaps: Vec<Option<f32>> = hits.iter().map(|h| h.score_gap.map(|g| (g * 100.0).round() / 100.0)).collect();
+        assert_eq!(gaps, vec![Some(0.05), Some(0.25), Some(0.05), None]);
+        let flagged: Vec<bool> = hits.iter().map(|h| h.largest_gap).collect();
+        assert_eq!(flagged, vec![false, true, false, false]);
+
+        let json = serde_json::to_value(&hits[3]).unwrap();
+        assert!(json.get("score_gap").is_none());
+        assert!(json.get("largest_gap").is_none());
+    }
+
+    #[test]
+    fn test_score_gaps_need_two_hits() {
+        let mut hits = vec![hit(0.7)];
+        annotate_score_gaps(&mut hits);
+        assert_eq!(hits[0].score_gap, None);
+        assert!(!hits[0].largest_gap);
+
+        annotate_score_gaps(&mut []);
+    }
+
+    #[test]
+    fn test_round_scores_keeps_raw_similarity() {
+        let mut hits = vec![hit(0.876_543_2), hit(0.5)];
+        hits[0].raw_similarity = Some(0.876_543_2);
+        annotate_score_gaps(&mut hits);
+        round_scores(&mut hits, Some(2));
+
+        assert_eq!(hits[0].similarity, 0.88);
+        assert_eq!(hits[0].score_gap, Some(0.38));
+        assert_eq!(hits[0].raw_similarity, Some(0.876_543_2));
+        assert!(hits[0].largest_gap);
+    }
+}
@@ -1,3 +1,7 @@
 pub mod vector_store;
 pub mod context_strategy;
 pub mod inspect;
+pub mod export;
+pub mod eval;
+pub mod exclusions;
+pub mod hpo_search;
@@ -1,7 +1,8 @@
-use anyhow::{Result, Context};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use crate::embeddings::LocalEmbeddingService;
+use crate::embeddings::EmbeddingProvider;
+use crate::processing::orphanet::HPOAssociation;
+use super::exclusions::ConditionExclusions;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DocumentMetadata {
@@ -9,23 +10,358 @@ pub struct DocumentMetadata {
     pub source_id: String,
     pub file_name: Option<String>,
     pub orpha_code: Option<String>,
+    /// Structured HPO associations (Orphanet documents only)
+    #[serde(default)]
+    pub hpo_associations: Vec<HPOAssociation>,
+    /// Char range of the chunk in its source document (uploaded PDFs only)
+    #[serde(default)]
+    pub start_offset: Option<i32>,
+    #[serde(default)]
+    pub end_offset: Option<i32>,
+    /// Imaging modality and body region from the Vision analysis (uploaded images only)
+    #[serde(default)]
+    pub modality: Option<String>,
+    #[serde(default)]
+    pub region: Option<String>,
 }
 
+/// Which stored documents a search may return
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchFilter<'a> {
+    /// Only documents of this `source_type`
+    pub source_type: Option<&'a str>,
+    /// Uploaded-file chunks are only returned for these file ids, so one user's
+    /// documents never reach another user's answers; other sources are unaffected.
+    /// `None` leaves them unrestricted (admin tools).
+    pub user_file_ids: Option<&'a [String]>,
+}
+
+/// Failure modes of the vector store, so callers can decide whether to retry or degrade
+#[derive(Debug, thiserror::Error)]
+pub enum VectorStoreError {
+    /// The database could not be reached or the query failed for a transient reason
+    #[error("Vector store connection error: {0}")]
+    Connection(String),
+    /// The database rejected the query (bad SQL, missing column, constraint), so
+    /// running it again fails the same way
+    #[error("Vector store query error: {0}")]
+    Query(String),
+    /// Query or stored vectors don't match the provider/table dimension
+    #[error("Embedding dimension mismatch: {0}")]
+    DimensionMismatch(String),
+    /// A row or value could not be encoded/decoded
+    #[error("Vector store serialization error: {0}")]
+    Serialization(String),
+    /// The search exceeded `VECTOR_SEARCH_TIMEOUT_MS` and was cancelled
+    #[error("Vector search timed out: {0}")]
+    Timeout(String),
+    /// The vector table (or a requested row) does not exist
+    #[error("Not found in vector store: {0}")]
+    NotFound(String),
+    /// The query vector has no direction (e.g. the all-zeros embedding fallback), so
+    /// every cosine score ties and any ranking would be arbitrary
+    #[error("Query embedding is all zeros; nothing to rank by")]
+    ZeroQuery,
+}
+
+impl VectorStoreError {
+    /// Stable code for API clients (used in SSE `error` events)
+    pub fn code(&self) -> &'static str {
+        match self {
+            VectorStoreError::Connection(_) => "vector_store_unavailable",
+            VectorStoreError::Query(_) => "vector_store_query_error",
+            VectorStoreError::DimensionMismatch(_) => "dimension_mismatch",
+            VectorStoreError::Serialization(_) => "serialization_error",
+            VectorStoreError::Timeout(_) => "vector_store_timeout",
+            VectorStoreError::NotFound(_) => "not_found",
+            VectorStoreError::ZeroQuery => "zero_query_vector",
+        }
+    }
+
+    /// Only connection failures are worth retrying; the rest fail the same way again
+    /// (a retried timeout would just stall the stream twice as long)
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, VectorStoreError::Connection(_))
+    }
+
+    /// The store is down or overloaded rather than misconfigured, so the same
+    /// request is likely to work later
+    pub fn is_transient(&self) -> bool {
+        matches!(self, VectorStoreError::Connection(_) | VectorStoreError::Timeout(_))
+    }
+}
+
+impl From<sqlx::Error> for VectorStoreError {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::RowNotFound => VectorStoreError::NotFound(e.to_string()),
+            sqlx::Error::Encode(_)
+            | sqlx::Error::Decode(_)
+            | sqlx::Error::ColumnDecode { .. }
+            | sqlx::Error::ColumnNotFound(_)
+            | sqlx::Error::TypeNotFound { .. } => VectorStoreError::Serialization(e.to_string()),
+            sqlx::Error::Database(db) => {
+                let message = db.message();
+                // pgvector: "different vector dimensions 384 and 768" / "expected 384 dimensions, not 768"
+                if message.contains("vector dimensions") || message.contains("dimensions, not") {
+                    VectorStoreError::DimensionMismatch(message.to_string())
+                } else if db.code().as_deref() == Some("42P01") {
+                    // undefined_table
+                    VectorStoreError::NotFound(message.to_string())
+                } else if db.code().as_deref() == Some("57014") {
+                    // query_canceled, raised when statement_timeout fires
+                    VectorStoreError::Timeout(message.to_string())
+                } else if db.code().as_deref().is_some_and(is_connection_sqlstate) {
+                    VectorStoreError::Connection(message.to_string())
+                } else {
+                    VectorStoreError::Query(message.to_string())
+                }
+            }
+            sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolTimedOut => {
+                VectorStoreError::Connection(e.to_string())
+            }
+            _ => VectorStoreError::Query(e.to_string()),
+        }
+    }
+}
+
+/// SQLSTATEs meaning the server was unreachable, overloaded or going down rather
+/// than that the query was wrong: class 08 (connection exception), class 53
+/// (insufficient resources) and 57P01-57P03 (shutdown, cannot connect now)
+fn is_connection_sqlstate(code: &str) -> bool {
+    code.starts_with("08") || code.starts_with("53") || matches!(code, "57P01" | "57P02" | "57P03")
+}
+
+/// The db layer returns `anyhow`; recover the underlying sqlx error to classify it
+impl From<anyhow::Error> for VectorStoreError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<sqlx::Error>() {
+            Ok(sqlx_error) => sqlx_error.into(),
+            Err(other) => VectorStoreError::Query(format!("{:#}", other)),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, VectorStoreError>;
+
+/// A stored document with its full text and metadata, as returned by the admin
+/// lookup and written one per line by the NDJSON export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VectorDocument {
+    #[serde(default)]
+    pub id: uuid::Uuid,
+    pub text: String,
+    #[serde(flatten)]
+    pub metadata: DocumentMetadata,
+    /// Number of dimensions in the stored vector (0 if missing)
+    #[serde(default)]
+    pub embedding_length: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f32>>,
+    #[serde(default = "chrono::Utc::now")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// What an import does with a document whose source already has a stored row
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    #[default]
+    Skip,
+    Upsert,
+}
+
+/// Outcome of `RagVectorStore::add_documents`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ImportCounts {
+    pub inserted: usize,
+    pub updated: usize,
+    pub skipped: usize,
+}
+
+impl From<crate::db::models::EmbeddingDocumentRow> for VectorDocument {
+    fn from(row: crate::db::models::EmbeddingDocumentRow) -> Self {
+        VectorDocument {
+            id: row.id,
+            text: row.text,
+            metadata: DocumentMetadata {
+                source_type: row.source_type,
+                source_id: row.source_id,
+                file_name: row.file_name,
+                orpha_code: row.orpha_code,
+                hpo_associations: row.hpo_terms.map(|t| t.0).unwrap_or_default(),
+                start_offset: row.start_offset,
+                end_offset: row.end_offset,
+                modality: row.modality,
+                region: row.region,
+            },
+            embedding_length: row.dimension.unwrap_or(0).max(0) as usize,
+            embedding: row.embedding,
+            created_at: row.created_at,
+        }
+    }
+}
+
+/// Raw components of a cosine score, for explaining why a document ranked where it did
+#[derive(Debug, Clone, Copy)]
+pub struct SimilarityDebug {
+    pub raw_dot: f32,
+    pub doc_magnitude: f32,
+}
+
+/// Query vectors with a smaller norm are treated as all zeros
+const ZERO_QUERY_EPSILON: f32 = 1e-6;
+
+/// Searches with an all-zeros query vector (`ZERO_QUERY_VECTOR`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ZeroQueryMode {
+    /// Return no results (default)
+    #[default]
+    Empty,
+    /// Fail with `VectorStoreError::ZeroQuery`
+    Error,
+}
+
+impl ZeroQueryMode {
+    pub fn parse(value: Option<String>) -> Self {
+        match value.as_deref().map(str::to_lowercase).as_deref() {
+            Some("error") => Self::Error,
+            _ => Self::Empty,
+        }
+    }
+}
+
+/// Table created by the migrations; other tables are cloned from it on demand
+const DEFAULT_VECTOR_TABLE: &str = "embeddings";
+
 pub struct RagVectorStore {
     pool: PgPool,
+    /// Pool used for searches; a read replica when `DATABASE_READ_URL` is set
+    read_pool: PgPool,
+    table: String,
+    /// Per-search statement timeout (`VECTOR_SEARCH_TIMEOUT_MS`)
+    search_timeout: Option<std::time::Duration>,
+    zero_query: ZeroQueryMode,
+    /// Conditions dropped from every search (`EXCLUDED_ORPHA_CODES`)
+    exclusions: ConditionExclusions,
 }
 
 impl RagVectorStore {
+    /// Open the vector table named by `VECTOR_TABLE` (default `embeddings`), searching
+    /// on `DATABASE_READ_URL` if set and bounding each search by `VECTOR_SEARCH_TIMEOUT_MS`
+    pub async fn from_env(
+        pool: &PgPool,
+        embedding_service: std::sync::Arc<dyn EmbeddingProvider>,
+    ) -> anyhow::Result<Self> {
+        let table = std::env::var("VECTOR_TABLE")
+            .unwrap_or_else(|_| DEFAULT_VECTOR_TABLE.to_string());
+        let search_timeout = std::env::var("VECTOR_SEARCH_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|ms| *ms > 0)
+            .map(std::time::Duration::from_millis);
+        let zero_query = ZeroQueryMode::parse(std::env::var("ZERO_QUERY_VECTOR").ok());
+
+        let mut store = Self::new(pool, embedding_service, &table)
+            .await?
+            .with_search_timeout(search_timeout)
+            .with_zero_query_mode(zero_query);
+
+        if let Ok(read_url) = std::env::var("DATABASE_READ_URL") {
+            tracing::info!("Vector searches will use the read replica");
+            store = store.with_read_pool(crate::db::create_read_pool(&read_url).await?);
+        }
+
+        Ok(store)
+    }
+
     pub async fn new(
         pool: &PgPool,
-        _embedding_service: std::sync::Arc<LocalEmbeddingService>,
+        embedding_service: std::sync::Arc<dyn EmbeddingProvider>,
+        table: &str,
     ) -> Result<Self> {
-        tracing::info!("Initialized PostgreSQL vector store with pgvector");
+        validate_table_name(table)?;
+
+        // Separate tables let tests and environments share one database without
+        // clobbering each other's vectors
+        if table != DEFAULT_VECTOR_TABLE {
+            crate::db::queries::create_embeddings_table_like(pool, table).await?;
+            // Migrations only alter `embeddings`; an older clone lacks what they added
+            crate::db::queries::upgrade_embeddings_table(pool, table).await?;
+        }
+
+        // Vectors from different providers can't be compared, so refuse to start
+        // if the table was created for another dimension.
+        if let Some(column_dim) = crate::db::queries::embedding_column_dimension(pool, table).await?
+            && column_dim as usize != embedding_service.dimension()
+        {
+            return Err(VectorStoreError::DimensionMismatch(format!(
+                "Embedding provider '{}' produces {}-dim vectors but the embeddings table stores {}-dim vectors. \
+                 Re-create the table for the new dimension or switch EMBEDDING_PROVIDER back.",
+                embedding_service.name(),
+                embedding_service.dimension(),
+                column_dim
+            )));
+        }
+
+        tracing::info!("Initialized PostgreSQL vector store with pgvector (table: {})", table);
         
         Ok(Self {
             pool: pool.clone(),
+            read_pool: pool.clone(),
+            table: table.to_string(),
+            search_timeout: None,
+            zero_query: ZeroQueryMode::default(),
+            exclusions: ConditionExclusions::default(),
         })
     }
+
+    /// Run searches against a separate (e.g. replica) pool; writes stay on the primary
+    pub fn with_read_pool(mut self, read_pool: PgPool) -> Self {
+        self.read_pool = read_pool;
+        self
+    }
+
+    /// Cancel searches that run longer than `timeout` instead of stalling the caller
+    pub fn with_search_timeout(mut self, timeout: Option<std::time::Duration>) -> Self {
+        self.search_timeout = timeout;
+        self
+    }
+
+    pub fn with_zero_query_mode(mut self, mode: ZeroQueryMode) -> Self {
+        self.zero_query = mode;
+        self
+    }
+
+    /// Never return the conditions in `exclusions` (shared, so reloads apply)
+    pub fn with_exclusions(mut self, exclusions: ConditionExclusions) -> Self {
+        self.exclusions = exclusions;
+        self
+    }
+
+    pub fn exclusions(&self) -> &ConditionExclusions {
+        &self.exclusions
+    }
+
+    /// Rows to fetch so `top_k` remain after excluded conditions are dropped
+    fn fetch_limit(&self, top_k: usize) -> i64 {
+        top_k.saturating_add(self.exclusions.len()) as i64
+    }
+
+    /// `true` when the search should short-circuit to no results: pgvector scores a
+    /// zero vector NaN against everything, which would surface documents in table order
+    fn reject_zero_query(&self, query_embedding: &[f32]) -> Result<bool> {
+        if vector_magnitude(query_embedding) >= ZERO_QUERY_EPSILON {
+            return Ok(false);
+        }
+        match self.zero_query {
+            ZeroQueryMode::Empty => {
+                tracing::warn!("Query embedding is all zeros, returning no results");
+                Ok(true)
+            }
+            ZeroQueryMode::Error => Err(VectorStoreError::ZeroQuery),
+        }
+    }
     
     pub async fn add_document(
         &self,
@@ -36,28 +372,50 @@ impl RagVectorStore {
     ) -> Result<()> {
         crate::db::queries::add_embedding(
             &self.pool,
+            &self.table,
             text,
             embedding,
-            metadata.source_type,
-            metadata.source_id,
-            metadata.file_name,
-            metadata.orpha_code,
+            metadata,
         )
-        .await
-        .context("Failed to insert document into vector store")?;
+        .await?;
         
         Ok(())
     }
     
+    /// Top `top_k` documents by cosine similarity.
+    ///
+    /// Ranking happens in Postgres (`ORDER BY embedding <=> $1 LIMIT $2`), so only
+    /// `top_k` rows are ever transferred and memory stays O(top_k) regardless of
+    /// table size; there is no in-process full scan to bound.
     pub async fn search(
         &self,
         query_embedding: Vec<f32>,
         top_k: usize,
     ) -> Result<Vec<(String, f32, DocumentMetadata)>> {
+        self.search_source(query_embedding, top_k, SearchFilter::default()).await
+    }
+
+    /// Like `search`, restricted by `filter`. With an approximate (HNSW) index the
+    /// filter applies after the index scan, so sparse sources may return fewer than
+    /// `top_k` rows.
+    pub async fn search_source(
+        &self,
+        query_embedding: Vec<f32>,
+        top_k: usize,
+        filter: SearchFilter<'_>,
+    ) -> Result<Vec<(String, f32, DocumentMetadata)>> {
+        if self.reject_zero_query(&query_embedding)? {
+            return Ok(vec![]);
+        }
+
         let results = crate::db::queries::search_embeddings(
-            &self.pool,
+            &self.read_pool,
+            &self.table,
             query_embedding,
-            top_k as i64,
+            self.fetch_limit(top_k),
+            self.search_timeout,
+            filter.source_type,
+            filter.user_file_ids,
         )
         .await?;
         
@@ -65,29 +423,439 @@ impl RagVectorStore {
         // Cast f64 similarity scores to f32 for consistency
         let formatted_results = results
             .into_iter()
-            .map(|(text, similarity, source_type, source_id, file_name, orpha_code)| {
-                let metadata = DocumentMetadata {
-                    source_type,
-                    source_id,
-                    file_name,
-                    orpha_code,
-                };
-                (text, similarity as f32, metadata)
-            })
+            .map(Self::split_row)
             .collect();
         
-        Ok(formatted_results)
+        Ok(self.exclusions.filter(formatted_results, top_k, |(_, _, meta)| meta.orpha_code.as_deref()))
+    }
+
+    /// Documents listing the HPO terms `hpo_ids` (exact keyword match, not
+    /// semantic), most IDs listed first. Scored by cosine similarity to
+    /// `query_embedding`, so they compare with `search` results.
+    pub async fn search_hpo_ids(
+        &self,
+        query_embedding: Vec<f32>,
+        hpo_ids: &[String],
+        top_k: usize,
+    ) -> Result<Vec<(String, f32, DocumentMetadata)>> {
+        if hpo_ids.is_empty() || self.reject_zero_query(&query_embedding)? {
+            return Ok(vec![]);
+        }
+
+        let rows = crate::db::queries::search_embeddings_by_hpo_ids(
+            &self.read_pool,
+            &self.table,
+            hpo_ids,
+            query_embedding,
+            self.fetch_limit(top_k),
+            self.search_timeout,
+        )
+        .await?;
+
+        let results = rows.into_iter().map(Self::split_row).collect();
+
+        Ok(self.exclusions.filter(results, top_k, |(_, _, meta)| meta.orpha_code.as_deref()))
+    }
+
+    /// Fetch one stored document by id, including the raw vector if `include_vector`
+    pub async fn get_by_id(&self, id: uuid::Uuid, include_vector: bool) -> Result<VectorDocument> {
+        let row = crate::db::queries::get_embedding_by_id(&self.read_pool, &self.table, id, include_vector)
+            .await?
+            .ok_or_else(|| VectorStoreError::NotFound(format!("document {}", id)))?;
+
+        Ok(row.into())
+    }
+
+    /// Stream every stored document (export). Reads from the read pool via a cursor.
+    pub fn export(
+        &self,
+        include_vector: bool,
+    ) -> impl futures::Stream<Item = Result<VectorDocument>> + Send + 'static {
+        use futures::StreamExt;
+
+        crate::db::queries::stream_embeddings(self.read_pool.clone(), self.table.clone(), include_vector)
+            .map(|row| row.map(VectorDocument::from).map_err(VectorStoreError::from))
+    }
+
+    /// Bulk-insert exported documents (one transaction), matching existing rows by
+    /// source as described on `queries::import_embeddings`. Vectors must already
+    /// have this store's dimension.
+    pub async fn add_documents(&self, documents: &[VectorDocument], on_conflict: OnConflict) -> Result<ImportCounts> {
+        let counts = crate::db::queries::import_embeddings(
+            &self.pool,
+            &self.table,
+            documents,
+            on_conflict == OnConflict::Upsert,
+        )
+        .await?;
+
+        Ok(counts)
+    }
+
+    fn split_row(row: crate::db::models::EmbeddingSearchRow) -> (String, f32, DocumentMetadata) {
+        let metadata = DocumentMetadata {
+            source_type: row.source_type,
+            source_id: row.source_id,
+            file_name: row.file_name,
+            orpha_code: row.orpha_code,
+            hpo_associations: row.hpo_terms.map(|t| t.0).unwrap_or_default(),
+            start_offset: row.start_offset,
+            end_offset: row.end_offset,
+            modality: row.modality,
+            region: row.region,
+        };
+        (row.text, row.similarity as f32, metadata)
     }
     
+    /// Same ranking as `search`, with the dot product and stored vector norm per hit
+    pub async fn search_debug(
+        &self,
+        query_embedding: Vec<f32>,
+        top_k: usize,
+    ) -> Result<Vec<(String, f32, DocumentMetadata, SimilarityDebug)>> {
+        if self.reject_zero_query(&query_embedding)? {
+            return Ok(vec![]);
+        }
+
+        let results = crate::db::queries::search_embeddings_debug(
+            &self.read_pool,
+            &self.table,
+            query_embedding,
+            self.fetch_limit(top_k),
+            self.search_timeout,
+        )
+        .await?;
+
+        let formatted_results: Vec<_> = results
+            .into_iter()
+            .map(|row| {
+                let debug = SimilarityDebug {
+                    raw_dot: row.raw_dot as f32,
+                    doc_magnitude: row.doc_magnitude as f32,
+                };
+                let (text, similarity, metadata) = Self::split_row(row.hit);
+                (text, similarity, metadata, debug)
+            })
+            .collect();
+
+        Ok(self.exclusions.filter(formatted_results, top_k, |(_, _, meta, _)| meta.orpha_code.as_deref()))
+    }
+
     pub async fn count(&self) -> usize {
-        crate::db::queries::count_embeddings(&self.pool)
+        crate::db::queries::count_embeddings(&self.pool, &self.table)
             .await
             .unwrap_or(0) as usize
     }
     
     pub async fn count_by_source(&self, source_type: &str) -> Result<usize> {
-        let count = crate::db::queries::count_embeddings_by_source(&self.pool, source_type)
+        let count = crate::db::queries::count_embeddings_by_source(&self.pool, &self.table, source_type)
             .await?;
         Ok(count as usize)
     }
+
+    /// Similarity distribution of a random sample of at most `sample_size` stored
+    /// documents against `query_embedding`, for calibrating thresholds such as
+    /// `min_similarity`. `None` when the store is empty.
+    pub async fn sample_similarity_stats(
+        &self,
+        query_embedding: Vec<f32>,
+        sample_size: usize,
+    ) -> Result<Option<SimilarityStats>> {
+        let row = crate::db::queries::sample_similarity_stats(
+            &self.read_pool,
+            &self.table,
+            query_embedding,
+            sample_size.clamp(1, MAX_STATS_SAMPLE) as i64,
+            self.search_timeout,
+        )
+        .await?;
+
+        Ok(SimilarityStats::from_row(row))
+    }
+
+    /// A randomly chosen stored document's id and vector, to probe the corpus with itself
+    pub async fn random_embedding(&self) -> Result<Option<(uuid::Uuid, Vec<f32>)>> {
+        Ok(crate::db::queries::random_embedding(&self.read_pool, &self.table).await?)
+    }
+
+    /// Remove every document from one source (e.g. a user file's chunks)
+    pub async fn delete_by_source(&self, source_type: &str, source_id: &str) -> Result<usize> {
+        let deleted = crate::db::queries::delete_embeddings_by_source(&self.pool, &self.table, source_type, source_id)
+            .await?;
+        Ok(deleted as usize)
+    }
+}
+
+/// Euclidean norm of a vector
+pub fn vector_magnitude(vector: &[f32]) -> f32 {
+    vector.iter().map(|x| x * x).sum::<f32>().sqrt()
+}
+
+/// Largest sample `sample_similarity_stats` will score
+pub const MAX_STATS_SAMPLE: usize = 5000;
+
+/// Summary of sampled similarity scores (see `RagVectorStore::sample_similarity_stats`)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SimilarityStats {
+    pub sampled: usize,
+    pub mean: f32,
+    /// Population standard deviation
+    pub stddev: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl SimilarityStats {
+    fn from_row(row: crate::db::models::SimilarityStatsRow) -> Option<Self> {
+        Some(Self {
+            sampled: usize::try_from(row.sampled).ok().filter(|n| *n > 0)?,
+            mean: row.mean? as f32,
+            stddev: row.stddev.unwrap_or(0.0) as f32,
+            min: row.min? as f32,
+            max: row.max? as f32,
+        })
+    }
+}
+
+/// Most decimals an f32 score can meaningfully carry
+pub const MAX_SCORE_DECIMALS: u32 = 7;
+
+/// `score` rounded to `decimals` places for display (unchanged when `None`).
+/// Only response fields are rounded; sorting and thresholds use the full score.
+pub fn round_score(score: f32, decimals: Option<u32>) -> f32 {
+    let Some(decimals) = decimals else {
+        return score;
+    };
+    let scale = 10f64.powi(decimals.min(MAX_SCORE_DECIMALS) as i32);
+    ((score as f64 * scale).round() / scale) as f32
+}
+
+/// Cosine similarity of two vectors; 0.0 if either is all zeros. Only used for
+/// in-process comparisons such as PDF near-duplicate chunk dedup; retrieval
+/// scores come from pgvector (`<=>`).
+///
+/// With the `simd` feature this uses `cosine_similarity_lanes`, otherwise the scalar loop.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    #[cfg(feature = "simd")]
+    return cosine_similarity_lanes(a, b);
+
+    #[cfg(not(feature = "simd"))]
+    cosine_similarity_scalar(a, b)
+}
+
+#[cfg_attr(feature = "simd", allow(dead_code))]
+fn cosine_similarity_scalar(a: &[f32], b: &[f32]) -> f32 {
+    let denom = vector_magnitude(a) * vector_magnitude(b);
+    if denom == 0.0 {
+        return 0.0;
+    }
+    a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>() / denom
+}
+
+/// Independent accumulators per pass; 8 f32 lanes fill one AVX register (two on SSE/NEON)
+const LANES: usize = 8;
+
+/// Cosine similarity with dot product and both norms accumulated in one pass over
+/// `LANES`-wide chunks. The fixed-size inner loops have no cross-iteration dependency,
+/// so LLVM emits packed SIMD on stable Rust without `std::simd` or an extra crate.
+/// Summation order differs from the scalar loop, so results match within float tolerance.
+#[cfg_attr(not(feature = "simd"), allow(dead_code))]
+fn cosine_similarity_lanes(a: &[f32], b: &[f32]) -> f32 {
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+
+    let mut dot = [0.0f32; LANES];
+    let mut norm_a = [0.0f32; LANES];
+    let mut norm_b = [0.0f32; LANES];
+
+    let chunks_a = a.chunks_exact(LANES);
+    let chunks_b = b.chunks_exact(LANES);
+    let (tail_a, tail_b) = (chunks_a.remainder(), chunks_b.remainder());
+
+    for (xs, ys) in chunks_a.zip(chunks_b) {
+        for lane in 0..LANES {
+            dot[lane] += xs[lane] * ys[lane];
+            norm_a[lane] += xs[lane] * xs[lane];
+            norm_b[lane] += ys[lane] * ys[lane];
+        }
+    }
+
+    let mut dot: f32 = dot.iter().sum();
+    let mut norm_a: f32 = norm_a.iter().sum();
+    let mut norm_b: f32 = norm_b.iter().sum();
+    for (x, y) in tail_a.iter().zip(tail_b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+
+    let denom = norm_a.sqrt() * norm_b.sqrt();
+    if denom == 0.0 {
+        return 0.0;
+    }
+    dot / denom
+}
+
+/// The table name is interpolated into SQL, so only plain identifiers are allowed
+fn validate_table_name(table: &str) -> Result<()> {
+    let mut chars = table.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && table.len() <= 63;
+
+    // Such a table can never exist, so report it as missing rather than running the SQL
+    if !valid {
+        return Err(VectorStoreError::NotFound(format!(
+            "Invalid vector table name '{}': use letters, digits and underscores (max 63 chars)",
+            table
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store whose pool never connects, so any query that reaches the database fails
+    fn offline_store(zero_query: ZeroQueryMode) -> RagVectorStore {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_millis(50))
+            .connect_lazy("postgres://nobody@127.0.0.1:1/none")
+            .unwrap();
+        RagVectorStore {
+            pool: pool.clone(),
+            read_pool: pool,
+            table: DEFAULT_VECTOR_TABLE.to_string(),
+            search_timeout: None,
+            zero_query,
+            exclusions: ConditionExclusions::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_zero_query_vector_returns_no_documents() {
+        let store = offline_store(ZeroQueryMode::Empty);
+        assert!(store.search(vec![0.0; 384], 10).await.unwrap().is_empty());
+        assert!(store.search_debug(vec![1e-9; 384], 10).await.unwrap().is_empty());
+
+        // A real vector goes to the database (unreachable here)
+        assert!(store.search(vec![0.1; 384], 10).await.is_err());
+
+        let error = offline_store(ZeroQueryMode::Error).search(vec![0.0; 384], 10).await.unwrap_err();
+        assert!(matches!(error, VectorStoreError::ZeroQuery));
+        assert!(!error.is_transient());
+    }
+
+    #[test]
+    fn test_zero_query_mode_parse() {
+        assert_eq!(ZeroQueryMode::parse(Some("ERROR".to_string())), ZeroQueryMode::Error);
+        assert_eq!(ZeroQueryMode::parse(Some("empty".to_string())), ZeroQueryMode::Empty);
+        assert_eq!(ZeroQueryMode::parse(None), ZeroQueryMode::Empty);
+    }
+
+    #[test]
+    fn test_similarity_stats_from_row() {
+        let row = |sampled: i64, value: Option<f64>| crate::db::models::SimilarityStatsRow {
+            sampled,
+            mean: value,
+            stddev: value.map(|_| 0.1),
+            min: value.map(|v| v - 0.2),
+            max: value.map(|v| v + 0.2),
+        };
+
+        let stats = SimilarityStats::from_row(row(3, Some(0.5))).unwrap();
+        assert_eq!(stats.sampled, 3);
+        assert_eq!(stats.mean, 0.5);
+        assert_eq!(stats.stddev, 0.1);
+        assert_eq!((stats.min, stats.max), (0.3, 0.7));
+
+        // An empty table aggregates to NULLs
+        assert!(SimilarityStats::from_row(row(0, None)).is_none());
+    }
+
+    #[test]
+    fn test_round_score() {
+        assert_eq!(round_score(0.876_543_2, None), 0.876_543_2);
+        assert_eq!(round_score(0.876_543_2, Some(3)), 0.877);
+        assert_eq!(round_score(0.876_543_2, Some(0)), 1.0);
+        // Rounded scores serialize at the requested precision, not as float noise
+        assert_eq!(serde_json::to_string(&round_score(0.123_456, Some(2))).unwrap(), "0.12");
+        assert_eq!(round_score(0.123_456_78, Some(12)), round_score(0.123_456_78, Some(MAX_SCORE_DECIMALS)));
+    }
+
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(VectorStoreError::from(sqlx::Error::RowNotFound).code(), "not_found");
+        assert_eq!(VectorStoreError::from(sqlx::Error::PoolTimedOut).code(), "vector_store_unavailable");
+        assert!(VectorStoreError::from(sqlx::Error::PoolTimedOut).is_retryable());
+
+        let wrapped = anyhow::Error::from(sqlx::Error::ColumnNotFound("hpo_terms".to_string()));
+        let err = VectorStoreError::from(wrapped);
+        assert_eq!(err.code(), "serialization_error");
+        assert!(!err.is_retryable());
+        assert!(!err.is_transient());
+
+        // Anything the database can't fix by being asked again is a query error
+        let err = VectorStoreError::from(sqlx::Error::Protocol("unexpected message".to_string()));
+        assert_eq!(err.code(), "vector_store_query_error");
+        assert!(!err.is_retryable());
+        assert!(!err.is_transient());
+        assert!(is_connection_sqlstate("08006"));
+        assert!(is_connection_sqlstate("53300"));
+        assert!(is_connection_sqlstate("57P01"));
+        assert!(!is_connection_sqlstate("42703"));
+        assert!(!is_connection_sqlstate("23505"));
+
+        // Outages are transient for clients even when not retried server-side
+        assert!(VectorStoreError::Timeout("search".to_string()).is_transient());
+        assert!(!VectorStoreError::Timeout("search".to_string()).is_retryable());
+    }
+
+    /// Deterministic pseudo-random vectors in [-1, 1) (xorshift, no extra deps)
+    fn test_vectors(count: usize, dim: usize) -> Vec<Vec<f32>> {
+        let mut state: u32 = 0x9E37_79B9;
+        (0..count)
+            .map(|_| {
+                (0..dim)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 17;
+                        state ^= state << 5;
+                        (state as f32 / u32::MAX as f32) * 2.0 - 1.0
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cosine_lanes_matches_scalar() {
+        for dim in [3, 8, 384, 768, 771] {
+            let vectors = test_vectors(20, dim);
+            for pair in vectors.windows(2) {
+                let scalar = cosine_similarity_scalar(&pair[0], &pair[1]);
+                let lanes = cosine_similarity_lanes(&pair[0], &pair[1]);
+                assert!((scalar - lanes).abs() < 1e-5, "dim {}: {} vs {}", dim, scalar, lanes);
+            }
+        }
+
+        let v = [0.5, -0.25, 1.0];
+        assert!((cosine_similarity_lanes(&v, &v) - 1.0).abs() < 1e-6);
+        assert_eq!(cosine_similarity_lanes(&[0.0; 16], &[1.0; 16]), 0.0);
+        assert_eq!(cosine_similarity_lanes(&[], &[]), 0.0);
+    }
+
+    #[test]
+    fn test_validate_table_name() {
+        assert!(validate_table_name("embeddings").is_ok());
+        assert!(validate_table_name("embeddings_test_1").is_ok());
+        assert!(validate_table_name("_staging").is_ok());
+
+        assert!(validate_table_name("").is_err());
+        assert!(validate_table_name("1embeddings").is_err());
+        assert!(validate_table_name("embeddings; DROP TABLE users").is_err());
+        assert!(validate_table_name("public.embeddings").is_err());
+        assert!(validate_table_name(&"a".repeat(64)).is_err());
+    }
 }
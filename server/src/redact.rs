@@ -0,0 +1,83 @@
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether user content is kept out of logs (`LOG_REDACT_PII`); on until told otherwise
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Set once at startup from `Config::log_redact_pii`
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        tracing::warn!("LOG_REDACT_PII=false: patient text and file names will appear in logs");
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Per-process salt, so log hashes correlate lines within a run but can't be
+/// matched against a dictionary of common symptoms
+fn salt() -> &'static [u8; 16] {
+    static SALT: OnceLock<[u8; 16]> = OnceLock::new();
+    SALT.get_or_init(|| *uuid::Uuid::new_v4().as_bytes())
+}
+
+/// User content (medical text, file names) in a log line: written as its length
+/// and a short salted hash while redaction is on, verbatim otherwise
+pub fn pii(text: &str) -> Pii<'_> {
+    Pii { text, max_chars: None }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Pii<'a> {
+    text: &'a str,
+    max_chars: Option<usize>,
+}
+
+impl Pii<'_> {
+    /// Only show the first `max_chars` characters when redaction is off
+    pub fn truncated(self, max_chars: usize) -> Self {
+        Self { max_chars: Some(max_chars), ..self }
+    }
+
+    fn render(&self, redact: bool) -> String {
+        if redact {
+            let mut hasher = Sha256::new();
+            hasher.update(salt());
+            hasher.update(self.text.as_bytes());
+            let hash = format!("{:x}", hasher.finalize());
+            return format!("[redacted {} chars #{}]", self.text.chars().count(), &hash[..8]);
+        }
+        match self.max_chars {
+            Some(max) => self.text.chars().take(max).collect(),
+            None => self.text.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Pii<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(is_enabled()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_hides_text_but_keeps_length_and_identity() {
+        let message = pii("my daughter has seizures and an enlarged head");
+        let redacted = message.render(true);
+        assert!(redacted.starts_with("[redacted 45 chars #"));
+        assert!(!redacted.contains("seizures"));
+        assert_eq!(redacted, pii("my daughter has seizures and an enlarged head").truncated(10).render(true));
+        assert_ne!(redacted, pii("my son has seizures and an enlarged head").render(true));
+
+        assert_eq!(message.render(false), "my daughter has seizures and an enlarged head");
+        assert_eq!(message.truncated(11).render(false), "my daughter");
+    }
+}
@@ -0,0 +1,337 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures_util::StreamExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::auth::AuthConfig;
+use crate::config::Lookup;
+
+/// Appwrite rejects single requests over 5MB; larger files go up in chunks of this size
+const APPWRITE_CHUNK_SIZE: usize = 5 * 1024 * 1024;
+
+/// Bytes read per chunk when streaming a locally stored file back
+const LOCAL_READ_CHUNK: usize = 64 * 1024;
+
+/// Where the original bytes of uploaded files are kept (`FILE_STORAGE`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageBackend {
+    /// Bytes are dropped after processing; nothing can be downloaded or reprocessed
+    None,
+    /// Files under `FILE_STORAGE_DIR`, for single-node deployments and development
+    Local { dir: PathBuf },
+    /// An Appwrite Storage bucket, written with a server API key
+    Appwrite {
+        endpoint: String,
+        project_id: String,
+        api_key: String,
+        bucket_id: String,
+    },
+}
+
+impl StorageBackend {
+    /// Value recorded in `uploaded_files.storage_backend`
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            StorageBackend::None => None,
+            StorageBackend::Local { .. } => Some("local"),
+            StorageBackend::Appwrite { .. } => Some("appwrite"),
+        }
+    }
+}
+
+/// Where one file's bytes ended up
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredFile {
+    /// `StorageBackend::name` of the backend holding the bytes; `None` when they were dropped
+    pub backend: Option<String>,
+    pub file_id: String,
+    pub bucket_id: String,
+}
+
+/// Bytes of a stored file, streamed rather than buffered
+pub type FileStream = BoxStream<'static, std::io::Result<Bytes>>;
+
+/// Keeps the original bytes of uploads so they can be downloaded by their owner
+/// and reprocessed later. Cloning is cheap; one instance lives in `AppState`.
+#[derive(Clone)]
+pub struct FileStorage {
+    backend: Arc<StorageBackend>,
+    http_client: reqwest::Client,
+}
+
+impl FileStorage {
+    pub fn new(backend: StorageBackend) -> Self {
+        Self { backend: Arc::new(backend), http_client: reqwest::Client::new() }
+    }
+
+    /// Build from `FILE_STORAGE` (`none`, `local` or `appwrite`). The Appwrite
+    /// backend reuses the endpoint and project from `auth` and needs
+    /// `APPWRITE_API_KEY`; the bucket is `APPWRITE_BUCKET_ID`.
+    pub fn from_env(auth: &AuthConfig) -> Result<Self> {
+        Self::from_lookup(&|name| std::env::var(name).ok(), auth)
+    }
+
+    pub fn from_lookup(lookup: Lookup<'_>, auth: &AuthConfig) -> Result<Self> {
+        let backend = match lookup("FILE_STORAGE").unwrap_or_default().trim().to_lowercase().as_str() {
+            "" | "none" => StorageBackend::None,
+            "local" => StorageBackend::Local {
+                dir: lookup("FILE_STORAGE_DIR").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("uploads")),
+            },
+            "appwrite" => StorageBackend::Appwrite {
+                endpoint: auth.appwrite_endpoint.trim_end_matches('/').to_string(),
+                project_id: auth.appwrite_project_id.clone()
+                    .context("FILE_STORAGE=appwrite requires APPWRITE_PROJECT_ID")?,
+                api_key: lookup("APPWRITE_API_KEY")
+                    .context("FILE_STORAGE=appwrite requires APPWRITE_API_KEY")?,
+                bucket_id: lookup("APPWRITE_BUCKET_ID").unwrap_or_else(|| "medical_files".to_string()),
+            },
+            other => anyhow::bail!("Unknown FILE_STORAGE '{}' (expected none, local or appwrite)", other),
+        };
+
+        Ok(Self::new(backend))
+    }
+
+    pub fn backend(&self) -> &StorageBackend {
+        &self.backend
+    }
+
+    /// Store `data` under `file_id`. With no backend configured nothing is written
+    /// and the returned ids are placeholders.
+    pub async fn put(&self, file_id: Uuid, file_name: &str, data: &Bytes) -> Result<StoredFile> {
+        let backend = self.backend.name().map(str::to_string);
+        match self.backend.as_ref() {
+            StorageBackend::None => Ok(StoredFile {
+                backend,
+                file_id: file_id.to_string(),
+                bucket_id: "medical_files".to_string(),
+            }),
+            StorageBackend::Local { dir } => {
+                tokio::fs::create_dir_all(dir)
+                    .await
+                    .with_context(|| format!("Failed to create storage dir {:?}", dir))?;
+                let path = dir.join(file_id.to_string());
+                tokio::fs::write(&path, data)
+                    .await
+                    .with_context(|| format!("Failed to write {:?}", path))?;
+
+                Ok(StoredFile {
+                    backend,
+                    file_id: file_id.to_string(),
+                    bucket_id: dir.display().to_string(),
+                })
+            }
+            StorageBackend::Appwrite { endpoint, project_id, api_key, bucket_id } => {
+                let url = format!("{}/storage/buckets/{}/files", endpoint, bucket_id);
+                let total = data.len();
+                let mut start = 0;
+
+                // Always at least one request, so empty files are stored too
+                loop {
+                    let end = (start + APPWRITE_CHUNK_SIZE).min(total);
+                    // Appwrite sniffs the MIME type itself; ours stays on the database row
+                    let part = reqwest::multipart::Part::stream(data.slice(start..end))
+                        .file_name(file_name.to_string());
+                    let form = reqwest::multipart::Form::new()
+                        .text("fileId", file_id.to_string())
+                        .part("file", part);
+
+                    let mut request = self.http_client
+                        .post(&url)
+                        .header("X-Appwrite-Project", project_id)
+                        .header("X-Appwrite-Key", api_key)
+                        .multipart(form);
+                    if total > APPWRITE_CHUNK_SIZE {
+                        request = request
+                            .header("Content-Range", format!("bytes {}-{}/{}", start, end.saturating_sub(1), total));
+                        if start > 0 {
+                            request = request.header("X-Appwrite-ID", file_id.to_string());
+                        }
+                    }
+
+                    let response = request.send().await.context("Failed to call Appwrite Storage")?;
+                    let status = response.status();
+                    if !status.is_success() {
+                        let body = response.text().await.unwrap_or_default();
+                        anyhow::bail!("Appwrite Storage returned error {}: {}", status, body);
+                    }
+
+                    start = end;
+                    if start >= total {
+                        break;
+                    }
+                }
+
+                Ok(StoredFile {
+                    backend,
+                    file_id: file_id.to_string(),
+                    bucket_id: bucket_id.clone(),
+                })
+            }
+        }
+    }
+
+    /// Stream back a file stored by `put`. Fails when `stored.backend` is not the
+    /// backend configured now (the bytes live somewhere this server can't reach).
+    pub async fn open(&self, stored: &StoredFile) -> Result<FileStream> {
+        if stored.backend.is_none() || stored.backend.as_deref() != self.backend.name() {
+            anyhow::bail!(
+                "File is stored with backend {:?}, but FILE_STORAGE is {:?}",
+                stored.backend,
+                self.backend.name()
+            );
+        }
+
+        match self.backend.as_ref() {
+            StorageBackend::None => unreachable!("checked above"),
+            // The directory the file was written to, even if FILE_STORAGE_DIR moved since
+            StorageBackend::Local { .. } => {
+                let path = PathBuf::from(&stored.bucket_id).join(&stored.file_id);
+                let mut file = tokio::fs::File::open(&path)
+                    .await
+                    .with_context(|| format!("Failed to open {:?}", path))?;
+
+                let chunks = async_stream::stream! {
+                    loop {
+                        let mut buf = vec![0; LOCAL_READ_CHUNK];
+                        match file.read(&mut buf).await {
+                            Ok(0) => break,
+                            Ok(n) => {
+                                buf.truncate(n);
+                                yield Ok(Bytes::from(buf));
+                            }
+                            Err(e) => {
+                                yield Err(e);
+                                break;
+                            }
+                        }
+                    }
+                };
+                Ok(chunks.boxed())
+            }
+            StorageBackend::Appwrite { endpoint, project_id, api_key, .. } => {
+                let response = self.http_client
+                    .get(format!(
+                        "{}/storage/buckets/{}/files/{}/download",
+                        endpoint, stored.bucket_id, stored.file_id
+                    ))
+                    .header("X-Appwrite-Project", project_id)
+                    .header("X-Appwrite-Key", api_key)
+                    .send()
+                    .await
+                    .context("Failed to call Appwrite Storage")?;
+                let status = response.status();
+                if !status.is_success() {
+                    let body = response.text().await.unwrap_or_default();
+                    anyhow::bail!("Appwrite Storage returned error {}: {}", status, body);
+                }
+
+                Ok(response.bytes_stream().map(|chunk| chunk.map_err(std::io::Error::other)).boxed())
+            }
+        }
+    }
+
+    /// Remove a file stored by `put`, e.g. when recording it afterwards failed.
+    /// A no-op for files whose bytes were dropped.
+    pub async fn delete(&self, stored: &StoredFile) -> Result<()> {
+        match self.backend.as_ref() {
+            StorageBackend::None => Ok(()),
+            StorageBackend::Local { .. } => {
+                let path = PathBuf::from(&stored.bucket_id).join(&stored.file_id);
+                tokio::fs::remove_file(&path)
+                    .await
+                    .with_context(|| format!("Failed to remove {:?}", path))
+            }
+            StorageBackend::Appwrite { endpoint, project_id, api_key, .. } => {
+                let response = self.http_client
+                    .delete(format!("{}/storage/buckets/{}/files/{}", endpoint, stored.bucket_id, stored.file_id))
+                    .header("X-Appwrite-Project", project_id)
+                    .header("X-Appwrite-Key", api_key)
+                    .send()
+                    .await
+                    .context("Failed to call Appwrite Storage")?;
+                let status = response.status();
+                if !status.is_success() {
+                    let body = response.text().await.unwrap_or_default();
+                    anyhow::bail!("Appwrite Storage returned error {}: {}", status, body);
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(vars: &[(&str, &str)], auth: &AuthConfig) -> Result<FileStorage> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        FileStorage::from_lookup(&|name| vars.get(name).cloned(), auth)
+    }
+
+    #[test]
+    fn test_backend_from_lookup() {
+        let auth = AuthConfig::from_lookup(&|name| match name {
+            "APPWRITE_ENDPOINT" => Some("http://appwrite.local/v1/".to_string()),
+            "APPWRITE_PROJECT_ID" => Some("quwa".to_string()),
+            _ => None,
+        });
+
+        assert_eq!(*load(&[], &auth).unwrap().backend(), StorageBackend::None);
+        assert_eq!(
+            *load(&[("FILE_STORAGE", "local"), ("FILE_STORAGE_DIR", "/data/files")], &auth).unwrap().backend(),
+            StorageBackend::Local { dir: PathBuf::from("/data/files") }
+        );
+        assert_eq!(
+            *load(&[("FILE_STORAGE", "Appwrite"), ("APPWRITE_API_KEY", "k")], &auth).unwrap().backend(),
+            StorageBackend::Appwrite {
+                endpoint: "http://appwrite.local/v1".to_string(),
+                project_id: "quwa".to_string(),
+                api_key: "k".to_string(),
+                bucket_id: "medical_files".to_string(),
+            }
+        );
+
+        assert!(load(&[("FILE_STORAGE", "appwrite")], &auth).is_err());
+        assert!(load(&[("FILE_STORAGE", "s3")], &auth).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_local_round_trip() {
+        let dir = std::env::temp_dir().join(format!("quwa-storage-test-{}", Uuid::new_v4()));
+        let storage = FileStorage::new(StorageBackend::Local { dir: dir.clone() });
+        let data = Bytes::from(vec![7u8; LOCAL_READ_CHUNK * 2 + 10]);
+
+        let stored = storage.put(Uuid::new_v4(), "report.pdf", &data).await.unwrap();
+        assert_eq!(stored.backend.as_deref(), Some("local"));
+
+        let chunks: Vec<Bytes> = storage.open(&stored).await.unwrap()
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks.concat(), data.to_vec());
+
+        // Files written before FILE_STORAGE_DIR changed are still found
+        let moved = FileStorage::new(StorageBackend::Local { dir: dir.join("moved") });
+        assert!(moved.open(&stored).await.is_ok());
+
+        // Bytes recorded before storage was configured can't be served
+        let unstored = StoredFile { backend: None, ..stored.clone() };
+        assert!(storage.open(&unstored).await.is_err());
+
+        storage.delete(&stored).await.unwrap();
+        assert!(storage.open(&stored).await.is_err());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}